@@ -0,0 +1,107 @@
+//! # Decoding/encoding benchmark based on CQG's template
+//!
+//! Run with `cargo bench --bench cqg`.
+//!
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use fastlib::{Decoder, Encoder, MessageFactory, TextMessageFactory, TextMessageVisitor, Value};
+
+const DEFINITION: &str = include_str!("../tests/templates.xml");
+
+const ROUNDS: u32 = 20_000;
+
+/// Message factory that only counts callbacks, so the benchmark measures the decoder itself.
+struct CountingMessageFactory {
+    count: usize,
+}
+
+impl MessageFactory for CountingMessageFactory {
+    fn start_template(&mut self, _id: u32, _name: &str) { self.count += 1 }
+    fn stop_template(&mut self) {}
+    fn set_value(&mut self, _id: u32, _name: &str, value: Option<Value>) { self.count += black_box(value).is_some() as usize }
+    fn start_sequence(&mut self, _id: u32, _name: &str, _length: u32) {}
+    fn start_sequence_item(&mut self, _index: u32) {}
+    fn stop_sequence_item(&mut self) {}
+    fn stop_sequence(&mut self) {}
+    fn start_group(&mut self, _name: &str) {}
+    fn stop_group(&mut self) {}
+    fn start_template_ref(&mut self, _name: &str, _dynamic: bool) {}
+    fn stop_template_ref(&mut self) {}
+}
+
+fn messages() -> Vec<Vec<u8>> {
+    vec![
+        vec![0xc0, 0x84, 0x81, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2c, 0x58, 0x80],
+        vec![0x80, 0x82, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2d, 0x26, 0x90],
+        vec![0x80, 0x83, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2d, 0x74, 0xa0],
+        vec![0x7f, 0x57, 0xc0, 0x82, 0x07, 0xc4, 0x23, 0x7a, 0x17, 0x15, 0x7a, 0x4d, 0x59, 0x83, 0x07, 0xc6, 0x82, 0x80, 0x09, 0x53, 0x35, 0xe9, 0x00, 0x68, 0x73, 0x5e, 0x80, 0x4d, 0x42, 0x54, 0x53, 0x31, 0xb3, 0x4d, 0x42, 0x54, 0x53, 0x31, 0x33, 0x43, 0x31, 0x30, 0xb0, 0x4d, 0x69, 0x63, 0x72, 0x6f, 0x20, 0x42, 0x69, 0x74, 0x63, 0x6f, 0x69, 0x6e, 0x20, 0x52, 0x65, 0x76, 0x65, 0x72, 0x73, 0x65, 0x20, 0x43, 0x61, 0x6c, 0x20, 0x53, 0x70, 0x72, 0x65, 0x61, 0xe4, 0x4d, 0x42, 0x54, 0x53, 0x31, 0x33, 0x58, 0x32, 0xb4, 0x1c, 0x79, 0x58, 0xfe, 0x46, 0x58, 0x58, 0x58, 0x58, 0xd8, 0x47, 0x4c, 0x42, 0xd8, 0x46, 0x2e, 0x55, 0x53, 0x2e, 0x4d, 0x42, 0x54, 0x57, 0x31, 0x33, 0x58, 0x32, 0xb4, 0x81, 0x80, 0x55, 0x53, 0xc4, 0x83, 0x80, 0x80, 0xc0, 0x43, 0x51, 0x47, 0xc9, 0x81, 0x82, 0xc0, 0x07, 0xeb, 0x31, 0x30, 0xb0, 0x0c, 0x2d, 0xac, 0x81, 0x81, 0xff, 0x81, 0x81, 0x81, 0xb4, 0x84, 0x81, 0x32, 0x33, 0x39, 0x2e, 0x32, 0x34, 0x36, 0x2e, 0x35, 0x2e, 0xb4, 0x55, 0xfc, 0x82, 0x32, 0x33, 0x39, 0x2e, 0x32, 0x34, 0x36, 0x2e, 0x36, 0x2e, 0xb4, 0x5d, 0xe4, 0x83, 0x31, 0x30, 0x2e, 0x31, 0x2e, 0x30, 0x2e, 0x31, 0x32, 0xb0, 0x4e, 0x90, 0x83, 0x31, 0x30, 0x2e, 0x31, 0x2e, 0x30, 0x2e, 0x31, 0x32, 0xb0, 0x4e, 0x91, 0x86, 0x09, 0x53, 0x31, 0x93, 0x23, 0x7a, 0x14, 0x7a, 0x6e, 0x50, 0x46, 0x80, 0x23, 0x7a, 0x14, 0x7a, 0x6a, 0x49, 0x5f, 0xe0, 0x23, 0x7a, 0x14, 0x7e, 0x46, 0x59, 0x2d, 0x80, 0x23, 0x7a, 0x14, 0x7e, 0x46, 0x59, 0x2d, 0x80, 0x00, 0xc8, 0x02, 0x0c, 0x1c, 0x23, 0x20, 0x80, 0x02, 0x0c, 0x1c, 0x23, 0x20, 0x80, 0x02, 0x0c, 0x1c, 0x23, 0x20, 0x80, 0x02, 0x0c, 0x1c, 0x23, 0x20, 0x80, 0x81, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x81, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x81, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x81, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x80, 0x80, 0x80],
+        vec![0x18, 0xc0, 0x07, 0xc5, 0x23, 0x7a, 0x17, 0x15, 0x7a, 0x4d, 0x59, 0x83, 0x82, 0x80, 0x7f, 0x98, 0x7b, 0x1d, 0x53, 0x80, 0x4d, 0x42, 0x54, 0x53, 0xb1, 0x4d, 0x42, 0x54, 0x53, 0x31, 0x43, 0x31, 0x30, 0xb0, 0x4d, 0x42, 0x54, 0x53, 0x31, 0x56, 0x32, 0xb4, 0x1c, 0x79, 0x58, 0xc1, 0x46, 0x2e, 0x55, 0x53, 0x2e, 0x4d, 0x42, 0x54, 0x57, 0x31, 0x56, 0x32, 0xb4, 0x81, 0x80, 0x83, 0x80, 0x80, 0xc0, 0x43, 0x51, 0x47, 0xc9, 0x81, 0x82, 0x80, 0x80, 0xff, 0x84, 0x81, 0x32, 0x33, 0x39, 0x2e, 0x32, 0x34, 0x36, 0x2e, 0x35, 0x2e, 0xb4, 0x55, 0xfc, 0x82, 0x32, 0x33, 0x39, 0x2e, 0x32, 0x34, 0x36, 0x2e, 0x36, 0x2e, 0xb4, 0x5d, 0xe4, 0x83, 0x31, 0x30, 0x2e, 0x31, 0x2e, 0x30, 0x2e, 0x31, 0x32, 0xb0, 0x4e, 0x90, 0x83, 0x31, 0x30, 0x2e, 0x31, 0x2e, 0x30, 0x2e, 0x31, 0x32, 0xb0, 0x4e, 0x91, 0x86, 0x7f, 0xb4, 0x7d, 0x64, 0x70, 0x30, 0x10, 0x80, 0x7d, 0x64, 0x70, 0x30, 0x10, 0x80, 0x7d, 0x64, 0x70, 0x30, 0x10, 0x80, 0x7d, 0x64, 0x70, 0x30, 0x10, 0x80, 0x00, 0xc8, 0x02, 0x0c, 0x1c, 0x23, 0x20, 0x80, 0x02, 0x0c, 0x1c, 0x23, 0x20, 0x80, 0x02, 0x0c, 0x1c, 0x23, 0x20, 0x80, 0x02, 0x0c, 0x1c, 0x23, 0x20, 0x80, 0x81, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x81, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x81, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x81, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x80, 0x80, 0x80],
+        vec![0x00, 0xc0, 0x07, 0xc6, 0x23, 0x7a, 0x17, 0x15, 0x7a, 0x4d, 0x59, 0x83, 0x82, 0x80, 0x00, 0xe8, 0x04, 0x62, 0x2d, 0x80, 0x4d, 0x42, 0x54, 0x53, 0x31, 0x58, 0x32, 0xb4, 0x1c, 0x79, 0x58, 0xc0, 0x46, 0x2e, 0x55, 0x53, 0x2e, 0x4d, 0x42, 0x54, 0x57, 0x31, 0x58, 0x32, 0xb4, 0x81, 0x80, 0x83, 0x80, 0x80, 0xc0, 0x43, 0x51, 0x47, 0xc9, 0x81, 0x82, 0x80, 0x80, 0x82, 0x84, 0x81, 0x32, 0x33, 0x39, 0x2e, 0x32, 0x34, 0x36, 0x2e, 0x35, 0x2e, 0xb4, 0x55, 0xfc, 0x82, 0x32, 0x33, 0x39, 0x2e, 0x32, 0x34, 0x36, 0x2e, 0x36, 0x2e, 0xb4, 0x5d, 0xe4, 0x83, 0x31, 0x30, 0x2e, 0x31, 0x2e, 0x30, 0x2e, 0x31, 0x32, 0xb0, 0x4e, 0x90, 0x83, 0x31, 0x30, 0x2e, 0x31, 0x2e, 0x30, 0x2e, 0x31, 0x32, 0xb0, 0x4e, 0x91, 0x86, 0x7f, 0xb4, 0x7d, 0x64, 0x70, 0x30, 0x10, 0x80, 0x7d, 0x64, 0x70, 0x30, 0x10, 0x80, 0x7d, 0x64, 0x70, 0x30, 0x10, 0x80, 0x7d, 0x64, 0x70, 0x30, 0x10, 0x80, 0x00, 0xc8, 0x02, 0x0c, 0x1c, 0x23, 0x20, 0x80, 0x02, 0x0c, 0x1c, 0x23, 0x20, 0x80, 0x02, 0x0c, 0x1c, 0x23, 0x20, 0x80, 0x02, 0x0c, 0x1c, 0x23, 0x20, 0x80, 0x81, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x81, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x81, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x81, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x03, 0x5c, 0x6b, 0x14, 0x80, 0x80, 0x80, 0x80],
+    ]
+}
+
+fn report(name: &str, elapsed: Duration, messages: usize) {
+    let per_msg = elapsed.as_nanos() as f64 / (ROUNDS as f64 * messages as f64);
+    println!("{name:<24} {per_msg:>10.1} ns/msg");
+}
+
+fn bench_decode(raw: &[Vec<u8>]) {
+    let mut d = Decoder::new_from_xml(DEFINITION).unwrap();
+    let mut msg = CountingMessageFactory { count: 0 };
+    let raw: Vec<bytes::Bytes> = raw.iter().map(|m| bytes::Bytes::from(m.clone())).collect();
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        d.reset();
+        for m in &raw {
+            let mut buf = m.clone();
+            d.decode_bytes(&mut buf, &mut msg).unwrap();
+        }
+    }
+    report("decode_bytes", start.elapsed(), raw.len());
+    black_box(msg.count);
+}
+
+fn bench_decode_slice(raw: &[Vec<u8>]) {
+    let mut d = Decoder::new_from_xml(DEFINITION).unwrap();
+    let mut msg = CountingMessageFactory { count: 0 };
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        d.reset();
+        for m in raw {
+            d.decode_slice(m, &mut msg).unwrap();
+        }
+    }
+    report("decode_slice", start.elapsed(), raw.len());
+    black_box(msg.count);
+}
+
+fn bench_encode(raw: &[Vec<u8>]) {
+    // Get text representation of the messages first.
+    let mut d = Decoder::new_from_xml(DEFINITION).unwrap();
+    let texts: Vec<String> = raw.iter().map(|m| {
+        let mut msg = TextMessageFactory::new();
+        d.decode_vec(m.clone(), &mut msg).unwrap();
+        msg.text
+    }).collect();
+
+    let mut e = Encoder::new_from_xml(DEFINITION).unwrap();
+    let mut elapsed = Duration::ZERO;
+    for _ in 0..ROUNDS {
+        e.reset();
+        for t in &texts {
+            let mut msg = TextMessageVisitor::from_text(t).unwrap();
+            let start = Instant::now();
+            black_box(e.encode_vec(&mut msg).unwrap());
+            elapsed += start.elapsed();
+        }
+    }
+    report("encode_vec", elapsed, raw.len());
+}
+
+fn main() {
+    let raw = messages();
+    bench_decode(&raw);
+    bench_decode_slice(&raw);
+    bench_encode(&raw);
+}
@@ -0,0 +1,82 @@
+use crate::base::instruction::Instruction;
+use crate::base::types::{Dictionary, Operator, Presence};
+use crate::base::value::{Value, ValueType};
+
+/// Describes a field instruction of a template: its type, presence, field operator and dictionary entry.
+///
+/// It is passed to [`MessageFactory::set_field`][crate::MessageFactory::set_field] and
+/// [`MessageVisitor::get_field`][crate::MessageVisitor::get_field] along with the field value.
+#[derive(Debug, Clone, Copy)]
+pub struct FieldInfo<'a> {
+    pub(crate) instruction: &'a Instruction,
+}
+
+impl<'a> FieldInfo<'a> {
+    pub(crate) fn new(instruction: &'a Instruction) -> Self {
+        Self { instruction }
+    }
+
+    /// The field instruction id.
+    pub fn id(&self) -> u32 {
+        self.instruction.id
+    }
+
+    /// The field name; empty for decimal subcomponents.
+    pub fn name(&self) -> &'a str {
+        &self.instruction.name
+    }
+
+    /// The basic encoding of the field.
+    pub fn value_type(&self) -> &'a ValueType {
+        &self.instruction.value_type
+    }
+
+    /// Presence of the field.
+    pub fn presence(&self) -> Presence {
+        self.instruction.presence
+    }
+
+    /// `true` if the field has optional presence.
+    pub fn is_optional(&self) -> bool {
+        self.instruction.is_optional()
+    }
+
+    /// The field operator; decimal fields with individual operators on the subcomponents have [`Operator::None`].
+    pub fn operator(&self) -> Operator {
+        self.instruction.operator
+    }
+
+    /// Initial value specified by the `value` attribute of the field operator.
+    pub fn initial_value(&self) -> Option<&'a Value> {
+        self.instruction.initial_value.as_ref()
+    }
+
+    /// Name of the dictionary the field operator stores its previous value in:
+    /// "global", "template", "type" or a user defined name.
+    pub fn dictionary(&self) -> &'a str {
+        match &self.instruction.dictionary {
+            Dictionary::Inherit | Dictionary::Global => "global",
+            Dictionary::Template => "template",
+            Dictionary::Type => "type",
+            Dictionary::UserDefined(name) => name,
+        }
+    }
+
+    /// The dictionary key of the field; it is the field name unless the `key` attribute is specified.
+    pub fn key(&self) -> &'a str {
+        &self.instruction.key
+    }
+
+    /// `true` if the field is the exponent or the mantissa of a decimal field.
+    pub fn is_decimal_component(&self) -> bool {
+        matches!(self.instruction.value_type, ValueType::Exponent | ValueType::Mantissa)
+    }
+
+    /// The exponent and the mantissa subcomponents of a decimal field, `None` for other field types.
+    pub fn decimal_components(&self) -> Option<(FieldInfo<'a>, FieldInfo<'a>)> {
+        match (&self.instruction.value_type, self.instruction.instructions.as_slice()) {
+            (ValueType::Decimal, [exponent, mantissa]) => Some((FieldInfo::new(exponent), FieldInfo::new(mantissa))),
+            _ => None,
+        }
+    }
+}
@@ -1,11 +1,12 @@
-use std::cell::Cell;
-use std::rc::Rc;
+use std::borrow::Cow;
+use std::sync::Arc;
 
 use roxmltree::Node;
 
-use crate::{Decimal, Error, Result};
+use crate::{Decimal, Error, FastErrorCode, Result};
 use crate::base::types::{Dictionary, Operator, Presence, TypeRef};
-use crate::base::value::{Value, ValueType};
+use crate::base::value::{Value, ValueRef, ValueType};
+use crate::common::context::DictionarySlot;
 use crate::decoder::decoder::DecoderContext;
 use crate::encoder::encoder::EncoderContext;
 use crate::encoder::writer::Writer;
@@ -55,11 +56,17 @@ pub(crate) struct Instruction {
     pub(crate) type_ref: TypeRef,
 
     // Internal key name for lookup in storage
-    pub(crate) key: Rc<str>,
+    pub(crate) key: Arc<str>,
+
+    // Resolved dictionary entry for the `key` within the effective dictionary.
+    pub(crate) slot: DictionarySlot,
+
+    // Index of the application type from `type_ref`; 0 is the special type `any`.
+    pub(crate) type_index: usize,
 
     // For ::Sequence it shows if the instruction needs a pmap.
     // For ::Decimal it shows if any of its subcomponent needs a pmap.
-    pub(crate) has_pmap: Cell<bool>,
+    pub(crate) has_pmap: bool,
 }
 
 impl Instruction {
@@ -86,8 +93,10 @@ impl Instruction {
             instructions: Vec::new(),
             dictionary: Dictionary::Inherit,
             type_ref: TypeRef::Any,
-            key: Rc::from(ky),
-            has_pmap: Cell::new(false),
+            key: Arc::from(ky),
+            slot: DictionarySlot::Static(0),
+            type_index: 0,
+            has_pmap: false,
         }
     }
 
@@ -102,7 +111,7 @@ impl Instruction {
         let unicode = match node.attribute("charset") {
             Some("unicode") => true,
             Some(charset) => {
-                return Err(Error::Static(format!("unknown charset: {charset}")));
+                return Err(Error::fast(FastErrorCode::S1, format!("unknown charset: {charset}"))); // [ERR S1]
             }
             _ => false
         };
@@ -111,7 +120,7 @@ impl Instruction {
             ValueType::Mantissa | ValueType::Exponent | ValueType::Sequence | ValueType::Group | ValueType::TemplateReference => {}
             _ => {
                 if id == 0 {
-                    return Err(Error::Runtime("instruction must have non-zero 'id' attribute".to_string()));
+                    return Err(Error::fast(FastErrorCode::S1, "instruction must have non-zero 'id' attribute")); // [ERR S1]
                 }
             }
         }
@@ -119,7 +128,7 @@ impl Instruction {
             ValueType::Mantissa | ValueType::Exponent | ValueType::Length | ValueType::TemplateReference => {}
             _ => {
                 if name.is_empty() {
-                    return Err(Error::Runtime("instruction must have 'name' attribute".to_string()));
+                    return Err(Error::fast(FastErrorCode::S1, "instruction must have 'name' attribute")); // [ERR S1]
                 }
             }
         }
@@ -132,7 +141,7 @@ impl Instruction {
             instruction.dictionary = Dictionary::from_str(d);
         }
         if let Some(k) = node.attribute("key") {
-            instruction.key = Rc::from(k);
+            instruction.key = Arc::from(k);
         }
         if let Some(k) = node.attribute("typeRef") {
             instruction.type_ref = TypeRef::from_str(k);
@@ -146,6 +155,10 @@ impl Instruction {
                     if !n.is_element() {
                         continue;
                     }
+                    if n.tag_name().name() == "typeRef" {
+                        instruction.type_ref = TypeRef::from_node(n)?;
+                        continue;
+                    }
                     let i = Instruction::from_node(n)?;
                     instruction.add_instruction(i);
                 }
@@ -157,6 +170,10 @@ impl Instruction {
                     if !n.is_element() {
                         continue;
                     }
+                    if n.tag_name().name() == "typeRef" {
+                        instruction.type_ref = TypeRef::from_node(n)?;
+                        continue;
+                    }
                     let mut instr = Instruction::from_node(n)?;
                     if i == 0 {
                         match instr.value_type {
@@ -240,7 +257,7 @@ impl Instruction {
                             _ => {}
                         }
                         if let Some(v) = initial_value {
-                            let d = Decimal::from_string(&v)?; // [ERR S3]
+                            let d = Decimal::from_string(&v).map_err(|e| e.with_code(FastErrorCode::S3))?; // [ERR S3]
                             ex.initial_value = Some(Value::Int32(d.exponent));
                             mn.initial_value = Some(Value::Int64(d.mantissa));
                         }
@@ -252,7 +269,7 @@ impl Instruction {
                         mn = m;
                     }
                     _ => {
-                        return Err(Error::Static("invalid decimal elements".to_string()));
+                        return Err(Error::fast(FastErrorCode::S1, "invalid decimal elements")); // [ERR S1]
                     }
                 }
                 // Set proper presence flag.
@@ -260,10 +277,10 @@ impl Instruction {
                 mn.presence = Presence::Mandatory;
                 // Set proper storage keys if it is not set explicitly with 'key' attribute.
                 if ex.key.is_empty() {
-                    ex.key = Rc::from(format!("{}:exponent", &instruction.key));
+                    ex.key = Arc::from(format!("{}:exponent", &instruction.key));
                 }
                 if mn.key.is_empty() {
-                    mn.key = Rc::from(format!("{}:mantissa", &instruction.key));
+                    mn.key = Arc::from(format!("{}:mantissa", &instruction.key));
                 }
                 instruction.operator = op;
                 // Put subcomponents into instruction.
@@ -278,7 +295,7 @@ impl Instruction {
                     }
                     instruction.operator = Operator::new_from_tag(operator.tag_name().name())?;
                     if let Some(s) = operator.attribute("value") {
-                        instruction.set_initial_value(s)?; // [ERR S3]
+                        instruction.set_initial_value(s).map_err(|e| e.with_code(FastErrorCode::S3))?; // [ERR S3]
                     }
                     break;
                 }
@@ -299,14 +316,14 @@ impl Instruction {
                 // The constant operator is applicable to all field types.
                 // It is a static error [ERR S4] if the instruction context has no initial value.
                 if self.initial_value.is_none() {
-                    return Err(Error::Static("constant operator has no initial value".to_string())); // [ERR S4]
+                    return Err(Error::fast(FastErrorCode::S4, "constant operator has no initial value")); // [ERR S4]
                 }
             }
             Operator::Default => {
                 // The default operator is applicable to all field types.
                 // Unless the field has optional presence, it is a static error [ERR S5] if the instruction context has no initial value.
                 if !self.is_optional() && self.initial_value.is_none() {
-                    return Err(Error::Static("default operator has no initial value".to_string())); // [ERR S5]
+                    return Err(Error::fast(FastErrorCode::S5, "default operator has no initial value")); // [ERR S5]
                 }
             }
             Operator::Increment => {
@@ -315,7 +332,7 @@ impl Instruction {
                     ValueType::UInt32 | ValueType::Int32 | ValueType::UInt64 | ValueType::Int64 |
                     ValueType::Length | ValueType::Exponent | ValueType::Mantissa => {}
                     _ => {
-                        return Err(Error::Static(format!("increment operator is not applicable to {} field type", self.value_type.type_str()))); // [ERR S2]
+                        return Err(Error::fast(FastErrorCode::S2, format!("increment operator is not applicable to {} field type", self.value_type.type_str()))); // [ERR S2]
                     }
                 }
             }
@@ -324,7 +341,7 @@ impl Instruction {
                 match self.value_type {
                     ValueType::ASCIIString | ValueType::UnicodeString | ValueType::Bytes => {}
                     _ => {
-                        return Err(Error::Static(format!("tail operator is not applicable to {} field type", self.value_type.type_str()))); // [ERR S2]
+                        return Err(Error::fast(FastErrorCode::S2, format!("tail operator is not applicable to {} field type", self.value_type.type_str()))); // [ERR S2]
                     }
                 }
             }
@@ -372,7 +389,7 @@ impl Instruction {
         }
     }
 
-    pub(crate) fn extract(&self, s: &mut DecoderContext) -> Result<Option<Value>> {
+    pub(crate) fn extract<'d>(&self, s: &mut DecoderContext<'_, 'd>) -> Result<Option<ValueRef<'d>>> {
         match self.operator {
             Operator::None => {
                 Ok(self.read(s)?)
@@ -384,7 +401,7 @@ impl Instruction {
             Operator::Constant => {
                 let v = if !self.is_optional() || s.pmap_next_bit_set() {
                     match &self.initial_value {
-                        Some(v) => Some(v.clone()),
+                        Some(v) => Some(ValueRef::from(v.clone())),
                         None => unreachable!(),
                     }
                 } else {
@@ -398,12 +415,12 @@ impl Instruction {
             // if the instruction context has no initial value. If the field has optional presence and no initial value,
             // the field is considered absent when there is no value in the stream.
             Operator::Default => {
-                let v: Option<Value>;
+                let v: Option<ValueRef>;
                 if s.pmap_next_bit_set() {
                     v = self.read(s)?;
                 } else {
                     v = match &self.initial_value {
-                        Some(v) => Some(v.clone()),
+                        Some(v) => Some(ValueRef::from(v.clone())),
                         None => {
                             if self.is_optional() {
                                 None
@@ -418,25 +435,25 @@ impl Instruction {
 
             // The copy operator specifies that the value of a field is optionally present in the stream.
             Operator::Copy => {
-                let v: Option<Value>;
+                let v: Option<ValueRef>;
                 if s.pmap_next_bit_set() {
                     // If the value is present in the stream it becomes the new previous value.
                     v = self.read(s)?;
-                    s.ctx_set(&self, &v);
+                    s.ctx_set_ref(&self, &v);
                 } else {
                     // When the value is not present in the stream there are three cases depending
                     // on the state of the previous value:
                     v = match s.ctx_get(&self)? {
                         Some(v) => match v {
                             // Assigned: The value of the field is the previous value.
-                            Some(prev) => Some(prev.clone()),
+                            Some(prev) => Some(ValueRef::from(prev)),
                             // Empty: If the field is optional the value is considered absent.
                             // It is a dynamic error [ERR D6] if the field is mandatory.
                             None => {
                                 if self.is_optional() {
                                     None
                                 } else {
-                                    return Err(Error::Runtime("copy operator has no previous value".to_string())); // [ERR D6]
+                                    return Err(Error::fast(FastErrorCode::D6, "copy operator has no previous value")); // [ERR D6]
                                 }
                             }
                         }
@@ -451,12 +468,12 @@ impl Instruction {
                                     if self.is_optional() {
                                         None
                                     } else {
-                                        return Err(Error::Runtime("copy operator has no initial value".to_string())); // [ERR D5]
+                                        return Err(Error::fast(FastErrorCode::D5, "copy operator has no initial value")); // [ERR D5]
                                     }
                                 }
                             };
                             s.ctx_set(&self, &v);
-                            v
+                            v.map(ValueRef::from)
                         }
                     }
                 }
@@ -465,11 +482,11 @@ impl Instruction {
 
             // The increment operator specifies that the value of a field is optionally present in the stream.
             Operator::Increment => {
-                let v: Option<Value>;
+                let v: Option<ValueRef>;
                 if s.pmap_next_bit_set() {
                     //If the value is present in the stream it becomes the new previous value.
                     v = self.read(s)?;
-                    s.ctx_set(&self, &v);
+                    s.ctx_set_ref(&self, &v);
                 } else {
                     // When the value is not present in the stream there are three cases depending on the state of the previous value:
                     v = match s.ctx_get(&self)? {
@@ -479,7 +496,7 @@ impl Instruction {
                             Some(prev) => {
                                 let v = Some(prev.apply_increment()?);
                                 s.ctx_set(&self, &v);
-                                v
+                                v.map(ValueRef::from)
                             }
                             // Empty: the value of the field is empty.
                             // If the field is optional, the value is considered absent.
@@ -488,7 +505,7 @@ impl Instruction {
                                 if self.is_optional() {
                                     None
                                 } else {
-                                    return Err(Error::Runtime("increment operator has no previous value".to_string())); // [ERR D6]
+                                    return Err(Error::fast(FastErrorCode::D6, "increment operator has no previous value")); // [ERR D6]
                                 }
                             }
                         }
@@ -503,12 +520,12 @@ impl Instruction {
                                     if self.is_optional() {
                                         None
                                     } else {
-                                        return Err(Error::Runtime("increment operator has no initial value".to_string())); // [ERR D5]
+                                        return Err(Error::fast(FastErrorCode::D5, "increment operator has no initial value")); // [ERR D5]
                                     }
                                 }
                             };
                             s.ctx_set(&self, &v);
-                            v
+                            v.map(ValueRef::from)
                         }
                     };
                 }
@@ -533,7 +550,7 @@ impl Instruction {
                         Some(prev) => prev.clone(),
                         // Empty: It is a dynamic error [ERR D6] if the previous value is empty.
                         None => {
-                            return Err(Error::Runtime("delta operator has no previous value".to_string())); // [ERR D6]
+                            return Err(Error::fast(FastErrorCode::D6, "delta operator has no previous value")); // [ERR D6]
                         }
                     }
                     // Undefined: The base value is the initial value if present in the instruction context.
@@ -547,7 +564,7 @@ impl Instruction {
                 };
                 let value = Some(base.apply_delta(delta, aux)?);
                 s.ctx_set(&self, &value);
-                Ok(value)
+                Ok(value.map(ValueRef::from))
             }
 
             // The tail operator specifies that a tail value is optionally present in the stream.
@@ -561,7 +578,7 @@ impl Instruction {
                             return if self.is_optional() {
                                 Ok(None)
                             } else {
-                                Err(Error::Runtime("tail operator has no previous value".to_string())) // [ERR D7]
+                                Err(Error::fast(FastErrorCode::D7, "tail operator has no previous value")) // [ERR D7]
                             }
                         }
                         Some(t) => t,
@@ -606,7 +623,7 @@ impl Instruction {
                                 if self.is_optional() {
                                     None
                                 } else {
-                                    return Err(Error::Runtime("tail operator has no previous value".to_string())); // [ERR D7]
+                                    return Err(Error::fast(FastErrorCode::D7, "tail operator has no previous value")); // [ERR D7]
                                 }
                             }
                         }
@@ -621,7 +638,7 @@ impl Instruction {
                                     if self.is_optional() {
                                         None
                                     } else {
-                                        return Err(Error::Runtime("tail operator has no initial value".to_string())); // [ERR D6]
+                                        return Err(Error::fast(FastErrorCode::D6, "tail operator has no initial value")); // [ERR D6]
                                     }
                                 }
                             };
@@ -630,53 +647,102 @@ impl Instruction {
                         }
                     };
                 }
-                Ok(value)
+                Ok(value.map(ValueRef::from))
+            }
+        }
+    }
+
+    // Consume the field from the stream without building its value where possible. The fields which operators
+    // update the dictionary are extracted as usual.
+    pub(crate) fn skip(&self, s: &mut DecoderContext) -> Result<()> {
+        match self.operator {
+            Operator::None => self.skip_value(s),
+            Operator::Constant => {
+                if self.is_optional() {
+                    s.pmap_next_bit_set();
+                }
+                Ok(())
+            }
+            Operator::Default => {
+                if s.pmap_next_bit_set() {
+                    self.skip_value(s)?;
+                }
+                Ok(())
+            }
+            _ => {
+                self.extract(s)?;
+                Ok(())
+            }
+        }
+    }
+
+    fn skip_value(&self, s: &mut DecoderContext) -> Result<()> {
+        match self.value_type {
+            // Strict mode has to check the string encoding, so it is read in full.
+            ValueType::ASCIIString if !s.strict => {
+                while s.rdr.read_u8()? & 0x80 == 0 {}
+                Ok(())
+            }
+            ValueType::UnicodeString | ValueType::Bytes => {
+                let length = if self.is_nullable() {
+                    s.rdr.read_uint_nullable()?
+                } else {
+                    Some(s.rdr.read_uint()?)
+                };
+                match length {
+                    Some(length) => s.rdr.skip_slice(length),
+                    None => Ok(()),
+                }
+            }
+            _ => {
+                self.read(s)?;
+                Ok(())
             }
         }
     }
 
-    fn read(&self, s: &mut DecoderContext) -> Result<Option<Value>> {
+    fn read<'d>(&self, s: &mut DecoderContext<'_, 'd>) -> Result<Option<ValueRef<'d>>> {
         match self.value_type {
             ValueType::UInt32 | ValueType::Length => {
                 match self.read_uint32(s)? {
                     None => Ok(None),
-                    Some(v) => Ok(Some(Value::UInt32(v))),
+                    Some(v) => Ok(Some(ValueRef::UInt32(v))),
                 }
             }
             ValueType::UInt64 => {
                 match self.read_uint64(s)? {
                     None => Ok(None),
-                    Some(v) => Ok(Some(Value::UInt64(v))),
+                    Some(v) => Ok(Some(ValueRef::UInt64(v))),
                 }
             }
             ValueType::Int32 => {
                 match self.read_int32(s)? {
                     None => Ok(None),
-                    Some(v) => Ok(Some(Value::Int32(v))),
+                    Some(v) => Ok(Some(ValueRef::Int32(v))),
                 }
             }
             ValueType::Int64 | ValueType::Mantissa => {
                 match self.read_int64(s)? {
                     None => Ok(None),
-                    Some(v) => Ok(Some(Value::Int64(v))),
+                    Some(v) => Ok(Some(ValueRef::Int64(v))),
                 }
             }
             ValueType::ASCIIString => {
                 match self.read_ascii_string(s)? {
                     None => Ok(None),
-                    Some(v) => Ok(Some(Value::ASCIIString(v))),
+                    Some(v) => Ok(Some(ValueRef::ASCIIString(Cow::Owned(v)))),
                 }
             }
             ValueType::UnicodeString => {
                 match self.read_unicode_string(s)? {
                     None => Ok(None),
-                    Some(v) => Ok(Some(Value::UnicodeString(v))),
+                    Some(v) => Ok(Some(ValueRef::UnicodeString(v))),
                 }
             }
             ValueType::Bytes => {
                 match self.read_bytes(s)? {
                     None => Ok(None),
-                    Some(v) => Ok(Some(Value::Bytes(v))),
+                    Some(v) => Ok(Some(ValueRef::Bytes(v))),
                 }
             }
             // A scaled number is represented as a Signed Integer exponent followed by a Signed Integer mantissa.
@@ -687,12 +753,12 @@ impl Instruction {
                         return Ok(None)
                     }
                 };
-                Ok(Some(Value::Decimal(Decimal::new(exponent, mantissa))))
+                Ok(Some(ValueRef::Decimal(Decimal::new(exponent, mantissa))))
             }
             ValueType::Exponent => {
                 match self.read_exponent(s)? {
                     None => Ok(None),
-                    Some(v) => Ok(Some(Value::Int32(v))),
+                    Some(v) => Ok(Some(ValueRef::Int32(v))),
                 }
             }
             _ => unreachable!()
@@ -705,7 +771,7 @@ impl Instruction {
                 None => Ok(None),
                 Some(v) => {
                     if v > MAX_UINT32 {
-                        return Err(Error::Runtime(format!("uInt32 value is out of range: {}", v)));
+                        return Err(Error::fast(FastErrorCode::D2, format!("uInt32 value is out of range: {}", v))); // [ERR D2]
                     }
                     Ok(Some(v as u32))
                 }
@@ -713,7 +779,7 @@ impl Instruction {
         } else {
             let v = s.rdr.read_uint()?;
             if v > MAX_UINT32 {
-                return Err(Error::Runtime(format!("uInt32 value is out of range: {}", v)));
+                return Err(Error::fast(FastErrorCode::D2, format!("uInt32 value is out of range: {}", v))); // [ERR D2]
             }
             Ok(Some(v as u32))
         }
@@ -733,7 +799,7 @@ impl Instruction {
                 None => Ok(None),
                 Some(v) => {
                     if v < MIN_INT32 || v > MAX_INT32 {
-                        return Err(Error::Runtime(format!("uInt32 value is out of range: {}", v))); // [ERR D2]
+                        return Err(Error::fast(FastErrorCode::D2, format!("int32 value is out of range: {}", v))); // [ERR D2]
                     }
                     Ok(Some(v as i32))
                 }
@@ -741,7 +807,7 @@ impl Instruction {
         } else {
             let v = s.rdr.read_int()?;
             if v < MIN_INT32 || v > MAX_INT32 {
-                return Err(Error::Runtime(format!("uInt32 value is out of range: {}", v))); // [ERR D2]
+                return Err(Error::fast(FastErrorCode::D2, format!("int32 value is out of range: {}", v))); // [ERR D2]
             }
             Ok(Some(v as i32))
         }
@@ -763,19 +829,19 @@ impl Instruction {
         }
     }
 
-    fn read_unicode_string(&self, s: &mut DecoderContext) -> Result<Option<String>> {
+    fn read_unicode_string<'d>(&self, s: &mut DecoderContext<'_, 'd>) -> Result<Option<Cow<'d, str>>> {
         if self.is_nullable() {
-            Ok(s.rdr.read_unicode_string_nullable()?)
+            Ok(s.rdr.read_unicode_string_ref_nullable()?)
         } else {
-            Ok(Some(s.rdr.read_unicode_string()?))
+            Ok(Some(s.rdr.read_unicode_string_ref()?))
         }
     }
 
-    fn read_bytes(&self, s: &mut DecoderContext) -> Result<Option<Vec<u8>>> {
+    fn read_bytes<'d>(&self, s: &mut DecoderContext<'_, 'd>) -> Result<Option<Cow<'d, [u8]>>> {
         if self.is_nullable() {
-            Ok(s.rdr.read_bytes_nullable()?)
+            Ok(s.rdr.read_bytes_ref_nullable()?)
         } else {
-            Ok(Some(s.rdr.read_bytes()?))
+            Ok(Some(s.rdr.read_bytes_ref()?))
         }
     }
 
@@ -802,7 +868,7 @@ impl Instruction {
                             }
                             ValueType::UnicodeString | ValueType::Bytes => {
                                 let diff = self.read_bytes(s)?.unwrap();
-                                Ok(Some((Value::Bytes(diff), sub)))
+                                Ok(Some((Value::Bytes(diff.into_owned()), sub)))
                             }
                             _ => unreachable!()
                         }
@@ -819,7 +885,7 @@ impl Instruction {
                 Ok(self.read_ascii_string(s)?.map(|s| Value::ASCIIString(s)))
             }
             ValueType::UnicodeString | ValueType::Bytes => {
-                Ok(self.read_bytes(s)?.map(|b| Value::Bytes(b)))
+                Ok(self.read_bytes(s)?.map(|b| Value::Bytes(b.into_owned())))
             }
             _ => unreachable!()
         }
@@ -838,7 +904,7 @@ impl Instruction {
             .ok_or_else(|| Error::Runtime("mantissa field not found".to_string()))?
             .extract(s)?;
 
-        if let (Some(Value::Int32(e)), Some(Value::Int64(m))) = (exponent, mantissa) {
+        if let (Some(ValueRef::Int32(e)), Some(ValueRef::Int64(m))) = (exponent, mantissa) {
             Ok(Some((e, m)))
         } else {
             return Err(Error::Runtime("exponent or mantissa not found".to_string()));
@@ -851,7 +917,7 @@ impl Instruction {
             Some(e) => e,
         };
         if e > MAX_EXPONENT || e < MIN_EXPONENT {
-            return Err(Error::Dynamic(format!("exponent value is out of range: {}", e))); // [ERR R1]
+            return Err(Error::fast(FastErrorCode::R1, format!("exponent value is out of range: {}", e))); // [ERR R1]
         }
         Ok(Some(e))
     }
@@ -931,7 +997,7 @@ impl Instruction {
                     Some(v) => match v {
                         Some(v) => v,
                         None => {
-                            return Err(Error::Runtime("delta operator has empty previous value".to_string())); // [ERR D6]
+                            return Err(Error::fast(FastErrorCode::D6, "delta operator has empty previous value")); // [ERR D6]
                         }
                     },
                     None => match &self.initial_value {
@@ -1198,7 +1264,7 @@ impl Instruction {
     fn write_exponent(&self, buf: &mut dyn Writer, value: Option<i32>) -> Result<()> {
         if let Some(e) = value {
             if e > MAX_EXPONENT || e < MIN_EXPONENT {
-                return Err(Error::Dynamic(format!("exponent value is out of range: {}", e))); // [ERR R1]
+                return Err(Error::fast(FastErrorCode::R1, format!("exponent value is out of range: {}", e))); // [ERR R1]
             }
         }
         self.write_int(buf, value)
@@ -1,5 +1,8 @@
 use crate::{Result, ValueType};
 use crate::Value;
+use crate::base::field::FieldInfo;
+use crate::base::value::ValueRef;
+use crate::decoder::packet::Preamble;
 
 /// Defines the interface for message factories.
 ///
@@ -20,6 +23,14 @@ pub trait MessageFactory {
     /// * `value` is the field value which is optional.
     fn set_value(&mut self, id: u32, name: &str, value: Option<Value>);
 
+    /// Called by the decoder when a field element is processed; calls [`MessageFactory::set_value`] by default.
+    /// Implement it to get the field metadata:
+    /// * `field` describes the field instruction;
+    /// * `value` is the field value which is optional.
+    fn set_field(&mut self, field: FieldInfo, value: Option<Value>) {
+        self.set_value(field.id(), field.name(), value)
+    }
+
     /// Called when a \<sequence> element processing is started.
     /// * `id` is the sequence instruction id; can be `0` if id is not specified;
     /// * `name` is the sequence name;
@@ -48,8 +59,352 @@ pub trait MessageFactory {
     /// * `dynamic` is `true` if the template reference is dynamic.
     fn start_template_ref(&mut self, name: &str, dynamic: bool);
 
+    /// Called by the decoder when a template reference processing is started;
+    /// calls [`MessageFactory::start_template_ref`] by default. Implement it to get the template id:
+    /// * `id` is the template id; for a dynamic reference it is the id read from the stream;
+    /// * `name` is the template name;
+    /// * `dynamic` is `true` if the template reference is dynamic.
+    fn start_template_ref_id(&mut self, _id: u32, name: &str, dynamic: bool) {
+        self.start_template_ref(name, dynamic)
+    }
+
     /// Called when a template reference (\<templateRef>) processing is finished.
     fn stop_template_ref(&mut self);
+
+    /// Called before each message decoded by [`Decoder::decode_packet`][crate::Decoder::decode_packet]
+    /// with the fields parsed from the packet preamble.
+    fn set_preamble(&mut self, _preamble: &Preamble) {}
+
+    /// Called instead of all other callbacks for a message which template is skipped by
+    /// [`Decoder::skip_template`][crate::Decoder::skip_template].
+    /// * `id` is the template id;
+    /// * `name` is the template name.
+    fn skip_template(&mut self, _id: u32, _name: &str) {}
+}
+
+/// Defines the interface for message factories that accept values borrowed from the decoded input.
+///
+/// The callback functions are the same as in [`MessageFactory`] except [`MessageFactoryRef::set_value`]
+/// that receives [`ValueRef`] which can borrow string and byte vector data from the input buffer for lifetime `'a`.
+/// Every [`MessageFactory`] implements this trait, so it can be used with zero-copy decoding as well.
+///
+pub trait MessageFactoryRef<'a> {
+    /// Called when a \<template> processing is started.
+    /// * `id` is the template id;
+    /// * `name` is the template name.
+    fn start_template(&mut self, id: u32, name: &str);
+
+    /// Called when a \<template> processing is finished.
+    fn stop_template(&mut self);
+
+    /// Called when a field element is processed.
+    /// * `id` is the field instruction id;
+    /// * `name` is the field name;
+    /// * `value` is the field value which is optional; it may borrow data from the input buffer.
+    fn set_value(&mut self, id: u32, name: &str, value: Option<ValueRef<'a>>);
+
+    /// Called by the decoder when a field element is processed; calls [`MessageFactoryRef::set_value`] by default.
+    /// * `field` describes the field instruction;
+    /// * `value` is the field value which is optional; it may borrow data from the input buffer.
+    fn set_field(&mut self, field: FieldInfo, value: Option<ValueRef<'a>>) {
+        self.set_value(field.id(), field.name(), value)
+    }
+
+    /// Called when a \<sequence> element processing is started.
+    /// * `id` is the sequence instruction id; can be `0` if id is not specified;
+    /// * `name` is the sequence name;
+    /// * `length` is the sequence length.
+    fn start_sequence(&mut self, id: u32, name: &str, length: u32);
+
+    /// Called when a sequence item processing is started.
+    /// * `index` is the sequence item index.
+    fn start_sequence_item(&mut self, index: u32);
+
+    /// Called when a sequence item processing is finished.
+    fn stop_sequence_item(&mut self);
+
+    /// Called when a \<sequence> processing is finished.
+    fn stop_sequence(&mut self);
+
+    /// Called when a \<group> element processing is started.
+    /// * `name` is the group name.
+    fn start_group(&mut self, name: &str);
+
+    /// Called when a \<group> element processing is finished.
+    fn stop_group(&mut self);
+
+    /// Called when a template reference (\<templateRef>) processing is started.
+    /// * `name` is the template name;
+    /// * `dynamic` is `true` if the template reference is dynamic.
+    fn start_template_ref(&mut self, name: &str, dynamic: bool);
+
+    /// Called by the decoder when a template reference processing is started;
+    /// calls [`MessageFactoryRef::start_template_ref`] by default.
+    /// * `id` is the template id; for a dynamic reference it is the id read from the stream;
+    /// * `name` is the template name;
+    /// * `dynamic` is `true` if the template reference is dynamic.
+    fn start_template_ref_id(&mut self, _id: u32, name: &str, dynamic: bool) {
+        self.start_template_ref(name, dynamic)
+    }
+
+    /// Called when a template reference (\<templateRef>) processing is finished.
+    fn stop_template_ref(&mut self);
+
+    /// Called before each message decoded by [`Decoder::decode_packet`][crate::Decoder::decode_packet]
+    /// with the fields parsed from the packet preamble.
+    fn set_preamble(&mut self, _preamble: &Preamble) {}
+
+    /// Called instead of all other callbacks for a message which template is skipped by
+    /// [`Decoder::skip_template`][crate::Decoder::skip_template].
+    /// * `id` is the template id;
+    /// * `name` is the template name.
+    fn skip_template(&mut self, _id: u32, _name: &str) {}
+}
+
+impl<'a, T: MessageFactory + ?Sized> MessageFactoryRef<'a> for T {
+    fn start_template(&mut self, id: u32, name: &str) {
+        MessageFactory::start_template(self, id, name)
+    }
+
+    fn stop_template(&mut self) {
+        MessageFactory::stop_template(self)
+    }
+
+    fn set_value(&mut self, id: u32, name: &str, value: Option<ValueRef<'a>>) {
+        MessageFactory::set_value(self, id, name, value.map(ValueRef::into_owned))
+    }
+
+    fn set_field(&mut self, field: FieldInfo, value: Option<ValueRef<'a>>) {
+        MessageFactory::set_field(self, field, value.map(ValueRef::into_owned))
+    }
+
+    fn start_sequence(&mut self, id: u32, name: &str, length: u32) {
+        MessageFactory::start_sequence(self, id, name, length)
+    }
+
+    fn start_sequence_item(&mut self, index: u32) {
+        MessageFactory::start_sequence_item(self, index)
+    }
+
+    fn stop_sequence_item(&mut self) {
+        MessageFactory::stop_sequence_item(self)
+    }
+
+    fn stop_sequence(&mut self) {
+        MessageFactory::stop_sequence(self)
+    }
+
+    fn start_group(&mut self, name: &str) {
+        MessageFactory::start_group(self, name)
+    }
+
+    fn stop_group(&mut self) {
+        MessageFactory::stop_group(self)
+    }
+
+    fn start_template_ref(&mut self, name: &str, dynamic: bool) {
+        MessageFactory::start_template_ref(self, name, dynamic)
+    }
+
+    fn start_template_ref_id(&mut self, id: u32, name: &str, dynamic: bool) {
+        MessageFactory::start_template_ref_id(self, id, name, dynamic)
+    }
+
+    fn stop_template_ref(&mut self) {
+        MessageFactory::stop_template_ref(self)
+    }
+
+    fn set_preamble(&mut self, preamble: &Preamble) {
+        MessageFactory::set_preamble(self, preamble)
+    }
+
+    fn skip_template(&mut self, id: u32, name: &str) {
+        MessageFactory::skip_template(self, id, name)
+    }
+}
+
+/// Tells the decoder how to proceed after a [`TryMessageFactory`] callback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    /// Continue decoding the message.
+    Continue,
+    /// Decode the rest of the message without passing it to the factory. The message is still read to the end
+    /// and the dictionaries are updated, so the following messages are decoded correctly.
+    ///
+    /// The factory receives no more callbacks for the message, including the closing ones: after `Skip` is
+    /// returned from [`TryMessageFactory::start_template`], the matching [`TryMessageFactory::stop_template`]
+    /// is never called. State pushed in a start callback must be dropped when `Skip` is returned.
+    Skip,
+}
+
+/// Defines the interface for message factories that can reject a message or stop passing it to the factory.
+///
+/// The callback functions are the same as in [`MessageFactoryRef`] but return [`Control`] that tells the decoder
+/// how to proceed. An error returned from a callback aborts decoding; the decoder returns this error,
+/// e.g. [`Error::Aborted`][crate::Error::Aborted]. Every [`MessageFactory`] and [`MessageFactoryRef`] implements
+/// this trait and always continues.
+///
+pub trait TryMessageFactory<'a> {
+    /// Called when a \<template> processing is started.
+    /// * `id` is the template id;
+    /// * `name` is the template name.
+    fn start_template(&mut self, id: u32, name: &str) -> Result<Control>;
+
+    /// Called when a \<template> processing is finished.
+    fn stop_template(&mut self) -> Result<Control>;
+
+    /// Called when a field element is processed.
+    /// * `id` is the field instruction id;
+    /// * `name` is the field name;
+    /// * `value` is the field value which is optional; it may borrow data from the input buffer.
+    fn set_value(&mut self, id: u32, name: &str, value: Option<ValueRef<'a>>) -> Result<Control>;
+
+    /// Called by the decoder when a field element is processed; calls [`TryMessageFactory::set_value`] by default.
+    /// * `field` describes the field instruction;
+    /// * `value` is the field value which is optional; it may borrow data from the input buffer.
+    fn set_field(&mut self, field: FieldInfo, value: Option<ValueRef<'a>>) -> Result<Control> {
+        self.set_value(field.id(), field.name(), value)
+    }
+
+    /// Called when a \<sequence> element processing is started.
+    /// * `id` is the sequence instruction id; can be `0` if id is not specified;
+    /// * `name` is the sequence name;
+    /// * `length` is the sequence length.
+    fn start_sequence(&mut self, id: u32, name: &str, length: u32) -> Result<Control>;
+
+    /// Called when a sequence item processing is started.
+    /// * `index` is the sequence item index.
+    fn start_sequence_item(&mut self, index: u32) -> Result<Control>;
+
+    /// Called when a sequence item processing is finished.
+    fn stop_sequence_item(&mut self) -> Result<Control>;
+
+    /// Called when a \<sequence> processing is finished.
+    fn stop_sequence(&mut self) -> Result<Control>;
+
+    /// Called when a \<group> element processing is started.
+    /// * `name` is the group name.
+    fn start_group(&mut self, name: &str) -> Result<Control>;
+
+    /// Called when a \<group> element processing is finished.
+    fn stop_group(&mut self) -> Result<Control>;
+
+    /// Called when a template reference (\<templateRef>) processing is started.
+    /// * `name` is the template name;
+    /// * `dynamic` is `true` if the template reference is dynamic.
+    fn start_template_ref(&mut self, name: &str, dynamic: bool) -> Result<Control>;
+
+    /// Called by the decoder when a template reference processing is started;
+    /// calls [`TryMessageFactory::start_template_ref`] by default.
+    /// * `id` is the template id; for a dynamic reference it is the id read from the stream;
+    /// * `name` is the template name;
+    /// * `dynamic` is `true` if the template reference is dynamic.
+    fn start_template_ref_id(&mut self, _id: u32, name: &str, dynamic: bool) -> Result<Control> {
+        self.start_template_ref(name, dynamic)
+    }
+
+    /// Called when a template reference (\<templateRef>) processing is finished.
+    fn stop_template_ref(&mut self) -> Result<Control>;
+
+    /// Called before each message decoded by [`Decoder::decode_packet`][crate::Decoder::decode_packet]
+    /// with the fields parsed from the packet preamble. [`Control::Skip`] skips the following message.
+    fn set_preamble(&mut self, _preamble: &Preamble) -> Result<Control> {
+        Ok(Control::Continue)
+    }
+
+    /// Called instead of all other callbacks for a message which template is skipped by
+    /// [`Decoder::skip_template`][crate::Decoder::skip_template].
+    /// * `id` is the template id;
+    /// * `name` is the template name.
+    fn skip_template(&mut self, _id: u32, _name: &str) -> Result<Control> {
+        Ok(Control::Continue)
+    }
+}
+
+impl<'a, T: MessageFactoryRef<'a> + ?Sized> TryMessageFactory<'a> for T {
+    fn start_template(&mut self, id: u32, name: &str) -> Result<Control> {
+        MessageFactoryRef::start_template(self, id, name);
+        Ok(Control::Continue)
+    }
+
+    fn stop_template(&mut self) -> Result<Control> {
+        MessageFactoryRef::stop_template(self);
+        Ok(Control::Continue)
+    }
+
+    fn set_value(&mut self, id: u32, name: &str, value: Option<ValueRef<'a>>) -> Result<Control> {
+        MessageFactoryRef::set_value(self, id, name, value);
+        Ok(Control::Continue)
+    }
+
+    fn set_field(&mut self, field: FieldInfo, value: Option<ValueRef<'a>>) -> Result<Control> {
+        MessageFactoryRef::set_field(self, field, value);
+        Ok(Control::Continue)
+    }
+
+    fn start_sequence(&mut self, id: u32, name: &str, length: u32) -> Result<Control> {
+        MessageFactoryRef::start_sequence(self, id, name, length);
+        Ok(Control::Continue)
+    }
+
+    fn start_sequence_item(&mut self, index: u32) -> Result<Control> {
+        MessageFactoryRef::start_sequence_item(self, index);
+        Ok(Control::Continue)
+    }
+
+    fn stop_sequence_item(&mut self) -> Result<Control> {
+        MessageFactoryRef::stop_sequence_item(self);
+        Ok(Control::Continue)
+    }
+
+    fn stop_sequence(&mut self) -> Result<Control> {
+        MessageFactoryRef::stop_sequence(self);
+        Ok(Control::Continue)
+    }
+
+    fn start_group(&mut self, name: &str) -> Result<Control> {
+        MessageFactoryRef::start_group(self, name);
+        Ok(Control::Continue)
+    }
+
+    fn stop_group(&mut self) -> Result<Control> {
+        MessageFactoryRef::stop_group(self);
+        Ok(Control::Continue)
+    }
+
+    fn start_template_ref(&mut self, name: &str, dynamic: bool) -> Result<Control> {
+        MessageFactoryRef::start_template_ref(self, name, dynamic);
+        Ok(Control::Continue)
+    }
+
+    fn start_template_ref_id(&mut self, id: u32, name: &str, dynamic: bool) -> Result<Control> {
+        MessageFactoryRef::start_template_ref_id(self, id, name, dynamic);
+        Ok(Control::Continue)
+    }
+
+    fn stop_template_ref(&mut self) -> Result<Control> {
+        MessageFactoryRef::stop_template_ref(self);
+        Ok(Control::Continue)
+    }
+
+    fn set_preamble(&mut self, preamble: &Preamble) -> Result<Control> {
+        MessageFactoryRef::set_preamble(self, preamble);
+        Ok(Control::Continue)
+    }
+
+    fn skip_template(&mut self, id: u32, name: &str) -> Result<Control> {
+        MessageFactoryRef::skip_template(self, id, name);
+        Ok(Control::Continue)
+    }
+}
+
+/// Identifies the template of a dynamic template reference, see [`MessageVisitor::select_template_ref_key`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemplateKey {
+    /// The template id.
+    Id(u32),
+    /// The template name.
+    Name(String),
 }
 
 /// Defines the interface for message visitors.
@@ -61,6 +416,13 @@ pub trait MessageVisitor {
 
     fn get_value(&mut self, name: &str, type_: &ValueType) -> Result<Option<Value>>;
 
+    /// Called by the encoder to get the value of a field element; calls [`MessageVisitor::get_value`] by default.
+    /// Implement it to get the field metadata:
+    /// * `field` describes the field instruction.
+    fn get_field(&mut self, field: FieldInfo) -> Result<Option<Value>> {
+        self.get_value(field.name(), field.value_type())
+    }
+
     fn select_group(&mut self, name: &str) -> Result<bool>;
 
     fn release_group(&mut self) -> Result<()>;
@@ -75,5 +437,14 @@ pub trait MessageVisitor {
 
     fn select_template_ref(&mut self, name: &str, dynamic: bool) -> Result<Option<String>>;
 
+    /// Called by the encoder when a template reference processing is started;
+    /// calls [`MessageVisitor::select_template_ref`] by default. Implement it to select the template
+    /// of a dynamic reference by id:
+    /// * `name` is the template name of a static reference, empty for a dynamic reference;
+    /// * `dynamic` is `true` if the template reference is dynamic.
+    fn select_template_ref_key(&mut self, name: &str, dynamic: bool) -> Result<Option<TemplateKey>> {
+        Ok(self.select_template_ref(name, dynamic)?.map(TemplateKey::Name))
+    }
+
     fn release_template_ref(&mut self) -> Result<()>;
 }
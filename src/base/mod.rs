@@ -1,4 +1,5 @@
 pub(crate) mod message;
+pub(crate) mod field;
 pub(crate) mod instruction;
 pub(crate) mod pmap;
 pub(crate) mod value;
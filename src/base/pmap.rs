@@ -1,44 +1,106 @@
-#[derive(Debug, PartialEq)]
-pub(crate) struct PresenceMap {
+// Number of bits kept inline, i.e. 9 bytes of the presence map in the transfer encoding.
+const INLINE_BITS: usize = 63;
+
+/// Represents the presence map field.
+///
+/// There is no limit on the size of the presence map. The first 63 bits are stored inline, so the common
+/// short presence maps never allocate; the following bits are kept in a vector, 7 bits per byte as they are
+/// laid out in the stream.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PresenceMap {
+    // The first (up to 63) bits; the first bit of the presence map is the most significant one.
     pub(crate) bitmap: u64,
-    pub(crate) mask: u64,
-    pub(crate) size: u8,
+    // The bits past the first 63 ones, 7 bits per byte.
+    pub(crate) rest: Vec<u8>,
+    // Number of bits in the presence map, always a multiple of 7.
+    pub(crate) size: usize,
+    // Index of the next bit to read or to write.
+    pub(crate) pos: usize,
 }
 
-/// Represents the presence map field.
 impl PresenceMap {
     pub(crate) fn new_empty() -> Self {
-        Self {
-            bitmap: 0,
-            mask: 0x40, // 0100 0000
-            size: 7,
-        }
+        Self::default()
     }
 
-    pub(crate) fn new(bitmap: u64, size: u8) -> Self {
+    #[cfg(test)]
+    pub(crate) fn new(bitmap: u64, size: usize) -> Self {
+        assert!(size % 7 == 0 && size <= INLINE_BITS);
         Self {
             bitmap,
-            mask: 1u64 << (size - 1),
             size,
+            ..Self::default()
+        }
+    }
+
+    /// Number of bits in the presence map.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the bit at `index`; bits past the end of the presence map are not set.
+    pub fn bit(&self, index: usize) -> bool {
+        if index >= self.size {
+            return false;
+        }
+        let inline_size = self.size.min(INLINE_BITS);
+        if index < inline_size {
+            return (self.bitmap >> (inline_size - 1 - index)) & 1 != 0;
+        }
+        let index = index - INLINE_BITS;
+        (self.rest[index / 7] >> (6 - index % 7)) & 1 != 0
+    }
+
+    // Appends 7 bits (one byte of the transfer encoding without the stop bit) to the presence map.
+    pub(crate) fn push_group(&mut self, group: u8) {
+        if self.size < INLINE_BITS {
+            self.bitmap = (self.bitmap << 7) | (group & 0x7f) as u64;
+        } else {
+            self.rest.push(group & 0x7f);
         }
+        self.size += 7;
+    }
+
+    // Returns 7 bits of the presence map at `index` (in groups of 7 bits).
+    pub(crate) fn group(&self, index: usize) -> u8 {
+        let inline_groups = self.size.min(INLINE_BITS) / 7;
+        if index < inline_groups {
+            ((self.bitmap >> ((inline_groups - 1 - index) * 7)) & 0x7f) as u8
+        } else {
+            self.rest[index - inline_groups]
+        }
+    }
+
+    // Number of 7-bit groups in the presence map.
+    pub(crate) fn groups(&self) -> usize {
+        self.size / 7
     }
 
     pub(crate) fn next_bit_set(&mut self) -> bool {
-        let res = self.bitmap & self.mask != 0;
-        self.mask >>= 1;
+        let res = self.bit(self.pos);
+        self.pos += 1;
         res
     }
 
+    // Returns `true` if any of the bits not read yet is set.
+    pub(crate) fn has_unread_bits_set(&self) -> bool {
+        (self.pos..self.size).any(|i| self.bit(i))
+    }
+
     pub(crate) fn set_next_bit(&mut self, value: bool) {
-        if self.mask == 0 {
-            self.bitmap <<= 7;
-            self.mask = 0x40;
-            self.size += 7;
+        if self.pos == self.size {
+            self.push_group(0);
         }
         if value {
-            self.bitmap |= self.mask;
+            let inline_size = self.size.min(INLINE_BITS);
+            if self.pos < inline_size {
+                self.bitmap |= 1 << (inline_size - 1 - self.pos);
+            } else {
+                let index = self.pos - INLINE_BITS;
+                self.rest[index / 7] |= 1 << (6 - index % 7);
+            }
         }
-        self.mask >>= 1;
+        self.pos += 1;
     }
 }
 
@@ -82,4 +144,45 @@ mod tests {
         assert_eq!(pmap.bitmap, 0b10101101010000);
         assert_eq!(pmap.size, 14);
     }
+
+    #[test]
+    fn presence_map_longer_than_63_bits() {
+        let bits: Vec<bool> = (0..150).map(|i| i % 3 == 0 || i == 149).collect();
+        let mut pmap = PresenceMap::new_empty();
+        for b in &bits {
+            pmap.set_next_bit(*b);
+        }
+        assert_eq!(pmap.size(), 154);
+        assert_eq!(pmap.groups(), 22);
+        assert_eq!(pmap.rest.len(), 13);
+        for (i, b) in bits.iter().enumerate() {
+            assert_eq!(pmap.bit(i), *b, "bit {i}");
+        }
+        // the same bits read back from the 7-bit groups
+        let mut copy = PresenceMap::new_empty();
+        for i in 0..pmap.groups() {
+            copy.push_group(pmap.group(i));
+        }
+        for b in &bits {
+            assert_eq!(copy.next_bit_set(), *b);
+        }
+        assert!(!copy.has_unread_bits_set());
+        assert_eq!(copy.next_bit_set(), false);
+    }
+
+    #[test]
+    fn presence_map_has_unread_bits_set() {
+        let mut pmap = PresenceMap::new_empty();
+        for _ in 0..70 {
+            pmap.push_group(0);
+        }
+        pmap.push_group(0b0000001);
+        for _ in 0..pmap.size() - 1 {
+            assert!(pmap.has_unread_bits_set());
+            assert_eq!(pmap.next_bit_set(), false);
+        }
+        assert!(pmap.has_unread_bits_set());
+        assert_eq!(pmap.next_bit_set(), true);
+        assert!(!pmap.has_unread_bits_set());
+    }
 }
@@ -1,9 +1,8 @@
-use std::cell::Cell;
-use std::rc::Rc;
+use std::sync::Arc;
 
 use roxmltree::Node;
 
-use crate::{Error, Result};
+use crate::{Error, FastErrorCode, Result};
 use crate::base::instruction::Instruction;
 
 /// A template contains a sequence of instructions. The order of the instructions is significant and corresponds
@@ -15,26 +14,39 @@ pub(crate) struct Template {
     pub(crate) dictionary: Dictionary,
     pub(crate) instructions: Vec<Instruction>,
 
+    // Position of the template in definitions; selects its block of "template" dictionary slots.
+    pub(crate) index: usize,
+
+    // Index of the application type from `type_ref`; 0 is the special type `any`.
+    pub(crate) type_index: usize,
+
     // This flag indicates if the template requires a presence map in case of statically referenced
-    // from another template. If the flag is None, the presence map is not calculated yet.
-    pub(crate) require_pmap: Cell<Option<bool>>,
+    // from another template. It is calculated when definitions are created.
+    pub(crate) require_pmap: bool,
+
+    // Set by `reset="yes"` attribute: all dictionaries are reset after a message of this template is processed.
+    pub(crate) reset: bool,
 }
 
 impl Template {
     pub(crate) fn from_node(node: Node) -> Result<Self> {
         if node.tag_name().name() != "template" {
-            return Err(Error::Static(format!("expected <template/> node, got <{}/>", node.tag_name().name())));
+            return Err(Error::fast(FastErrorCode::S1, format!("expected <template/> node, got <{}/>", node.tag_name().name()))); // [ERR S1]
         }
         let id = node
             .attribute("id")
             .or(Some("0"))
             .unwrap()
             .parse::<u32>()?;
+        // A template without a name can only be referenced by id, e.g. from a dynamic template reference.
         let name = node
             .attribute("name")
-            .ok_or_else(|| Error::Static("template name not found".to_string()))?
+            .unwrap_or("")
             .to_string();
-        let type_ref = node
+        if id == 0 && name.is_empty() {
+            return Err(Error::fast(FastErrorCode::S1, "template must have 'id' or 'name' attribute")); // [ERR S1]
+        }
+        let mut type_ref = node
             .attribute("typeRef")
             .map(|d| TypeRef::from_str(d))
             .unwrap_or(TypeRef::Any);
@@ -42,11 +54,21 @@ impl Template {
             .attribute("dictionary")
             .map(|d| Dictionary::from_str(d))
             .unwrap_or(Dictionary::Global);
+        let reset = match node.attribute("reset") {
+            None | Some("no") => false,
+            Some("yes") => true,
+            Some(r) => return Err(Error::fast(FastErrorCode::S1, format!("unknown reset value: {r}"))), // [ERR S1]
+        };
         let mut instructions = Vec::new();
         for child in node.children() {
-            if child.is_element() {
-                instructions.push(Instruction::from_node(child)?);
+            if !child.is_element() {
+                continue;
             }
+            if child.tag_name().name() == "typeRef" {
+                type_ref = TypeRef::from_node(child)?;
+                continue;
+            }
+            instructions.push(Instruction::from_node(child)?);
         }
         Ok(Self {
             id,
@@ -54,7 +76,10 @@ impl Template {
             type_ref,
             dictionary,
             instructions,
-            require_pmap: Cell::new(None),
+            index: 0,
+            type_index: 0,
+            require_pmap: false,
+            reset,
         })
     }
 }
@@ -62,7 +87,7 @@ impl Template {
 
 /// Field operators specify ways to optimize the encoding of a field.
 #[derive(Debug, PartialEq, Clone, Copy)]
-pub(crate) enum Operator {
+pub enum Operator {
     None,
     Constant,
     Default,
@@ -81,7 +106,19 @@ impl Operator {
             "increment" => Ok(Self::Increment),
             "delta" => Ok(Self::Delta),
             "tail" => Ok(Self::Tail),
-            _ => Err(Error::Static(format!("Unknown operator: {}", t))),
+            _ => Err(Error::fast(FastErrorCode::S1, format!("Unknown operator: {}", t))), // [ERR S1]
+        }
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Constant => "constant",
+            Self::Default => "default",
+            Self::Copy => "copy",
+            Self::Increment => "increment",
+            Self::Delta => "delta",
+            Self::Tail => "tail",
         }
     }
 }
@@ -89,7 +126,7 @@ impl Operator {
 
 /// The optional presence attribute indicates whether the field is mandatory or optional.
 #[derive(Debug, PartialEq, Copy, Clone)]
-pub(crate) enum Presence {
+pub enum Presence {
     Mandatory,
     Optional,
 }
@@ -99,7 +136,7 @@ impl Presence {
         match s {
             "mandatory" => Ok(Self::Mandatory),
             "optional" => Ok(Self::Optional),
-            _ => Err(Error::Static(format!("unknown presence: {s}"))),
+            _ => Err(Error::fast(FastErrorCode::S1, format!("unknown presence: {s}"))), // [ERR S1]
         }
     }
 }
@@ -114,7 +151,7 @@ pub(crate) enum Dictionary {
     Global,
     Template,
     Type,
-    UserDefined(Rc<str>),
+    UserDefined(Arc<str>),
 }
 
 impl Dictionary {
@@ -123,7 +160,7 @@ impl Dictionary {
             "global" => Self::Global,
             "template" => Self::Template,
             "type" => Self::Type,
-            _ => Self::UserDefined(Rc::from(name)),
+            _ => Self::UserDefined(Arc::from(name)),
         }
     }
 }
@@ -131,14 +168,26 @@ impl Dictionary {
 
 /// The current application type initially the special type any.
 /// The current application type changes when the processor encounters an element containing a "typeRef" element.
+/// Application type is identified by (namespace, name); the `typeRef` attribute has no namespace.
 #[derive(Debug, PartialEq, Clone)]
 pub(crate) enum TypeRef {
     Any,
-    ApplicationType(Rc<str>),
+    ApplicationType(Option<Arc<str>>, Arc<str>),
 }
 
 impl TypeRef {
     pub(crate) fn from_str(name: &str) -> Self {
-        Self::ApplicationType(Rc::from(name))
+        Self::ApplicationType(None, Arc::from(name))
+    }
+
+    /// Parse `<typeRef name=".." ns=".."/>` child element of a template, group or sequence.
+    pub(crate) fn from_node(node: Node) -> Result<Self> {
+        match node.attribute("name") {
+            Some(name) if !name.is_empty() => {
+                let ns = node.attribute("ns").filter(|ns| !ns.is_empty()).map(Arc::from);
+                Ok(Self::ApplicationType(ns, Arc::from(name)))
+            }
+            _ => Err(Error::fast(FastErrorCode::S1, "typeRef must have 'name' attribute")), // [ERR S1]
+        }
     }
 }
@@ -1,9 +1,10 @@
+use std::borrow::Cow;
 use std::cmp::min;
 use std::fmt::{Display, Formatter};
 
-use crate::{Error, Result};
+use crate::{Error, FastErrorCode, Result};
 use crate::base::decimal::Decimal;
-use crate::utils::bytes::{bytes_delta, bytes_tail, string_delta, string_tail, string_to_bytes};
+use crate::utils::bytes::{bytes_delta, bytes_tail, bytes_to_string, string_delta, string_tail, string_to_bytes};
 
 /// Represents type of field instruction.
 ///
@@ -48,7 +49,7 @@ impl ValueType {
             "sequence" => Ok(Self::Sequence),
             "group" => Ok(Self::Group),
             "templateRef" => Ok(Self::TemplateReference),
-            _ => Err(Error::Static(format!("Unknown type: {}", tag))),
+            _ => Err(Error::fast(FastErrorCode::S1, format!("Unknown type: {}", tag))), // [ERR S1]
         }
     }
 
@@ -142,6 +143,10 @@ pub enum Value {
 impl Value {
     // It is a dynamic error [ERR D11] if a string does not match the syntax.
     pub fn set_from_string(&mut self, s: &str) -> Result<()> {
+        self.parse_string(s).map_err(|e| e.with_code(FastErrorCode::D11))
+    }
+
+    fn parse_string(&mut self, s: &str) -> Result<()> {
         match self {
             Value::UInt32(_) => {
                 *self = Value::UInt32(s.parse()?);
@@ -188,7 +193,7 @@ impl Value {
                 i = sub as usize;
             }
             if i > len {
-                return Err(Error::Dynamic(format!("subtraction length ({i}) is larger than string length ('{len}')")));  // [ERR D7]
+                return Err(Error::fast(FastErrorCode::D7, format!("subtraction length ({i}) is larger than string length ('{len}')")));  // [ERR D7]
             }
             if !front {
                 i = len - i;
@@ -245,7 +250,7 @@ impl Value {
             }
             (Value::UnicodeString(v), Value::Bytes(d)) => {
                 let b = bytes_delta(v.as_bytes(), d, sub)?;
-                let s = String::from_utf8(b)?; // [ERR R2]
+                let s = String::from_utf8(b).map_err(|e| Error::from(e).with_code(FastErrorCode::R2))?; // [ERR R2]
                 Ok(Value::UnicodeString(s))
             }
             _ => Err(Error::Runtime(format!("Cannot apply delta {:?} to {:?}", delta, self))),
@@ -360,3 +365,95 @@ impl Display for Value {
         }
     }
 }
+
+
+/// Represents current value of a field that may borrow its data from the decoded input.
+///
+/// Unicode strings and byte vectors are borrowed when the message is decoded from a contiguous buffer
+/// (see [`Decoder::decode_slice`][crate::Decoder::decode_slice]). ASCII strings and values derived from
+/// the dictionary (previous, initial, delta or tail values) are always owned.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ValueRef<'a> {
+    UInt32(u32),
+    Int32(i32),
+    UInt64(u64),
+    Int64(i64),
+    Decimal(Decimal),
+    ASCIIString(Cow<'a, str>),
+    UnicodeString(Cow<'a, str>),
+    Bytes(Cow<'a, [u8]>),
+}
+
+impl ValueRef<'_> {
+    /// Converts the value into owned [`Value`], copying borrowed data if needed.
+    pub fn into_owned(self) -> Value {
+        match self {
+            ValueRef::UInt32(v) => Value::UInt32(v),
+            ValueRef::Int32(v) => Value::Int32(v),
+            ValueRef::UInt64(v) => Value::UInt64(v),
+            ValueRef::Int64(v) => Value::Int64(v),
+            ValueRef::Decimal(v) => Value::Decimal(v),
+            ValueRef::ASCIIString(s) => Value::ASCIIString(s.into_owned()),
+            ValueRef::UnicodeString(s) => Value::UnicodeString(s.into_owned()),
+            ValueRef::Bytes(b) => Value::Bytes(b.into_owned()),
+        }
+    }
+
+    /// Returns owned copy of the value.
+    pub fn to_value(&self) -> Value {
+        self.clone().into_owned()
+    }
+
+    /// Returns `true` if the value borrows its data from the decoded input.
+    pub fn is_borrowed(&self) -> bool {
+        matches!(
+            self,
+            ValueRef::ASCIIString(Cow::Borrowed(_)) | ValueRef::UnicodeString(Cow::Borrowed(_)) | ValueRef::Bytes(Cow::Borrowed(_))
+        )
+    }
+}
+
+impl From<Value> for ValueRef<'_> {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::UInt32(v) => ValueRef::UInt32(v),
+            Value::Int32(v) => ValueRef::Int32(v),
+            Value::UInt64(v) => ValueRef::UInt64(v),
+            Value::Int64(v) => ValueRef::Int64(v),
+            Value::Decimal(v) => ValueRef::Decimal(v),
+            Value::ASCIIString(s) => ValueRef::ASCIIString(Cow::Owned(s)),
+            Value::UnicodeString(s) => ValueRef::UnicodeString(Cow::Owned(s)),
+            Value::Bytes(b) => ValueRef::Bytes(Cow::Owned(b)),
+        }
+    }
+}
+
+impl<'a> From<&'a Value> for ValueRef<'a> {
+    fn from(value: &'a Value) -> Self {
+        match value {
+            Value::UInt32(v) => ValueRef::UInt32(*v),
+            Value::Int32(v) => ValueRef::Int32(*v),
+            Value::UInt64(v) => ValueRef::UInt64(*v),
+            Value::Int64(v) => ValueRef::Int64(*v),
+            Value::Decimal(v) => ValueRef::Decimal(v.clone()),
+            Value::ASCIIString(s) => ValueRef::ASCIIString(Cow::Borrowed(s)),
+            Value::UnicodeString(s) => ValueRef::UnicodeString(Cow::Borrowed(s)),
+            Value::Bytes(b) => ValueRef::Bytes(Cow::Borrowed(b)),
+        }
+    }
+}
+
+impl Display for ValueRef<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValueRef::UInt32(v) => f.write_fmt(format_args!("{v}")),
+            ValueRef::Int32(v) => f.write_fmt(format_args!("{v}")),
+            ValueRef::UInt64(v) => f.write_fmt(format_args!("{v}")),
+            ValueRef::Int64(v) => f.write_fmt(format_args!("{v}")),
+            ValueRef::Decimal(v) => f.write_fmt(format_args!("{v}")),
+            ValueRef::ASCIIString(s) => f.write_str(s),
+            ValueRef::UnicodeString(s) => f.write_str(s),
+            ValueRef::Bytes(b) => f.write_str(&bytes_to_string(b)),
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use crate::{Error, Result};
+use crate::decoder::reader::Reader;
+use crate::encoder::writer::Writer;
+
+/// Encoding of the length that precedes each message in block-framed streams.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BlockLength {
+    /// Stop-bit encoded unsigned integer as defined by the FAST block encoding.
+    StopBit,
+    /// Fixed-size 2 bytes unsigned integer in big-endian byte order.
+    U16BigEndian,
+    /// Fixed-size 2 bytes unsigned integer in little-endian byte order.
+    U16LittleEndian,
+    /// Fixed-size 4 bytes unsigned integer in big-endian byte order.
+    U32BigEndian,
+    /// Fixed-size 4 bytes unsigned integer in little-endian byte order.
+    U32LittleEndian,
+}
+
+impl BlockLength {
+    /// Read the block length. Returns [`Error::Eof`][crate::Error::Eof] if the stream ends before the first byte.
+    pub(crate) fn read(&self, rdr: &mut (impl Reader + ?Sized)) -> Result<usize> {
+        let first = match rdr.read_u8() {
+            Ok(b) => b,
+            Err(Error::UnexpectedEof) => return Err(Error::Eof),
+            Err(e) => return Err(e),
+        };
+        let (size, big_endian) = match self {
+            BlockLength::StopBit => {
+                let mut value: u64 = (first & 0x7f) as u64;
+                let mut byte = first;
+                while byte & 0x80 == 0 {
+                    byte = rdr.read_u8()?;
+                    value = (value << 7) | (byte & 0x7f) as u64;
+                }
+                return usize::try_from(value)
+                    .map_err(|_| Error::Dynamic(format!("block length is too big: {}", value)));
+            }
+            BlockLength::U16BigEndian => (2, true),
+            BlockLength::U16LittleEndian => (2, false),
+            BlockLength::U32BigEndian => (4, true),
+            BlockLength::U32LittleEndian => (4, false),
+        };
+        let mut bytes = [first, 0, 0, 0];
+        for b in bytes.iter_mut().take(size).skip(1) {
+            *b = rdr.read_u8()?;
+        }
+        let bytes = &mut bytes[..size];
+        if !big_endian {
+            bytes.reverse();
+        }
+        Ok(bytes.iter().fold(0usize, |v, b| (v << 8) | *b as usize))
+    }
+
+    /// Write the block length.
+    pub(crate) fn write(&self, wrt: &mut (impl Writer + ?Sized), length: usize) -> Result<()> {
+        match self {
+            BlockLength::StopBit => wrt.write_uint(length as u64),
+            BlockLength::U16BigEndian | BlockLength::U16LittleEndian => {
+                let length = u16::try_from(length)
+                    .map_err(|_| Error::Runtime(format!("block length doesn't fit 2 bytes: {}", length)))?;
+                if *self == BlockLength::U16BigEndian {
+                    wrt.write_buf(&length.to_be_bytes())
+                } else {
+                    wrt.write_buf(&length.to_le_bytes())
+                }
+            }
+            BlockLength::U32BigEndian | BlockLength::U32LittleEndian => {
+                let length = u32::try_from(length)
+                    .map_err(|_| Error::Runtime(format!("block length doesn't fit 4 bytes: {}", length)))?;
+                if *self == BlockLength::U32BigEndian {
+                    wrt.write_buf(&length.to_be_bytes())
+                } else {
+                    wrt.write_buf(&length.to_le_bytes())
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+
+    use super::*;
+
+    #[test]
+    fn block_length_read_write() {
+        for (header, length, raw) in [
+            (BlockLength::StopBit, 300usize, vec![0x02, 0xac]),
+            (BlockLength::U16BigEndian, 300, vec![0x01, 0x2c]),
+            (BlockLength::U16LittleEndian, 300, vec![0x2c, 0x01]),
+            (BlockLength::U32BigEndian, 300, vec![0x00, 0x00, 0x01, 0x2c]),
+            (BlockLength::U32LittleEndian, 300, vec![0x2c, 0x01, 0x00, 0x00]),
+        ] {
+            let mut buf = BytesMut::new();
+            header.write(&mut buf, length).unwrap();
+            assert_eq!(buf.to_vec(), raw, "{:?}", header);
+            let mut buf = bytes::Bytes::from(raw);
+            assert_eq!(header.read(&mut buf).unwrap(), length, "{:?}", header);
+            assert!(matches!(header.read(&mut buf), Err(Error::Eof)));
+        }
+        assert!(BlockLength::U16BigEndian.write(&mut BytesMut::new(), 70000).is_err());
+    }
+}
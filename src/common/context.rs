@@ -1,115 +1,291 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
-use hashbrown::HashMap;
+use crate::{Result, Value};
 
-use crate::Value;
-
-pub enum DictionaryType {
+/// Identifies a single dictionary, see [`Decoder::reset_dictionary`][crate::Decoder::reset_dictionary].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DictionaryId {
+    /// The "global" dictionary.
     Global,
+    /// The "template" dictionary of the template with the id.
     Template(u32),
-    Type(Rc<str>),
-    UserDefined(Rc<str>),
+    /// The "type" dictionary of the application type with the name and namespace;
+    /// the special type `any` is named "__any__" and has no namespace.
+    Type { name: String, ns: Option<String> },
+    /// The user defined dictionary with the name.
+    User(String),
+}
+
+/// An assigned entry of a dictionary, see [`Decoder::dictionary_entries`][crate::Decoder::dictionary_entries].
+#[derive(Debug, Clone, PartialEq)]
+pub struct DictionaryEntry {
+    /// The dictionary of the entry.
+    pub dictionary: DictionaryId,
+    /// The key of the entry; it is the field name unless the `key` attribute is specified.
+    pub key: String,
+    /// The previous value; `None` if it is empty.
+    pub value: Option<Value>,
+}
+
+/// Dictionary entry of an instruction resolved by [`DictionaryLayout`].
+///
+/// Entries of "global" and user defined dictionaries are resolved to a [`Context`] slot right away.
+/// The "template" and "type" dictionaries depend on the current template and application type,
+/// so only the key index is resolved; the slot is calculated from it during processing.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub(crate) enum DictionarySlot {
+    Static(usize),
+    Template(usize),
+    Type(usize),
+}
+
+/// Layout of all dictionaries in the flat [`Context`] storage. Computed once when definitions are created.
+///
+/// The storage consists of three regions:
+/// * slots of "global" and user defined dictionaries;
+/// * template dictionaries: `templates` blocks of `template_keys.len()` slots each;
+/// * type dictionaries: `types.len()` blocks of `type_keys.len()` slots each.
+#[derive(Debug, Default)]
+pub(crate) struct DictionaryLayout {
+    // Entries of "global" and user defined dictionaries as (dictionary name, key); `None` is the "global" dictionary.
+    pub(crate) static_keys: Vec<(Option<Arc<str>>, Arc<str>)>,
+
+    // Keys used with "template" dictionary.
+    pub(crate) template_keys: Vec<Arc<str>>,
+
+    // Keys used with "type" dictionary.
+    pub(crate) type_keys: Vec<Arc<str>>,
+
+    // Application types as (namespace, name). Index 0 is the special type `any`.
+    pub(crate) types: Vec<(Option<Arc<str>>, Arc<str>)>,
+
+    // Number of templates.
+    pub(crate) templates: usize,
+}
+
+impl DictionaryLayout {
+    /// Total number of slots in the storage.
+    pub(crate) fn size(&self) -> usize {
+        self.static_keys.len()
+            + self.templates * self.template_keys.len()
+            + self.types.len() * self.type_keys.len()
+    }
+
+    /// Slot of the key `k` in the "template" dictionary of the template `template`.
+    #[inline]
+    pub(crate) fn template_slot(&self, template: usize, k: usize) -> usize {
+        self.static_keys.len() + template * self.template_keys.len() + k
+    }
+
+    /// Slot of the key `k` in the "type" dictionary of the application type `type_`.
+    #[inline]
+    pub(crate) fn type_slot(&self, type_: usize, k: usize) -> usize {
+        self.static_keys.len() + self.templates * self.template_keys.len() + type_ * self.type_keys.len() + k
+    }
+
+    /// Dictionary entry of the storage `slot`; the inverse of [`DictionaryLayout::template_slot`]
+    /// and [`DictionaryLayout::type_slot`].
+    pub(crate) fn entry(&self, slot: usize) -> DictionarySlot {
+        if slot < self.static_keys.len() {
+            return DictionarySlot::Static(slot);
+        }
+        let slot = slot - self.static_keys.len();
+        let templates_size = self.templates * self.template_keys.len();
+        if slot < templates_size {
+            return DictionarySlot::Template(slot % self.template_keys.len());
+        }
+        DictionarySlot::Type((slot - templates_size) % self.type_keys.len())
+    }
 }
 
 /// Decoder state that stores global state during all messages decoding.
 /// Created when decoder is created.
 /// Destroyed when decoder is destroyed.
 /// Can be reset during messages decoding.
-#[derive(Debug, PartialEq)]
+///
+/// Each slot holds the state of the previous value: `None` if undefined, `Some(None)` if empty
+/// and `Some(Some(v))` if assigned.
+///
+/// Changes can be recorded in a journal (see [`Context::begin`]) to be undone with [`Context::rollback`].
+///
+/// The slots are shared with the forks of the context (see [`Context::fork`]) and copied on the first change.
+#[derive(Debug)]
 pub(crate) struct Context {
-    global: HashMap<Rc<str>, Option<Value>>,
-    template: HashMap<u32, HashMap<Rc<str>, Option<Value>>>,
-    type_: HashMap<Rc<str>, HashMap<Rc<str>, Option<Value>>>,
-    user: HashMap<Rc<str>, HashMap<Rc<str>, Option<Value>>>,
+    values: Arc<Vec<Option<Option<Value>>>>,
+
+    // Previous states of the changed slots, in order of change.
+    journal: Vec<(usize, Option<Option<Value>>)>,
+    journaling: bool,
 }
 
 impl Context {
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(size: usize) -> Self {
         Self {
-            global: HashMap::new(),
-            template: HashMap::new(),
-            type_: HashMap::new(),
-            user: HashMap::new(),
+            values: Arc::new(vec![None; size]),
+            journal: Vec::new(),
+            journaling: false,
         }
     }
 
     pub(crate) fn reset(&mut self) {
-        self.global.clear();
-        self.template.clear();
-        self.type_.clear();
-        self.user.clear();
-    }
-
-    pub(crate) fn set(&mut self, dict: DictionaryType, key: Rc<str>, val: &Option<Value>) {
-        match dict {
-            DictionaryType::Global => {
-                self.global.insert(key, val.clone());
-            }
-            DictionaryType::Template(id) => {
-                if !self.template.contains_key(&id) {
-                    let mut hm = HashMap::new();
-                    hm.insert(key, val.clone());
-                    self.template.insert(id, hm);
-                } else {
-                    self.template.get_mut(&id).unwrap().insert(key, val.clone());
-                }
-            }
-            DictionaryType::Type(name) => {
-                if !self.type_.contains_key(&name) {
-                    let mut hm = HashMap::new();
-                    hm.insert(key.clone(), val.clone());
-                    self.type_.insert(name, hm);
-                } else {
-                    self.type_.get_mut(&name).unwrap().insert(key.clone(), val.clone());
-                }
-            }
-            DictionaryType::UserDefined(name) => {
-                if !self.user.contains_key(&name) {
-                    let mut hm = HashMap::new();
-                    hm.insert(key.clone(), val.clone());
-                    self.user.insert(name, hm);
-                } else {
-                    self.user.get_mut(&name).unwrap().insert(key.clone(), val.clone());
-                }
-            }
+        if !self.journaling {
+            self.values = Arc::new(vec![None; self.values.len()]);
+            self.journal.clear();
+            return;
+        }
+        // Record the assigned slots, so the reset can be rolled back as any other change.
+        for slot in 0..self.values.len() {
+            self.clear(slot);
         }
     }
 
-    pub(crate) fn get(&self, dict: DictionaryType, key: &Rc<str>) -> Option<Option<Value>> {
-        match dict {
-            DictionaryType::Global => {
-                match self.global.get(key) {
-                    None => None,
-                    Some(v) => Some(v.clone()),
-                }
-            }
-            DictionaryType::Template(id) => {
-                match self.template.get(&id) {
-                    None => None,
-                    Some(hm) => match hm.get(key) {
-                        None => None,
-                        Some(v) => Some(v.clone()),
-                    }
-                }
-            }
-            DictionaryType::Type(name) => {
-                match self.type_.get(&name) {
-                    None => None,
-                    Some(hm) => match hm.get(key) {
-                        None => None,
-                        Some(v) => Some(v.clone()),
-                    }
-                }
-            }
-            DictionaryType::UserDefined(name) => {
-                match self.user.get(&name) {
-                    None => None,
-                    Some(hm) => match hm.get(key) {
-                        None => None,
-                        Some(v) => Some(v.clone()),
-                    }
-                }
-            }
+    #[inline]
+    pub(crate) fn set(&mut self, slot: usize, val: Option<Value>) {
+        let prev = Arc::make_mut(&mut self.values)[slot].replace(val);
+        if self.journaling {
+            self.journal.push((slot, prev));
         }
     }
+
+    #[inline]
+    pub(crate) fn get(&self, slot: usize) -> Option<Option<Value>> {
+        self.values[slot].clone()
+    }
+
+    /// Make the slot undefined.
+    pub(crate) fn clear(&mut self, slot: usize) {
+        if self.values[slot].is_none() {
+            return;
+        }
+        let prev = Arc::make_mut(&mut self.values)[slot].take();
+        if self.journaling && prev.is_some() {
+            self.journal.push((slot, prev));
+        }
+    }
+
+    /// Replace the state of all slots with the `entries` of a snapshot; the other slots become undefined.
+    pub(crate) fn restore(&mut self, entries: &[(usize, Option<Value>)]) {
+        let mut values = vec![None; self.values.len()];
+        for (slot, value) in entries {
+            values[*slot] = Some(value.clone());
+        }
+        self.values = Arc::new(values);
+        self.journal.clear();
+    }
+
+    /// Copy of the context that shares the slots with this one until either of them is changed.
+    /// The journal is not copied, the fork does not record changes.
+    pub(crate) fn fork(&self) -> Self {
+        Self {
+            values: Arc::clone(&self.values),
+            journal: Vec::new(),
+            journaling: false,
+        }
+    }
+
+    /// Slots that are not undefined, with their values.
+    pub(crate) fn assigned(&self) -> impl Iterator<Item = (usize, &Option<Value>)> {
+        self.values.iter().enumerate().filter_map(|(slot, v)| v.as_ref().map(|v| (slot, v)))
+    }
+
+    /// Start recording changes.
+    pub(crate) fn begin(&mut self) {
+        self.journal.clear();
+        self.journaling = true;
+    }
+
+    /// Stop recording changes and keep them.
+    pub(crate) fn commit(&mut self) {
+        self.journal.clear();
+        self.journaling = false;
+    }
+
+    /// Stop recording changes and undo all changes made since [`Context::begin`].
+    pub(crate) fn rollback(&mut self) {
+        while let Some((slot, prev)) = self.journal.pop() {
+            Arc::make_mut(&mut self.values)[slot] = prev;
+        }
+        self.journaling = false;
+    }
+}
+
+/// Owner of a [`Context`] that processes messages as transactions on the dictionaries.
+pub(crate) trait Transactional {
+    fn context_mut(&mut self) -> &mut Context;
+
+    // Run `f` as a single transaction on the dictionaries: the changes are kept if it succeeds and undone otherwise.
+    fn transaction<T>(&mut self, enabled: bool, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        if !enabled {
+            return f(self);
+        }
+        self.context_mut().begin();
+        let res = f(self);
+        match res {
+            Ok(_) => self.context_mut().commit(),
+            Err(_) => self.context_mut().rollback(),
+        }
+        res
+    }
+}
+
+impl PartialEq for Context {
+    fn eq(&self, other: &Self) -> bool {
+        self.values == other.values
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn context_rollback() {
+        let mut ctx = Context::new(3);
+        ctx.set(0, Some(Value::UInt32(1)));
+
+        ctx.begin();
+        ctx.set(0, Some(Value::UInt32(2)));
+        ctx.set(1, None);
+        ctx.set(0, Some(Value::UInt32(3)));
+        assert_eq!(ctx.get(0), Some(Some(Value::UInt32(3))));
+        ctx.rollback();
+        assert_eq!(ctx.get(0), Some(Some(Value::UInt32(1))));
+        assert_eq!(ctx.get(1), None);
+
+        ctx.begin();
+        ctx.set(2, Some(Value::UInt32(4)));
+        ctx.commit();
+        ctx.rollback();
+        assert_eq!(ctx.get(2), Some(Some(Value::UInt32(4))));
+
+        ctx.begin();
+        ctx.reset();
+        assert_eq!(ctx.get(0), None);
+        assert_eq!(ctx.get(2), None);
+        ctx.rollback();
+        assert_eq!(ctx.get(0), Some(Some(Value::UInt32(1))));
+        assert_eq!(ctx.get(2), Some(Some(Value::UInt32(4))));
+    }
+
+    #[test]
+    fn context_fork() {
+        let mut ctx = Context::new(2);
+        ctx.set(0, Some(Value::UInt32(1)));
+
+        let mut fork = ctx.fork();
+        assert!(Arc::ptr_eq(&ctx.values, &fork.values));
+        assert_eq!(fork, ctx);
+
+        fork.set(0, Some(Value::UInt32(2)));
+        fork.set(1, None);
+        assert!(!Arc::ptr_eq(&ctx.values, &fork.values));
+        assert_eq!(fork.get(0), Some(Some(Value::UInt32(2))));
+        assert_eq!(ctx.get(0), Some(Some(Value::UInt32(1))));
+        assert_eq!(ctx.get(1), None);
+
+        let fork = ctx.fork();
+        ctx.reset();
+        assert_eq!(fork.get(0), Some(Some(Value::UInt32(1))));
+        assert_eq!(ctx.get(0), None);
+    }
 }
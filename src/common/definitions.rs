@@ -1,27 +1,59 @@
-use std::cell::Cell;
 use std::collections::HashMap;
-use std::rc::Rc;
+use std::sync::Arc;
 
-use crate::{Error, Result};
+use crate::{Error, FastErrorCode, Result};
 use crate::base::instruction::Instruction;
 use crate::base::types::{Dictionary, Operator, Presence, Template, TypeRef};
 use crate::base::value::ValueType;
+use crate::common::context::{Context, DictionaryEntry, DictionaryId, DictionaryLayout, DictionarySlot};
 
 /// Stores template definitions and global processing context.
+///
+/// Definitions are immutable once created, so they can be parsed once and shared between
+/// decoders and encoders running on different threads, see [`Decoder::with_definitions`][crate::Decoder::with_definitions]
+/// and [`Encoder::with_definitions`][crate::Encoder::with_definitions].
 pub struct Definitions {
-    pub(crate) templates: Vec<Rc<Template>>,
-    pub(crate) templates_by_id: HashMap<u32, Rc<Template>>,
-    pub(crate) templates_by_name: HashMap<String, Rc<Template>>,
-    pub(crate) template_id_instruction: Rc<Instruction>,
+    pub(crate) templates: Vec<Arc<Template>>,
+    pub(crate) templates_by_id: HashMap<u32, Arc<Template>>,
+    pub(crate) templates_by_name: HashMap<String, Arc<Template>>,
+    pub(crate) template_id_instruction: Arc<Instruction>,
+    pub(crate) layout: DictionaryLayout,
+    pub(crate) fingerprint: u64,
 }
 
 impl Definitions {
-    pub(crate) fn new_from_templates(ts: Vec<Template>) -> Result<Self> {
+    pub(crate) fn new_from_templates(mut ts: Vec<Template>) -> Result<Self> {
+        let mut template_id_instruction = Instruction {
+            id: 0,
+            name: "__template_id__".to_string(),
+            value_type: ValueType::UInt32,
+            presence: Presence::Mandatory,
+            operator: Operator::Copy,
+            initial_value: None,
+            instructions: Vec::new(),
+            dictionary: Dictionary::Global,
+            key: Arc::from("__template_id__"),
+            slot: DictionarySlot::Static(0),
+            type_ref: TypeRef::Any,
+            type_index: 0,
+            has_pmap: false,
+        };
+
+        PresenceMapBuilder::default().finalize(&mut ts)?;
+
+        let mut builder = LayoutBuilder::default();
+        builder.assign_instruction(&mut template_id_instruction, &Dictionary::Global);
+        for (index, t) in ts.iter_mut().enumerate() {
+            t.index = index;
+            builder.assign_template(t);
+        }
+        let layout = builder.finish(ts.len());
+
         let mut templates = Vec::with_capacity(ts.len());
         let mut templates_by_id = HashMap::with_capacity(ts.len());
         let mut templates_by_name = HashMap::with_capacity(ts.len());
         for t in ts {
-            let t = Rc::new(t);
+            let t = Arc::new(t);
             if t.id != 0 {
                 templates_by_id.insert(t.id, t.clone());
             }
@@ -31,28 +63,91 @@ impl Definitions {
             templates.push(t);
         }
 
-        let template_id_instruction = Rc::new(Instruction {
-            id: 0,
-            name: "__template_id__".to_string(),
-            value_type: ValueType::UInt32,
-            presence: Presence::Mandatory,
-            operator: Operator::Copy,
-            initial_value: None,
-            instructions: Vec::new(),
-            dictionary: Dictionary::Global,
-            key: Rc::from("__template_id__"),
-            type_ref: TypeRef::Any,
-            has_pmap: Cell::new(false),
-        });
-
-        let definitions = Self {
+        let fingerprint = fingerprint(&templates, &layout);
+        Ok(Self {
             templates,
             templates_by_id,
             templates_by_name,
-            template_id_instruction,
+            template_id_instruction: Arc::new(template_id_instruction),
+            layout,
+            fingerprint,
+        })
+    }
+
+    /// Hash of the templates and their dictionary entries. The dictionaries of a decoder or encoder can be restored
+    /// from a [`Snapshot`][crate::Snapshot] only if it was taken with definitions that have the same fingerprint.
+    /// It is stable across processes and platforms.
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    // Slots of the context storage that belong to `dictionary`.
+    pub(crate) fn dictionary_slots(&self, dictionary: &DictionaryId) -> Result<Vec<usize>> {
+        let layout = &self.layout;
+        let slots: Vec<usize> = match dictionary {
+            DictionaryId::Global | DictionaryId::User(_) => {
+                let name = match dictionary {
+                    DictionaryId::User(name) => Some(name.as_str()),
+                    _ => None,
+                };
+                layout.static_keys.iter()
+                    .enumerate()
+                    .filter(|(_, (d, _))| d.as_deref() == name)
+                    .map(|(slot, _)| slot)
+                    .collect()
+            }
+            DictionaryId::Template(id) => {
+                let template = self.templates_by_id
+                    .get(id)
+                    .ok_or_else(|| Error::fast(FastErrorCode::D9, format!("Unknown template id: {}", id)))?; // [ERR D9]
+                (0..layout.template_keys.len()).map(|k| layout.template_slot(template.index, k)).collect()
+            }
+            DictionaryId::Type { name, ns } => {
+                let type_ = layout.types.iter()
+                    .position(|(n, t)| n.as_deref() == ns.as_deref() && **t == **name)
+                    .ok_or_else(|| Error::Runtime(format!("Unknown application type: {}", name)))?;
+                (0..layout.type_keys.len()).map(|k| layout.type_slot(type_, k)).collect()
+            }
         };
-        definitions.finalize()?;
-        Ok(definitions)
+        if slots.is_empty() {
+            if let DictionaryId::User(name) = dictionary {
+                return Err(Error::Runtime(format!("Unknown dictionary: {}", name)));
+            }
+        }
+        Ok(slots)
+    }
+
+    // Dictionary and key of the context storage `slot`.
+    pub(crate) fn dictionary_key(&self, slot: usize) -> (DictionaryId, &str) {
+        let layout = &self.layout;
+        if slot < layout.static_keys.len() {
+            let (dictionary, key) = &layout.static_keys[slot];
+            let dictionary = match dictionary {
+                Some(name) => DictionaryId::User(name.to_string()),
+                None => DictionaryId::Global,
+            };
+            return (dictionary, key);
+        }
+        let slot = slot - layout.static_keys.len();
+        let templates_size = layout.templates * layout.template_keys.len();
+        if slot < templates_size {
+            let template = &self.templates[slot / layout.template_keys.len()];
+            return (DictionaryId::Template(template.id), &layout.template_keys[slot % layout.template_keys.len()]);
+        }
+        let slot = slot - templates_size;
+        let (ns, name) = &layout.types[slot / layout.type_keys.len()];
+        let dictionary = DictionaryId::Type { name: name.to_string(), ns: ns.as_deref().map(str::to_string) };
+        (dictionary, &layout.type_keys[slot % layout.type_keys.len()])
+    }
+
+    // Assigned entries of all dictionaries in the `context`.
+    pub(crate) fn dictionary_entries(&self, context: &Context) -> Vec<DictionaryEntry> {
+        context.assigned()
+            .map(|(slot, value)| {
+                let (dictionary, key) = self.dictionary_key(slot);
+                DictionaryEntry { dictionary, key: key.to_string(), value: value.clone() }
+            })
+            .collect()
     }
 
     pub fn new_from_xml(text: &str) -> Result<Self> {
@@ -72,21 +167,31 @@ impl Definitions {
         }
         Self::new_from_templates(templates)
     }
+}
+
+// Calculates which templates and instructions need a presence map.
+#[derive(Default)]
+struct PresenceMapBuilder {
+    // Templates processed so far and whether they require a presence map.
+    require_pmap: HashMap<String, bool>,
+}
 
+impl PresenceMapBuilder {
     // After generating the templates we have to go through all the instructions and set flags
     // for structures that must have a presence map. That can only be done when whole
     // templates structure is generated.
-    fn finalize(&self) -> Result<()> {
-        for tpl in &self.templates {
-            let need_pmap = self.require_presence_map_bit(&tpl.instructions)?;
-            tpl.require_pmap.set(Some(need_pmap));
+    fn finalize(&mut self, templates: &mut [Template]) -> Result<()> {
+        for tpl in templates {
+            let need_pmap = self.require_presence_map_bit(&mut tpl.instructions)?;
+            tpl.require_pmap = need_pmap;
+            self.require_pmap.insert(tpl.name.clone(), need_pmap);
         }
         Ok(())
     }
 
     // Go through sequence of instructions and check if any of them require presence map bit.
     // No early exit! Must iterate over all items because has_presence_map_bit() also initializes has_pmap bit.
-    fn require_presence_map_bit(&self, instructions: &[Instruction]) -> Result<bool> {
+    fn require_presence_map_bit(&self, instructions: &mut [Instruction]) -> Result<bool> {
         let mut has_pmap_bit = false;
         for i in instructions {
             if self.has_presence_map_bit(i)? {
@@ -96,25 +201,25 @@ impl Definitions {
         Ok(has_pmap_bit)
     }
 
-    fn set_has_pmap(&self, instr: &Instruction) -> Result<()> {
-        let instructions: &[Instruction];
+    fn set_has_pmap(&self, instr: &mut Instruction) -> Result<()> {
+        let instructions: &mut [Instruction];
         match instr.value_type {
             ValueType::Group | ValueType::TemplateReference | ValueType::Decimal => {
-                instructions = &instr.instructions;
+                instructions = &mut instr.instructions;
             }
             ValueType::Sequence => {
-                instructions = &instr.instructions[1..];
+                instructions = &mut instr.instructions[1..];
             }
             _ => {
                 return Ok(());
             }
         }
         let need_pmap = self.require_presence_map_bit(instructions)?;
-        instr.has_pmap.set(need_pmap);
+        instr.has_pmap = need_pmap;
         Ok(())
     }
 
-    fn has_presence_map_bit(&self, instr: &Instruction) -> Result<bool> {
+    fn has_presence_map_bit(&self, instr: &mut Instruction) -> Result<bool> {
         // first, initialize internals of the instruction
         self.set_has_pmap(instr)?;
 
@@ -127,22 +232,18 @@ impl Definitions {
             ValueType::Sequence => {
                 // For ::Sequence its length field show if the sequence has a bit in the presence map.
                 return self.has_presence_map_bit(
-                    instr.instructions.get(0)
+                    instr.instructions.get_mut(0)
                         .ok_or_else(|| Error::Static(format!("sequence '{}' has no length field", instr.name)))?
                 );
             }
             ValueType::TemplateReference => {
                 if !instr.name.is_empty() {
                     // Static template ref checks corresponding template it is needs any presence bit.
-                    let template = match self.templates_by_name.get(&instr.name) {
-                        None => return Err(Error::Static(format!("template '{}' not found", instr.name))),
-                        Some(t) => t,
-                    };
-                    return match template.require_pmap.get() {
+                    return match self.require_pmap.get(&instr.name) {
                         None => Err(Error::Static(
                             format!("template '{}' not initialized yet; consider reordering templates", instr.name)
                         )),
-                        Some(b) => Ok(b),
+                        Some(b) => Ok(*b),
                     }
                 } else {
                     // Dynamic template ref doesn't need a presence map bit.
@@ -150,7 +251,7 @@ impl Definitions {
                 }
             }
             ValueType::Decimal => {
-                if instr.has_pmap.get() {
+                if instr.has_pmap {
                     // We already know that this field require a presence bit due to its subcomponents.
                     return Ok(true);
                 }
@@ -171,3 +272,190 @@ impl Definitions {
         }
     }
 }
+
+// Resolves dictionary entries of all instructions to slots of the flat context storage.
+// The effective dictionary of an instruction is known statically, as every template sets its own dictionary;
+// "template" and "type" dictionaries are resolved to key indices only, since the current template and
+// application type are known during processing.
+#[derive(Default)]
+struct LayoutBuilder {
+    layout: DictionaryLayout,
+    static_keys: HashMap<(Option<Arc<str>>, Arc<str>), usize>,
+    template_keys: HashMap<Arc<str>, usize>,
+    type_keys: HashMap<Arc<str>, usize>,
+    types: HashMap<(Option<Arc<str>>, Arc<str>), usize>,
+}
+
+impl LayoutBuilder {
+    fn finish(mut self, templates: usize) -> DictionaryLayout {
+        self.layout.templates = templates;
+        self.layout
+    }
+
+    fn assign_template(&mut self, template: &mut Template) {
+        template.type_index = self.type_index(&template.type_ref);
+        let dictionary = match template.dictionary {
+            Dictionary::Inherit => Dictionary::Global,
+            ref d => d.clone(),
+        };
+        self.assign_instructions(&mut template.instructions, &dictionary);
+    }
+
+    fn assign_instructions(&mut self, instructions: &mut [Instruction], dictionary: &Dictionary) {
+        for instruction in instructions {
+            let dictionary = match instruction.dictionary {
+                Dictionary::Inherit => dictionary.clone(),
+                ref d => d.clone(),
+            };
+            self.assign_instruction(instruction, &dictionary);
+        }
+    }
+
+    fn assign_instruction(&mut self, instruction: &mut Instruction, dictionary: &Dictionary) {
+        instruction.type_index = self.type_index(&instruction.type_ref);
+        // Keep the effective dictionary for `FieldInfo::dictionary`.
+        instruction.dictionary = dictionary.clone();
+        match instruction.value_type {
+            // Groups and sequences don't store values in dictionaries themselves.
+            ValueType::Group | ValueType::Sequence => {
+                self.assign_instructions(&mut instruction.instructions, dictionary);
+            }
+            ValueType::TemplateReference => {}
+            // Decimal subcomponents always use the dictionary of the decimal field.
+            ValueType::Decimal => {
+                instruction.slot = self.slot(dictionary, &instruction.key);
+                for i in &mut instruction.instructions {
+                    self.assign_instruction(i, dictionary);
+                }
+            }
+            _ => {
+                instruction.slot = self.slot(dictionary, &instruction.key);
+            }
+        }
+    }
+
+    fn slot(&mut self, dictionary: &Dictionary, key: &Arc<str>) -> DictionarySlot {
+        match dictionary {
+            Dictionary::Inherit => unreachable!(),
+            Dictionary::Global => {
+                DictionarySlot::Static(intern(&mut self.static_keys, &mut self.layout.static_keys, (None, key.clone())))
+            }
+            Dictionary::UserDefined(name) => {
+                DictionarySlot::Static(intern(&mut self.static_keys, &mut self.layout.static_keys, (Some(name.clone()), key.clone())))
+            }
+            Dictionary::Template => {
+                DictionarySlot::Template(intern(&mut self.template_keys, &mut self.layout.template_keys, key.clone()))
+            }
+            Dictionary::Type => {
+                DictionarySlot::Type(intern(&mut self.type_keys, &mut self.layout.type_keys, key.clone()))
+            }
+        }
+    }
+
+    fn type_index(&mut self, type_ref: &TypeRef) -> usize {
+        if self.layout.types.is_empty() {
+            intern(&mut self.types, &mut self.layout.types, (None, Arc::from("__any__")));
+        }
+        match type_ref {
+            TypeRef::Any => 0,
+            TypeRef::ApplicationType(ns, name) => intern(&mut self.types, &mut self.layout.types, (ns.clone(), name.clone())),
+        }
+    }
+}
+
+// Calculate the fingerprint of the definitions from everything that gives meaning to the context storage slots.
+fn fingerprint(templates: &[Arc<Template>], layout: &DictionaryLayout) -> u64 {
+    fn hash_instructions(h: &mut Fnv64, instructions: &[Instruction]) {
+        h.write_usize(instructions.len());
+        for i in instructions {
+            h.write_u32(i.id);
+            h.write_str(&i.name);
+            h.write_str(i.value_type.type_str());
+            h.write_str(i.operator.name());
+            h.write_u8(i.is_optional() as u8);
+            h.write_str(&i.key);
+            let (region, index) = match i.slot {
+                DictionarySlot::Static(s) => (0, s),
+                DictionarySlot::Template(k) => (1, k),
+                DictionarySlot::Type(k) => (2, k),
+            };
+            h.write_u8(region);
+            h.write_usize(index);
+            h.write_usize(i.type_index);
+            hash_instructions(h, &i.instructions);
+        }
+    }
+
+    let mut h = Fnv64::default();
+    h.write_usize(templates.len());
+    for t in templates {
+        h.write_u32(t.id);
+        h.write_str(&t.name);
+        h.write_usize(t.type_index);
+        hash_instructions(&mut h, &t.instructions);
+    }
+    h.write_usize(layout.static_keys.len());
+    for (dictionary, key) in &layout.static_keys {
+        h.write_str(dictionary.as_deref().unwrap_or(""));
+        h.write_str(key);
+    }
+    for keys in [&layout.template_keys, &layout.type_keys] {
+        h.write_usize(keys.len());
+        for key in keys {
+            h.write_str(key);
+        }
+    }
+    h.write_usize(layout.types.len());
+    for (ns, name) in &layout.types {
+        h.write_str(ns.as_deref().unwrap_or(""));
+        h.write_str(name);
+    }
+    h.finish()
+}
+
+// 64-bit FNV-1a hash. Unlike `std::hash::DefaultHasher` its result doesn't depend on the Rust version,
+// and the integers are hashed as little-endian `u64`, so it doesn't depend on the platform either.
+struct Fnv64(u64);
+
+impl Default for Fnv64 {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Fnv64 {
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn write_u8(&mut self, v: u8) {
+        self.write(&[v]);
+    }
+
+    fn write_u32(&mut self, v: u32) {
+        self.write_usize(v as usize);
+    }
+
+    fn write_usize(&mut self, v: usize) {
+        self.write(&(v as u64).to_le_bytes());
+    }
+
+    fn write_str(&mut self, s: &str) {
+        self.write_usize(s.len());
+        self.write(s.as_bytes());
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+fn intern<K: std::hash::Hash + Eq + Clone>(index: &mut HashMap<K, usize>, names: &mut Vec<K>, key: K) -> usize {
+    *index.entry(key.clone()).or_insert_with(|| {
+        names.push(key);
+        names.len() - 1
+    })
+}
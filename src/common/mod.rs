@@ -1,2 +1,6 @@
+pub(crate) mod block;
 pub(crate) mod definitions;
 pub(crate) mod context;
+pub(crate) mod snapshot;
+pub(crate) mod stats;
+pub(crate) mod trace;
@@ -0,0 +1,218 @@
+use std::mem::{discriminant, Discriminant};
+
+use bytes::BytesMut;
+use hashbrown::HashMap;
+
+use crate::{Decimal, Error, Result, Value};
+use crate::base::instruction::Instruction;
+use crate::base::value::ValueType;
+use crate::common::context::{Context, DictionarySlot};
+use crate::common::definitions::Definitions;
+use crate::decoder::reader::Reader;
+use crate::encoder::writer::Writer;
+
+// Format version of the binary form.
+const VERSION: u64 = 1;
+
+// Tags of the dictionary entry states in the binary form.
+const EMPTY: u8 = 0;
+const UINT32: u8 = 1;
+const INT32: u8 = 2;
+const UINT64: u8 = 3;
+const INT64: u8 = 4;
+const DECIMAL: u8 = 5;
+const ASCII_STRING: u8 = 6;
+const UNICODE_STRING: u8 = 7;
+const BYTES: u8 = 8;
+
+/// Snapshot of all dictionaries of a decoder or an encoder, see [`Decoder::snapshot`][crate::Decoder::snapshot]
+/// and [`Encoder::snapshot`][crate::Encoder::snapshot].
+///
+/// It can be saved with [`Snapshot::to_bytes`] and restored into another decoder or encoder, e.g. after a process
+/// restart in the middle of a session. It can only be restored if the definitions have the same
+/// [`Definitions::fingerprint`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub(crate) fingerprint: u64,
+
+    // Number of slots in the context storage.
+    pub(crate) size: usize,
+
+    // Slots of the context storage that are not undefined, with their values.
+    pub(crate) entries: Vec<(usize, Option<Value>)>,
+}
+
+impl Snapshot {
+    pub(crate) fn new(definitions: &Definitions, context: &Context) -> Self {
+        Self {
+            fingerprint: definitions.fingerprint(),
+            size: definitions.layout.size(),
+            entries: context.assigned().map(|(slot, value)| (slot, value.clone())).collect(),
+        }
+    }
+
+    /// Fingerprint of the definitions the snapshot was taken with.
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    /// Serialize the snapshot to a compact binary form. Only the assigned entries are stored,
+    /// the numbers are encoded with the FAST stop bit encoding.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = BytesMut::new();
+        // Writing to `BytesMut` never fails.
+        _ = self.write(&mut buf);
+        buf.to_vec()
+    }
+
+    /// Deserialize the snapshot from the binary form made by [`Snapshot::to_bytes`].
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let mut rdr = bytes::Bytes::copy_from_slice(data);
+        let snapshot = Self::read(&mut rdr)?;
+        if !rdr.is_empty() {
+            return Err(Error::Runtime(format!("Bytes left in the snapshot: {}", rdr.len())));
+        }
+        Ok(snapshot)
+    }
+
+    // Check that the snapshot can be restored with `definitions`: the definitions are the same and each value
+    // has the type of a field stored in its dictionary entry.
+    pub(crate) fn check(&self, definitions: &Definitions) -> Result<()> {
+        if self.fingerprint != definitions.fingerprint() || self.size != definitions.layout.size() {
+            return Err(Error::Runtime("snapshot was taken with different definitions".to_string()));
+        }
+        let types = value_types(definitions);
+        for (slot, value) in &self.entries {
+            let Some(value) = value else {
+                continue;
+            };
+            let allowed = types.get(&definitions.layout.entry(*slot));
+            if !allowed.is_some_and(|t| t.contains(&discriminant(value))) {
+                let (dictionary, key) = definitions.dictionary_key(*slot);
+                return Err(Error::Runtime(format!(
+                    "snapshot entry {} of {:?} dictionary has value of wrong type: {:?}", key, dictionary, value
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&self, wrt: &mut impl Writer) -> Result<()> {
+        wrt.write_uint(VERSION)?;
+        wrt.write_uint(self.fingerprint)?;
+        wrt.write_uint(self.size as u64)?;
+        wrt.write_uint(self.entries.len() as u64)?;
+        for (slot, value) in &self.entries {
+            wrt.write_uint(*slot as u64)?;
+            match value {
+                None => wrt.write_u8(EMPTY)?,
+                Some(Value::UInt32(v)) => {
+                    wrt.write_u8(UINT32)?;
+                    wrt.write_uint(*v as u64)?;
+                }
+                Some(Value::Int32(v)) => {
+                    wrt.write_u8(INT32)?;
+                    wrt.write_int(*v as i64)?;
+                }
+                Some(Value::UInt64(v)) => {
+                    wrt.write_u8(UINT64)?;
+                    wrt.write_uint(*v)?;
+                }
+                Some(Value::Int64(v)) => {
+                    wrt.write_u8(INT64)?;
+                    wrt.write_int(*v)?;
+                }
+                Some(Value::Decimal(d)) => {
+                    wrt.write_u8(DECIMAL)?;
+                    wrt.write_int(d.exponent as i64)?;
+                    wrt.write_int(d.mantissa)?;
+                }
+                // ASCII strings are stored with length, as they can be empty or have leading zero characters.
+                Some(Value::ASCIIString(s)) => {
+                    wrt.write_u8(ASCII_STRING)?;
+                    wrt.write_unicode_string(s)?;
+                }
+                Some(Value::UnicodeString(s)) => {
+                    wrt.write_u8(UNICODE_STRING)?;
+                    wrt.write_unicode_string(s)?;
+                }
+                Some(Value::Bytes(b)) => {
+                    wrt.write_u8(BYTES)?;
+                    wrt.write_bytes(b)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read(rdr: &mut impl Reader) -> Result<Self> {
+        let version = rdr.read_uint()?;
+        if version != VERSION {
+            return Err(Error::Runtime(format!("unsupported snapshot version: {}", version)));
+        }
+        let fingerprint = rdr.read_uint()?;
+        let size = rdr.read_uint()? as usize;
+        let count = rdr.read_uint()? as usize;
+        if count > size {
+            return Err(Error::Runtime(format!("snapshot has {} entries in {} slots", count, size)));
+        }
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let slot = rdr.read_uint()? as usize;
+            if slot >= size {
+                return Err(Error::Runtime(format!("snapshot slot {} is out of {} slots", slot, size)));
+            }
+            let value = match rdr.read_u8()? {
+                EMPTY => None,
+                UINT32 => Some(Value::UInt32(rdr.read_uint()?.try_into().map_err(out_of_range)?)),
+                INT32 => Some(Value::Int32(rdr.read_int()?.try_into().map_err(out_of_range)?)),
+                UINT64 => Some(Value::UInt64(rdr.read_uint()?)),
+                INT64 => Some(Value::Int64(rdr.read_int()?)),
+                DECIMAL => {
+                    let exponent = rdr.read_int()?.try_into().map_err(out_of_range)?;
+                    let mantissa = rdr.read_int()?;
+                    Some(Value::Decimal(Decimal::new(exponent, mantissa)))
+                }
+                ASCII_STRING => Some(Value::ASCIIString(rdr.read_unicode_string()?)),
+                UNICODE_STRING => Some(Value::UnicodeString(rdr.read_unicode_string()?)),
+                BYTES => Some(Value::Bytes(rdr.read_bytes()?)),
+                tag => return Err(Error::Runtime(format!("invalid snapshot entry tag: {}", tag))),
+            };
+            entries.push((slot, value));
+        }
+        Ok(Self { fingerprint, size, entries })
+    }
+}
+
+// Value types of the fields by their dictionary entries. An entry can be shared by fields of different types.
+fn value_types(definitions: &Definitions) -> HashMap<DictionarySlot, Vec<Discriminant<Value>>> {
+    fn add(types: &mut HashMap<DictionarySlot, Vec<Discriminant<Value>>>, instruction: &Instruction) {
+        match instruction.value_type {
+            ValueType::Group | ValueType::Sequence | ValueType::TemplateReference => {}
+            _ => {
+                if let Ok(value) = instruction.value_type.to_default_value() {
+                    let entry = types.entry(instruction.slot).or_default();
+                    if !entry.contains(&discriminant(&value)) {
+                        entry.push(discriminant(&value));
+                    }
+                }
+            }
+        }
+        for i in &instruction.instructions {
+            add(types, i);
+        }
+    }
+
+    let mut types = HashMap::new();
+    add(&mut types, &definitions.template_id_instruction);
+    for t in &definitions.templates {
+        for i in &t.instructions {
+            add(&mut types, i);
+        }
+    }
+    types
+}
+
+fn out_of_range(e: std::num::TryFromIntError) -> Error {
+    Error::Runtime(format!("snapshot entry value is out of range: {}", e))
+}
@@ -0,0 +1,87 @@
+use hashbrown::HashMap;
+
+use crate::base::instruction::Instruction;
+
+/// Snapshot of the counters collected by the decoder or the encoder when statistics are enabled,
+/// see [`Decoder::set_stats`][crate::Decoder::set_stats] and [`Encoder::set_stats`][crate::Encoder::set_stats].
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Stats {
+    /// Counters by template id of the messages.
+    pub templates: HashMap<u32, TemplateStats>,
+}
+
+/// Counters of the messages with the same template.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TemplateStats {
+    /// Template name.
+    pub name: String,
+    /// Number of processed messages.
+    pub messages: u64,
+    /// Number of bytes of the processed messages in the transfer encoding.
+    pub bytes: u64,
+    /// Counters by field name. The fields of sequences, groups and referenced templates are included; the fields
+    /// with the same name share the counters.
+    pub fields: HashMap<String, FieldStats>,
+}
+
+/// Counters of a field.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FieldStats {
+    /// Field operator: `none`, `constant`, `default`, `copy`, `increment`, `delta` or `tail`.
+    pub operator: &'static str,
+    /// Number of times the field was processed.
+    pub count: u64,
+    /// Number of times the field value was absent.
+    pub nulls: u64,
+    /// Number of times the field value was taken from the dictionary or the initial value,
+    /// i.e. nothing but the presence map bit was transferred.
+    pub hits: u64,
+    /// Number of bytes of the field in the transfer encoding.
+    pub bytes: u64,
+}
+
+impl FieldStats {
+    /// Average size of the field in the transfer encoding, in bytes.
+    pub fn average_size(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        self.bytes as f64 / self.count as f64
+    }
+}
+
+impl Stats {
+    pub(crate) fn start_template(&mut self, id: u32, name: &str) {
+        if !self.templates.contains_key(&id) {
+            self.templates.insert(id, TemplateStats { name: name.to_string(), ..Default::default() });
+        }
+    }
+
+    pub(crate) fn add_message(&mut self, template_id: u32, bytes: usize) {
+        if let Some(t) = self.templates.get_mut(&template_id) {
+            t.messages += 1;
+            t.bytes += bytes as u64;
+        }
+    }
+
+    pub(crate) fn add_field(&mut self, template_id: u32, instruction: &Instruction, bytes: usize, is_null: bool) {
+        let Some(t) = self.templates.get_mut(&template_id) else {
+            return;
+        };
+        let f = match t.fields.get_mut(instruction.name.as_str()) {
+            Some(f) => f,
+            None => t.fields.entry(instruction.name.clone()).or_insert(FieldStats {
+                operator: instruction.operator.name(),
+                ..Default::default()
+            }),
+        };
+        f.count += 1;
+        f.bytes += bytes as u64;
+        if is_null {
+            f.nulls += 1;
+        }
+        if bytes == 0 {
+            f.hits += 1;
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use std::fmt::{Display, Formatter};
+
+use crate::base::instruction::Instruction;
+use crate::base::pmap::PresenceMap;
+use crate::base::types::Operator;
+use crate::Value;
+
+// Number of bytes in one line of the hex dump.
+const BYTES_PER_LINE: usize = 8;
+
+/// Annotated wire trace of the last message processed by the decoder or the encoder when tracing is enabled,
+/// see [`Decoder::set_trace`][crate::Decoder::set_trace] and [`Encoder::set_trace`][crate::Encoder::set_trace].
+///
+/// It is rendered with `Display` as an annotated hex dump, one presence map or field per line.
+/// If the message failed to decode, the bytes read after the last decoded field are shown at the end.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Trace {
+    /// Bytes of the message in the transfer encoding.
+    pub data: Vec<u8>,
+    /// Presence maps and fields in the order they appear in the message.
+    pub entries: Vec<TraceEntry>,
+}
+
+/// One presence map or field of the message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEntry {
+    /// Offset of the first byte in the message.
+    pub offset: usize,
+    /// Number of bytes in the transfer encoding; can be `0` if the value is not transferred.
+    pub len: usize,
+    /// Presence map, template identifier or field.
+    pub kind: TraceKind,
+    /// Field name; empty for presence maps.
+    pub name: String,
+    /// Field operator: `none`, `constant`, `default`, `copy`, `increment`, `delta` or `tail`.
+    pub operator: &'static str,
+    /// The presence map bit consumed by the field, if any.
+    pub pmap_bit: Option<bool>,
+    /// Dictionary value before the field was processed: `None` if the operator doesn't use the dictionary
+    /// or the entry is undefined, `Some(None)` if the entry is empty.
+    pub previous: Option<Option<Value>>,
+    /// The field value.
+    pub value: Option<Value>,
+}
+
+/// Kind of [`TraceEntry`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceKind {
+    /// Presence map with its bits.
+    PresenceMap(Vec<bool>),
+    /// Template identifier.
+    TemplateId,
+    /// Field, including the length of a sequence.
+    Field,
+}
+
+impl Trace {
+    pub(crate) fn clear(&mut self) {
+        self.data.clear();
+        self.entries.clear();
+    }
+
+    // Set offsets of the entries recorded by the encoder, which doesn't know them while the message is encoded.
+    pub(crate) fn set_offsets(&mut self) {
+        let mut offset = 0;
+        for e in self.entries.iter_mut() {
+            e.offset = offset;
+            offset += e.len;
+        }
+    }
+}
+
+impl TraceEntry {
+    pub(crate) fn presence_map(offset: usize, len: usize, pmap: &PresenceMap) -> Self {
+        Self {
+            offset,
+            len,
+            kind: TraceKind::PresenceMap((0..pmap.size()).map(|i| pmap.bit(i)).collect()),
+            name: String::new(),
+            operator: Operator::None.name(),
+            pmap_bit: None,
+            previous: None,
+            value: None,
+        }
+    }
+
+    pub(crate) fn field(
+        offset: usize,
+        len: usize,
+        kind: TraceKind,
+        instruction: &Instruction,
+        pmap_bit: Option<bool>,
+        previous: Option<Option<Value>>,
+        value: Option<Value>,
+    ) -> Self {
+        Self {
+            offset,
+            len,
+            kind,
+            name: instruction.name.clone(),
+            operator: instruction.operator.name(),
+            pmap_bit,
+            previous,
+            value,
+        }
+    }
+}
+
+impl Display for TraceEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            TraceKind::PresenceMap(bits) => {
+                let bits: String = bits.iter().map(|b| if *b { '1' } else { '0' }).collect();
+                return write!(f, "presence map {bits}");
+            }
+            TraceKind::TemplateId => write!(f, "template id")?,
+            TraceKind::Field => write!(f, "{}", self.name)?,
+        }
+        match &self.value {
+            Some(v) => write!(f, " = {v}")?,
+            None => write!(f, " = null")?,
+        }
+        write!(f, " [{}", self.operator)?;
+        if let Some(bit) = self.pmap_bit {
+            write!(f, ", bit {}", bit as u8)?;
+        }
+        match &self.previous {
+            Some(Some(v)) => write!(f, ", prev {v}")?,
+            Some(None) => write!(f, ", prev null")?,
+            None => {}
+        }
+        write!(f, "]")
+    }
+}
+
+impl Display for Trace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut write_line = |offset: usize, bytes: &[u8], note: &str| -> std::fmt::Result {
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{b:02x}")).collect();
+            let line = format!("{:04x}  {:<width$}  {}", offset, hex.join(" "), note, width = BYTES_PER_LINE * 3 - 1);
+            writeln!(f, "{}", line.trim_end())
+        };
+        let mut end = 0;
+        for e in &self.entries {
+            let bytes = self.data.get(e.offset..e.offset + e.len).unwrap_or_default();
+            let note = e.to_string();
+            if bytes.is_empty() {
+                write_line(e.offset, &[], &note)?;
+            }
+            for (i, chunk) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+                write_line(e.offset + i * BYTES_PER_LINE, chunk, if i == 0 { &note } else { "" })?;
+            }
+            end = end.max(e.offset + e.len);
+        }
+        if end < self.data.len() {
+            for (i, chunk) in self.data[end..].chunks(BYTES_PER_LINE).enumerate() {
+                write_line(end + i * BYTES_PER_LINE, chunk, if i == 0 { "(not decoded)" } else { "" })?;
+            }
+        }
+        Ok(())
+    }
+}
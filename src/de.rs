@@ -3,7 +3,9 @@ use std::io::Read;
 use serde::de::Deserialize;
 
 use crate::{Decoder, Error, Reader, Result};
+use crate::decoder::reader::StreamReader;
 use crate::model::ModelFactory;
+use crate::model::template::TemplateData;
 
 pub fn from_vec<'de, T>(decoder: &mut Decoder, bytes: Vec<u8>) -> Result<T>
 where
@@ -14,8 +16,7 @@ where
     decoder.decode_vec(bytes, &mut msg)?;
 
     // Deserialize from internal data model into user data type
-    let data = msg.data.unwrap();
-    T::deserialize(data)
+    T::deserialize(into_data(msg)?)
 }
 
 #[allow(unused)]
@@ -28,8 +29,7 @@ where
     decoder.decode_bytes(bytes, &mut msg)?;
 
     // Deserialize from internal data model into user data type
-    let data = msg.data.unwrap();
-    T::deserialize(data)
+    T::deserialize(into_data(msg)?)
 }
 
 #[allow(unused)]
@@ -42,8 +42,7 @@ where
     decoder.decode_reader(rdr, &mut msg)?;
 
     // Deserialize from internal data model into user data type
-    let data = msg.data.unwrap();
-    T::deserialize(data)
+    T::deserialize(into_data(msg)?)
 }
 
 #[allow(unused)]
@@ -56,8 +55,31 @@ where
     decoder.decode_stream(rdr, &mut msg)?;
 
     // Deserialize from internal data model into user data type
-    let data = msg.data.unwrap();
-    T::deserialize(data)
+    T::deserialize(into_data(msg)?)
+}
+
+/// Iterate over messages from the stream deserializing each one into `T`.
+/// The iteration ends when the stream reaches the end of data between messages; any other error is returned and
+/// ends the iteration. Messages of templates skipped with [`Decoder::skip_template`] are not yielded.
+pub fn from_stream_iter<'a, 'de, T>(decoder: &'a mut Decoder, rdr: &'a mut dyn Read) -> impl Iterator<Item = Result<T>> + 'a
+where
+    T: Deserialize<'de> + 'a,
+{
+    decoder
+        .messages::<ModelFactory, _>(StreamReader::new(rdr))
+        .filter_map(|msg| match msg {
+            // Skipped templates produce no data, so there is nothing to deserialize.
+            Ok(msg) if msg.skipped.is_some() => None,
+            msg => Some(msg.and_then(into_data).and_then(T::deserialize)),
+        })
+}
+
+fn into_data(msg: ModelFactory) -> Result<TemplateData> {
+    match (msg.data, msg.skipped) {
+        (Some(data), _) => Ok(data),
+        (None, Some(name)) => Err(Error::Runtime(format!("template {name} was skipped, nothing to deserialize"))),
+        (None, None) => Err(Error::Runtime("no message was decoded".to_string())),
+    }
 }
 
 
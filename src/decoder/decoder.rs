@@ -1,171 +1,611 @@
 use std::io::Read;
-use std::rc::Rc;
+use std::sync::Arc;
 
-use crate::{Error, Result};
+use hashbrown::{HashMap, HashSet};
+
+use crate::{Error, FastErrorCode, Result};
 use crate::base::instruction::Instruction;
-use crate::base::message::MessageFactory;
+use crate::base::field::FieldInfo;
+use crate::base::message::{Control, MessageFactoryRef, TryMessageFactory};
 use crate::base::pmap::PresenceMap;
-use crate::base::types::{Dictionary, Template, TypeRef};
-use crate::base::value::{Value, ValueType};
-use crate::common::context::{Context, DictionaryType};
+use crate::base::types::{Operator, Template};
+use crate::base::value::{Value, ValueRef, ValueType};
+use crate::common::context::{Context, DictionaryEntry, DictionaryId, DictionarySlot, Transactional};
 use crate::common::definitions::Definitions;
-use crate::decoder::reader::{Reader, StreamReader};
+use crate::common::snapshot::Snapshot;
+use crate::common::stats::Stats;
+use crate::common::trace::{Trace, TraceEntry, TraceKind};
+use crate::common::block::BlockLength;
+use crate::decoder::options::{DecoderLimits, DecoderOptions, Strictness};
+use crate::decoder::packet::PacketLayout;
+use crate::decoder::reader::{BlockReader, BorrowingReader, LimitReader, OwnedReader, Reader, SliceReader, StreamReader, StrictReader, TraceReader};
 use crate::utils::stacked::Stacked;
 
 /// Decoder for FAST protocol messages.
 pub struct Decoder {
-    pub(crate) definitions: Definitions,
+    pub(crate) definitions: Arc<Definitions>,
     pub(crate) context: Context,
+    pub(crate) transactional: bool,
+    pub(crate) packet_layout: PacketLayout,
+    pub(crate) options: DecoderOptions,
+    pub(crate) skipped_templates: HashSet<u32>,
+    pub(crate) projections: HashMap<u32, HashSet<u32>>,
+    pub(crate) reset_templates: HashSet<u32>,
+    pub(crate) stats: Option<Stats>,
+    pub(crate) trace: Option<Trace>,
 }
 
 impl Decoder {
     #[allow(unused)]
     pub(crate) fn new_from_templates(ts: Vec<Template>) -> Result<Self> {
-        Ok(Decoder {
-            definitions: Definitions::new_from_templates(ts)?,
-            context: Context::new(),
-        })
+        Ok(Self::with_definitions(Arc::new(Definitions::new_from_templates(ts)?)))
     }
 
     pub fn new_from_xml(text: &str) -> Result<Self> {
-        Ok(Decoder {
-            definitions: Definitions::new_from_xml(text)?,
-            context: Context::new(),
-        })
+        Ok(Self::with_definitions(Arc::new(Definitions::new_from_xml(text)?)))
+    }
+
+    /// Create decoder that uses already parsed definitions. The definitions can be shared with other
+    /// decoders and encoders; each of them keeps its own dictionaries.
+    pub fn with_definitions(definitions: Arc<Definitions>) -> Self {
+        Decoder {
+            context: Context::new(definitions.layout.size()),
+            definitions,
+            transactional: true,
+            packet_layout: PacketLayout::default(),
+            options: DecoderOptions::default(),
+            skipped_templates: HashSet::new(),
+            projections: HashMap::new(),
+            reset_templates: HashSet::new(),
+            stats: None,
+            trace: None,
+        }
+    }
+
+    /// Definitions used by the decoder.
+    pub fn definitions(&self) -> &Arc<Definitions> {
+        &self.definitions
     }
 
     pub fn reset(&mut self) {
         self.context.reset()
     }
 
+    /// Reset a single dictionary, e.g. the "template" dictionary of one template. It is an error if the template,
+    /// the application type or the user defined dictionary is unknown.
+    pub fn reset_dictionary(&mut self, dictionary: &DictionaryId) -> Result<()> {
+        for slot in self.definitions.dictionary_slots(dictionary)? {
+            self.context.clear(slot);
+        }
+        Ok(())
+    }
+
+    /// Assigned entries of all dictionaries, for inspection of the decoder state. The entries that are undefined
+    /// are not listed.
+    pub fn dictionary_entries(&self) -> Vec<DictionaryEntry> {
+        self.definitions.dictionary_entries(&self.context)
+    }
+
+    /// Take a snapshot of all dictionaries, e.g. to save it with [`Snapshot::to_bytes`][crate::Snapshot::to_bytes].
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::new(&self.definitions, &self.context)
+    }
+
+    /// Replace all dictionaries with the `snapshot`. It is an error if the snapshot was taken
+    /// with different definitions or an entry has a value of another type than its fields; the dictionaries
+    /// are left unchanged then.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<()> {
+        snapshot.check(&self.definitions)?;
+        self.context.restore(&snapshot.entries);
+        Ok(())
+    }
+
+    /// Create a copy of the decoder for speculative decoding, e.g. to try decoding a message after a gap
+    /// without changing the state of this decoder. The copy shares the definitions and the dictionaries
+    /// with this decoder; the dictionaries are copied on the first change, so forking is cheap.
+    /// The copy has the same settings; statistics and trace start empty if they are enabled.
+    ///
+    /// To accept the result of the speculative decoding, replace this decoder with the copy.
+    pub fn fork(&self) -> Self {
+        Decoder {
+            definitions: Arc::clone(&self.definitions),
+            context: self.context.fork(),
+            transactional: self.transactional,
+            packet_layout: self.packet_layout.clone(),
+            options: self.options.clone(),
+            skipped_templates: self.skipped_templates.clone(),
+            projections: self.projections.clone(),
+            reset_templates: self.reset_templates.clone(),
+            stats: self.stats.as_ref().map(|_| Stats::default()),
+            trace: self.trace.as_ref().map(|_| Trace::default()),
+        }
+    }
+
+    /// Enable or disable transactional dictionary updates (enabled by default).
+    /// When enabled, dictionary changes made while decoding a message are discarded if the message fails to decode,
+    /// so the following messages are decoded against the same state as if the failed message was never received.
+    /// Disabling it saves journaling of the changed entries on latency-sensitive paths.
+    pub fn set_transactional(&mut self, transactional: bool) {
+        self.transactional = transactional;
+    }
+
+    /// Set layout of packets decoded with [`Decoder::decode_packet`]. By default, packets have no preamble.
+    pub fn set_packet_layout(&mut self, layout: PacketLayout) {
+        self.packet_layout = layout;
+    }
+
+    /// Set decoding options. By default, the decoder is lenient.
+    pub fn set_options(&mut self, options: DecoderOptions) {
+        self.options = options;
+    }
+
+    /// Decoding options.
+    pub fn options(&self) -> &DecoderOptions {
+        &self.options
+    }
+
+    /// Skip messages with template `id`. Such messages are still decoded to keep the dictionaries up to date,
+    /// but their values are not passed to the message factory. The factory receives only
+    /// [`TryMessageFactory::skip_template`][crate::TryMessageFactory::skip_template] notification instead.
+    pub fn skip_template(&mut self, id: u32) -> Result<()> {
+        if !self.definitions.templates_by_id.contains_key(&id) {
+            return Err(Error::fast(FastErrorCode::D9, format!("Unknown template id: {}", id))); // [ERR D9]
+        }
+        self.skipped_templates.insert(id);
+        Ok(())
+    }
+
+    /// Same as [`Decoder::skip_template`] but the template is specified by name.
+    pub fn skip_template_by_name(&mut self, name: &str) -> Result<()> {
+        let id = self.definitions.templates_by_name
+            .get(name)
+            .ok_or_else(|| Error::fast(FastErrorCode::D9, format!("Unknown template: {}", name)))? // [ERR D9]
+            .id;
+        self.skipped_templates.insert(id);
+        Ok(())
+    }
+
+    /// Stop skipping templates set by [`Decoder::skip_template`] and [`Decoder::skip_template_by_name`].
+    pub fn clear_skipped_templates(&mut self) {
+        self.skipped_templates.clear();
+    }
+
+    /// Pass to the message factory only the fields with ids in `field_ids` of the messages with template `template_id`,
+    /// including the fields of its sequences, groups and referenced templates. The other fields are read without
+    /// building their values where the dictionaries allow it. Sequences and groups are reported as usual.
+    pub fn set_projection(&mut self, template_id: u32, field_ids: &[u32]) -> Result<()> {
+        if !self.definitions.templates_by_id.contains_key(&template_id) {
+            return Err(Error::fast(FastErrorCode::D9, format!("Unknown template id: {}", template_id))); // [ERR D9]
+        }
+        self.projections.insert(template_id, field_ids.iter().copied().collect());
+        Ok(())
+    }
+
+    /// Remove all projections set by [`Decoder::set_projection`].
+    pub fn clear_projections(&mut self) {
+        self.projections.clear();
+    }
+
+    /// Reset all dictionaries after a message with one of the template `ids` is decoded, in addition to the templates
+    /// with `reset="yes"` attribute. Replaces the previously set ids; the empty list turns it off.
+    pub fn set_reset_templates(&mut self, ids: &[u32]) -> Result<()> {
+        if let Some(id) = ids.iter().find(|id| !self.definitions.templates_by_id.contains_key(id)) {
+            return Err(Error::fast(FastErrorCode::D9, format!("Unknown template id: {}", id))); // [ERR D9]
+        }
+        self.reset_templates = ids.iter().copied().collect();
+        Ok(())
+    }
+
+    /// Enable or disable collecting of statistics (disabled by default): the number of messages and bytes
+    /// per template, and how each field is encoded. Enabling resets the counters.
+    /// The messages that fail to decode are counted as well.
+    pub fn set_stats(&mut self, enabled: bool) {
+        self.stats = if enabled { Some(Stats::default()) } else { None };
+    }
+
+    /// Snapshot of the statistics if they are enabled with [`Decoder::set_stats`].
+    pub fn stats(&self) -> Option<Stats> {
+        self.stats.clone()
+    }
+
+    /// Enable or disable tracing (disabled by default). When enabled, the decoder records the bytes, presence maps
+    /// and fields of each message, see [`Trace`][crate::Trace]. Tracing slows decoding down, it is meant for debugging.
+    pub fn set_trace(&mut self, enabled: bool) {
+        self.trace = if enabled { Some(Trace::default()) } else { None };
+    }
+
+    /// Trace of the last message, including a message that failed to decode, if tracing is enabled with
+    /// [`Decoder::set_trace`].
+    pub fn trace(&self) -> Option<&Trace> {
+        self.trace.as_ref()
+    }
+
     /// Decode single message from bytes vector.
     /// The `bytes` vector must be the whole message. It is an error if any bytes left after the message is decoded.
-    pub fn decode_vec(&mut self, bytes: Vec<u8>, msg: &mut impl MessageFactory) -> Result<()> {
+    pub fn decode_vec(&mut self, bytes: Vec<u8>, msg: &mut impl TryMessageFactory<'static>) -> Result<()> {
         let mut raw = bytes::Bytes::from(bytes);
-        self.decode_reader(&mut raw, msg)?;
-        if !raw.is_empty() {
-            return Err(Error::Runtime(format!("Bytes left in the buffer after decoding: {}", raw.len())));
-        }
-        Ok(())
+        self.transaction(self.transactional, |d| {
+            d.decode_message(&mut OwnedReader::with_remaining(&mut raw, bytes::Bytes::len), msg)?;
+            if !raw.is_empty() {
+                return Err(Error::Runtime(format!("Bytes left in the buffer after decoding: {}", raw.len())));
+            }
+            Ok(())
+        })
     }
 
     /// Decode single message from `bytes::Bytes`.
-    pub fn decode_bytes(&mut self, bytes: &mut bytes::Bytes, msg: &mut impl MessageFactory) -> Result<()> {
-        self.decode_reader(bytes, msg)
+    pub fn decode_bytes(&mut self, bytes: &mut bytes::Bytes, msg: &mut impl TryMessageFactory<'static>) -> Result<()> {
+        let mut rdr = OwnedReader::with_remaining(bytes, bytes::Bytes::len);
+        self.transaction(self.transactional, |d| d.decode_message(&mut rdr, msg))
     }
 
     /// Decode single message from object that implements [`std::io::Read`][std::io::Read] trait.
-    pub fn decode_stream(&mut self, rdr: &mut dyn Read, msg: &mut impl MessageFactory) -> Result<()> {
+    pub fn decode_stream(&mut self, rdr: &mut dyn Read, msg: &mut impl TryMessageFactory<'static>) -> Result<()> {
         let mut rdr = StreamReader::new(rdr);
         self.decode_reader(&mut rdr, msg)
     }
 
     /// Decode single message from object that implements [`fastlib::Reader`][crate::decoder::reader::Reader] trait.
-    pub fn decode_reader(&mut self, rdr: &mut impl Reader, msg: &mut impl MessageFactory) -> Result<()> {
-        DecoderContext::new(self, rdr, msg).decode_template()
+    /// The reader's own implementations of the [`Reader`] methods are used, unless statistics, tracing, limits or
+    /// strict decoding are enabled; then all bytes are read with [`Reader::read_u8`].
+    /// Errors have no byte offset in the message unless the bytes are counted that way.
+    pub fn decode_reader(&mut self, rdr: &mut impl Reader, msg: &mut impl TryMessageFactory<'static>) -> Result<()> {
+        let mut rdr = OwnedReader::new(rdr);
+        self.transaction(self.transactional, |d| d.decode_message(&mut rdr, msg))
+    }
+
+    /// Iterate over messages from the reader; each message is decoded into a new message factory `F`.
+    /// The iteration ends when the reader reaches the end of data between messages; any other error,
+    /// including [`Error::UnexpectedEof`][crate::Error::UnexpectedEof], is returned and ends the iteration.
+    ///
+    /// ```rust,ignore
+    /// for msg in decoder.messages::<TextMessageFactory, _>(&mut bytes) {
+    ///     println!("{}", msg?.text);
+    /// }
+    /// ```
+    pub fn messages<'a, F, R>(&'a mut self, mut rdr: R) -> impl Iterator<Item = Result<F>> + 'a
+    where
+        F: TryMessageFactory<'static> + Default + 'a,
+        R: Reader + 'a,
+    {
+        let mut done = false;
+        std::iter::from_fn(move || {
+            if done {
+                return None;
+            }
+            let mut msg = F::default();
+            match self.decode_reader(&mut rdr, &mut msg) {
+                Ok(()) => Some(Ok(msg)),
+                Err(Error::Eof) => {
+                    done = true;
+                    None
+                }
+                Err(e) => {
+                    done = true;
+                    Some(Err(e))
+                }
+            }
+        })
+    }
+
+    /// Same as [`Decoder::messages`] but also returns the byte offset in `bytes` where each message starts.
+    pub fn messages_with_offsets<'a, F>(&'a mut self, bytes: bytes::Bytes) -> impl Iterator<Item = Result<(usize, F)>> + 'a
+    where
+        F: TryMessageFactory<'static> + Default + 'a,
+    {
+        let total = bytes.len();
+        let mut rdr = bytes;
+        let mut done = false;
+        std::iter::from_fn(move || {
+            if done {
+                return None;
+            }
+            let offset = total - rdr.len();
+            let mut msg = F::default();
+            match self.decode_reader(&mut rdr, &mut msg).map_err(|e| e.offset_by(offset)) {
+                Ok(()) => Some(Ok((offset, msg))),
+                Err(Error::Eof) => {
+                    done = true;
+                    None
+                }
+                Err(e) => {
+                    done = true;
+                    Some(Err(e))
+                }
+            }
+        })
+    }
+
+    /// Decode single message from a block-framed stream: the message is preceded by its length encoded as `length`.
+    /// It is an error if the message doesn't occupy exactly the declared number of bytes.
+    ///
+    /// Returns `false` if the message template is unknown; such block is skipped without decoding.
+    /// After an error the rest of the block is skipped as well, so the next call starts at the next block.
+    /// Returns [`Error::Eof`][crate::Error::Eof] if the stream ends before the block.
+    pub fn decode_block(&mut self, rdr: &mut impl Reader, length: BlockLength, msg: &mut impl TryMessageFactory<'static>) -> Result<bool> {
+        let mut rdr = BlockReader::new(rdr, length)?;
+        let res = self.transaction(self.transactional, |d| {
+            let decoded = {
+                let mut block = OwnedReader::with_remaining(&mut rdr, BlockReader::remaining);
+                let mut ctx = DecoderContext::new(d, &mut block, msg);
+                ctx.skip_unknown_template = true;
+                ctx.decode_template()?
+            };
+            if !decoded {
+                return Ok(false);
+            }
+            if rdr.remaining() != 0 {
+                return Err(Error::Dynamic(format!("Bytes left in the block after decoding: {}", rdr.remaining())));
+            }
+            Ok(true)
+        });
+        // Skipping the rest of the block is a best effort if the message failed; its error is reported first.
+        let skipped = rdr.skip();
+        let decoded = res?;
+        skipped?;
+        Ok(decoded)
+    }
+
+    /// Decode all messages from a datagram packet with the layout set by [`Decoder::set_packet_layout`].
+    /// The fields parsed from the packet preamble are passed to
+    /// [`TryMessageFactory::set_preamble`][crate::TryMessageFactory::set_preamble] before each message;
+    /// if it returns [`Control::Skip`][crate::Control::Skip], the message is decoded without passing it to the factory.
+    ///
+    /// Returns the number of decoded messages.
+    pub fn decode_packet<'d>(&mut self, packet: &'d [u8], msg: &mut impl TryMessageFactory<'d>) -> Result<usize> {
+        let preamble_len = self.packet_layout.preamble_len;
+        if packet.len() < preamble_len {
+            return Err(Error::Dynamic(format!("packet is shorter than preamble: {} < {}", packet.len(), preamble_len)));
+        }
+        let preamble = self.packet_layout.parse(&packet[..preamble_len])?;
+        let mut pos = preamble_len;
+        let mut count = 0;
+        while pos < packet.len() {
+            pos += match msg.set_preamble(&preamble)? {
+                Control::Continue => self.decode_slice(&packet[pos..], msg),
+                Control::Skip => self.decode_slice(&packet[pos..], &mut NullFactory),
+            }.map_err(|e| e.offset_by(pos))?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Decode single message from the beginning of `buf` without copying unicode strings and byte vectors.
+    /// The values passed to [`TryMessageFactory::set_value`][crate::TryMessageFactory::set_value] borrow
+    /// their data from `buf` where possible. `bytes::Bytes` can be decoded this way as it dereferences to `[u8]`.
+    ///
+    /// Returns the number of bytes consumed by the message, so the next message (if any) starts at that offset.
+    pub fn decode_slice<'d>(&mut self, buf: &'d [u8], msg: &mut impl TryMessageFactory<'d>) -> Result<usize> {
+        self.transaction(self.transactional, |d| d.decode_slice_message(buf, msg))
+    }
+
+    // Decode single message from the beginning of `buf`, returns the number of bytes consumed.
+    pub(crate) fn decode_slice_message<'d>(&mut self, buf: &'d [u8], msg: &mut impl TryMessageFactory<'d>) -> Result<usize> {
+        let mut rdr = SliceReader::new(buf);
+        self.decode_message(&mut rdr, msg)?;
+        Ok(rdr.position())
+    }
+
+    fn decode_message<'d>(&mut self, rdr: &mut impl BorrowingReader<'d>, msg: &mut impl TryMessageFactory<'d>) -> Result<()> {
+        DecoderContext::new(self, rdr, msg).decode_template()?;
+        Ok(())
+    }
+}
+
+impl Transactional for Decoder {
+    fn context_mut(&mut self) -> &mut Context {
+        &mut self.context
     }
 }
 
 /// Processing context of the decoder. It represents context state during one message decoding.
 /// Created when it starts decoding a new message and destroyed after decoding of a message.
-pub(crate) struct DecoderContext<'a> {
-    pub(crate) definitions: &'a mut Definitions,
+pub(crate) struct DecoderContext<'a, 'd> {
+    pub(crate) definitions: &'a Definitions,
     pub(crate) context: &'a mut Context,
-    pub(crate) rdr: Box<&'a mut dyn Reader>,
-    pub(crate) msg: Box<&'a mut dyn MessageFactory>,
+    pub(crate) rdr: Box<dyn BorrowingReader<'d> + 'a>,
+    pub(crate) msg: Box<&'a mut dyn TryMessageFactory<'d>>,
+
+    // Templates which messages are decoded without passing values to `msg`.
+    pub(crate) skipped_templates: &'a HashSet<u32>,
+
+    // Set while a message of a skipped template is decoded, or after `msg` returned `Control::Skip`;
+    // the callbacks go to `null_factory` instead of `msg`.
+    pub(crate) muted: bool,
+    null_factory: NullFactory,
 
-    // The current template id.
+    // Field projections by template id and the one of the current message.
+    pub(crate) projections: &'a HashMap<u32, HashSet<u32>>,
+    pub(crate) projection: Option<&'a HashSet<u32>>,
+
+    // Templates which messages reset all dictionaries, in addition to the ones with `reset="yes"` attribute.
+    pub(crate) reset_templates: &'a HashSet<u32>,
+
+    // Statistics to update if enabled, and the template id of the current message.
+    pub(crate) stats: Option<&'a mut Stats>,
+    pub(crate) stats_template_id: u32,
+
+    // Trace entries to add to if tracing is enabled.
+    pub(crate) trace: Option<&'a mut Vec<TraceEntry>>,
+
+    // The current template (as index in definitions).
     // It is updated when a template identifier is encountered in the stream. A static template reference can also change
     // the current template as described in the Template Reference Instruction section.
-    pub(crate) template_id: Stacked<u32>,
-
-    // The dictionary set and initial value are described in the Operators section.
-    pub(crate) dictionary: Stacked<Dictionary>,
+    pub(crate) template_index: Stacked<usize>,
 
-    // The current application type is initially the special type `any`. The current application type changes when the processor
-    // encounters an element containing a `typeRef` element. The new type is applicable to the instructions contained within
-    // the element. The `typeRef` can appear in the <template>, <group> and <sequence> elements.
-    pub(crate) type_ref: Stacked<TypeRef>,
+    // The current application type (as index in dictionary layout) is initially the special type `any`. The current
+    // application type changes when the processor encounters an element containing a `typeRef` element. The new type is
+    // applicable to the instructions contained within the element. The `typeRef` can appear in the <template>, <group>
+    // and <sequence> elements.
+    pub(crate) type_index: Stacked<usize>,
 
     // The presence map of the current segment.
     pub(crate) presence_map: Stacked<PresenceMap>,
+
+    // Don't fail on a message with unknown template id, leave it undecoded instead.
+    pub(crate) skip_unknown_template: bool,
+
+    // Reject reportable errors in the transfer encoding.
+    pub(crate) strict: bool,
+
+    pub(crate) limits: DecoderLimits,
+
+    // Nesting depth of template references.
+    pub(crate) template_ref_depth: usize,
 }
 
-impl<'a> DecoderContext<'a> {
+impl<'a, 'd> DecoderContext<'a, 'd> {
     pub(crate) fn new(d: &'a mut Decoder,
-                      r: &'a mut impl Reader,
-                      m: &'a mut impl MessageFactory,
+                      r: &'a mut impl BorrowingReader<'d>,
+                      m: &'a mut impl TryMessageFactory<'d>,
     ) -> Self {
+        let strict = d.options.strictness == Strictness::Strict;
+        let limits = d.options.limits;
+        if d.stats.is_some() || d.trace.is_some() || limits.limits_input() {
+            r.count_position();
+        }
+        let (rdr, trace) = match d.trace.as_mut() {
+            Some(t) => {
+                t.clear();
+                (wrap_reader(TraceReader::new(r, &mut t.data), strict, limits), Some(&mut t.entries))
+            }
+            None => (wrap_reader(r, strict, limits), None),
+        };
         Self {
-            definitions: &mut d.definitions,
+            definitions: &d.definitions,
             context: &mut d.context,
-            rdr: Box::new(r),
+            rdr,
             msg: Box::new(m),
-            template_id: Stacked::new_empty(),
-            dictionary: Stacked::new(Dictionary::Global),
-            type_ref: Stacked::new(TypeRef::Any),
+            skipped_templates: &d.skipped_templates,
+            muted: false,
+            null_factory: NullFactory,
+            projections: &d.projections,
+            projection: None,
+            reset_templates: &d.reset_templates,
+            stats: d.stats.as_mut(),
+            stats_template_id: 0,
+            trace,
+            template_index: Stacked::new_empty(),
+            type_index: Stacked::new(0),
             presence_map: Stacked::new_empty(),
+            skip_unknown_template: false,
+            strict,
+            limits,
+            template_ref_depth: 0,
         }
     }
 
+    // Number of bytes read so far; it is known when statistics, tracing or limits are enabled.
+    fn position(&self) -> usize {
+        self.rdr.position().unwrap_or(0)
+    }
+
     // Read template id from the stream.
     fn read_template_id(&mut self) -> Result<u32> {
         let instruction = self.definitions.template_id_instruction.clone();
-        match instruction.extract(self)? {
-            Some(Value::UInt32(id)) => Ok(id),
+        let value = if self.trace.is_some() {
+            self.extract_traced(&instruction, TraceKind::TemplateId)?
+        } else {
+            instruction.extract(self)?
+        };
+        match value {
+            Some(ValueRef::UInt32(id)) => Ok(id),
             Some(_) => Err(Error::Runtime("Wrong template id type in context storage".to_string())),
             None => Err(Error::Runtime("No template id in context storage".to_string())),
         }
     }
 
     // Decode template id from the stream and change the current processing context accordingly.
-    fn decode_template_id(&mut self) -> Result<()> {
+    fn decode_template_id(&mut self) -> Result<Arc<Template>> {
         let template_id = self.read_template_id()?;
-        self.template_id.push(template_id);
-        Ok(())
+        self.switch_template(template_id)
+    }
+
+    // Find the template by id and make it the current one.
+    fn switch_template(&mut self, template_id: u32) -> Result<Arc<Template>> {
+        let template = self.definitions.templates_by_id
+            .get(&template_id)
+            .ok_or_else(|| Error::fast(FastErrorCode::D9, format!("Unknown template id: {}", template_id)))? // [ERR D9]
+            .clone();
+        self.template_index.push(template.index);
+        Ok(template)
     }
 
     // Stop processing the current template id, restore the previous value in the processing context.
     fn drop_template_id(&mut self) {
-        self.template_id.pop();
+        self.template_index.pop();
     }
 
     // Decode presence map from the stream and change the current processing context accordingly.
     fn decode_presence_map(&mut self) -> Result<()> {
-        let (bitmap, size) = self.rdr.read_presence_map()?;
-        let presence_map = PresenceMap::new(bitmap, size);
+        let start = self.position();
+        let presence_map = self.rdr.read_presence_map()?;
+        let len = self.position() - start;
+        if let Some(trace) = self.trace.as_deref_mut() {
+            trace.push(TraceEntry::presence_map(start, len, &presence_map));
+        }
         self.presence_map.push(presence_map);
         Ok(())
     }
 
     // Restore the previous value for presence map in the processing context.
-    fn drop_presence_map(&mut self) {
-        _ = self.presence_map.pop();
+    fn drop_presence_map(&mut self) -> Result<()> {
+        let presence_map = self.presence_map.pop();
+        if self.strict && presence_map.is_some_and(|p| p.has_unread_bits_set()) {
+            return Err(Error::fast(FastErrorCode::R8, "presence map has more bits than required")); // [ERR R8]
+        }
+        Ok(())
     }
 
     // Decode a template from the stream.
-    pub(crate) fn decode_template(&mut self) -> Result<()> {
+    // Returns `false` if the template id is unknown and `skip_unknown_template` is set; the rest of the message is not read.
+    pub(crate) fn decode_template(&mut self) -> Result<bool> {
+        let start = self.position();
         self.decode_presence_map()?;
-        self.decode_template_id()?;
-        let template = self.definitions.templates_by_id
-            .get(self.template_id.peek().unwrap())
-            .ok_or_else(|| Error::Dynamic(format!("Unknown template id: {}", self.template_id.peek().unwrap())))? // [ErrD09]
-            .clone(); //
-        self.msg.start_template(template.id, &template.name);
+        let template_id = self.read_template_id()
+            .map_err(|e| self.locate(e, ""))?;
+        if self.skip_unknown_template && !self.definitions.templates_by_id.contains_key(&template_id) {
+            _ = self.presence_map.pop();
+            return Ok(false);
+        }
+        let template = self.switch_template(template_id)
+            .map_err(|e| self.locate(e, ""))?;
+        // The fields of a skipped template are decoded anyway as they can update the dictionaries.
+        self.muted = self.skipped_templates.contains(&template.id);
+        self.projection = self.projections.get(&template.id);
+        if let Some(stats) = self.stats.as_deref_mut() {
+            stats.start_template(template.id, &template.name);
+            self.stats_template_id = template.id;
+        }
+        let control = if self.muted {
+            self.msg.skip_template(template.id, &template.name)
+        } else {
+            self.msg.start_template(template.id, &template.name)
+        };
+        self.control(control)
+            .map_err(|e| e.at_template(template.id, &template.name, self.rdr.position()))?;
 
         // Update some context variables
-        let has_dictionary = self.switch_dictionary(&template.dictionary);
-        let has_type_ref = self.switch_type_ref(&template.type_ref);
+        let has_type_ref = self.switch_type_ref(template.type_index);
 
-        self.decode_instructions(&template.instructions)?;
+        self.decode_instructions(&template.instructions)
+            .map_err(|e| e.at_template(template.id, &template.name, self.rdr.position()))?;
 
-        if has_dictionary { self.restore_dictionary() }
         if has_type_ref { self.restore_type_ref() }
 
-        self.msg.stop_template();
+        self.notify(|m| m.stop_template())
+            .map_err(|e| e.at_template(template.id, &template.name, self.rdr.position()))?;
+        self.muted = false;
         self.drop_template_id();
-        self.drop_presence_map();
-        Ok(())
+        self.drop_presence_map()?;
+        let size = self.position() - start;
+        if let Some(stats) = self.stats.as_deref_mut() {
+            stats.add_message(template.id, size);
+        }
+        if template.reset || self.reset_templates.contains(&template.id) {
+            self.context.reset();
+        }
+        Ok(true)
     }
 
     fn decode_instructions(&mut self, instructions: &[Instruction]) -> Result<()> {
@@ -191,47 +631,65 @@ impl<'a> DecoderContext<'a> {
     fn decode_segment(&mut self, instructions: &[Instruction]) -> Result<()> {
         self.decode_presence_map()?;
         self.decode_instructions(instructions)?;
-        self.drop_presence_map();
+        self.drop_presence_map()?;
         Ok(())
     }
 
     fn decode_field(&mut self, instruction: &Instruction) -> Result<()> {
-        let value = self.extract_field(instruction)?;
-        self.msg.set_value(instruction.id, &instruction.name, value);
+        let wanted = !self.muted && self.projection.is_none_or(|p| p.contains(&instruction.id));
+        if !wanted && self.stats.is_none() && self.trace.is_none() {
+            return instruction.skip(self)
+                .map_err(|e| self.locate(e, &instruction.name));
+        }
+        let value = self.extract_field(instruction)
+            .map_err(|e| self.locate(e, &instruction.name))?;
+        if wanted {
+            self.notify(|m| m.set_field(FieldInfo::new(instruction), value))
+                .map_err(|e| self.locate(e, &instruction.name))?;
+        }
         Ok(())
     }
 
     // A sequence field instruction specifies that the field in the application type is of sequence type and that
     // the contained group of instructions should be used repeatedly to encode each element.
     fn decode_sequence(&mut self, instruction: &Instruction) -> Result<()> {
-        let has_dictionary = self.switch_dictionary(&instruction.dictionary);
-        let has_type_ref = self.switch_type_ref(&instruction.type_ref);
+        let has_type_ref = self.switch_type_ref(instruction.type_index);
 
         // A sequence has an associated length field containing an unsigned integer indicating the number of encoded
         // elements. When a length field is present in the stream, it must appear directly before the encoded elements.
         // The length field has a name, is of type uInt32 and can have a field operator.
         let length_instruction = instruction.instructions.get(0).unwrap();
-        match self.extract_field(length_instruction)? {
+        let length = self.extract_field(length_instruction)
+            .map_err(|e| self.locate(e, &format!("{}/{}", instruction.name, length_instruction.name)))?;
+        match length {
             None => {}
-            Some(Value::UInt32(length)) => {
-                self.msg.start_sequence(instruction.id, &instruction.name, length);
+            Some(ValueRef::UInt32(length)) => {
+                if length > self.limits.max_sequence_length {
+                    return Err(self.locate(Error::LimitExceeded(format!(
+                        "sequence length {} exceeds {}", length, self.limits.max_sequence_length
+                    )), &instruction.name));
+                }
+                self.notify(|m| m.start_sequence(instruction.id, &instruction.name, length))
+                    .map_err(|e| self.locate(e, &instruction.name))?;
                 for idx in 0..length {
-                    self.msg.start_sequence_item(idx);
+                    self.notify(|m| m.start_sequence_item(idx))
+                        .map_err(|e| self.locate(e, &format!("{}[{}]", instruction.name, idx)))?;
                     // If any instruction of the sequence needs to allocate a bit in a presence map, each element is represented
                     // as a segment in the transfer encoding.
-                    if instruction.has_pmap.get() {
-                        self.decode_segment(&instruction.instructions[1..])?;
+                    if instruction.has_pmap {
+                        self.decode_segment(&instruction.instructions[1..])
                     } else {
-                        self.decode_instructions(&instruction.instructions[1..])?;
-                    }
-                    self.msg.stop_sequence_item();
+                        self.decode_instructions(&instruction.instructions[1..])
+                    }.map_err(|e| self.locate(e, &format!("{}[{}]", instruction.name, idx)))?;
+                    self.notify(|m| m.stop_sequence_item())
+                        .map_err(|e| self.locate(e, &format!("{}[{}]", instruction.name, idx)))?;
                 }
-                self.msg.stop_sequence();
+                self.notify(|m| m.stop_sequence())
+                    .map_err(|e| self.locate(e, &instruction.name))?;
             }
-            _ => return Err(Error::Dynamic("Length field must be UInt32".to_string())), // [ErrD10]
+            _ => return Err(self.locate(Error::fast(FastErrorCode::D10, "Length field must be UInt32"), &instruction.name)), // [ERR D10]
         }
 
-        if has_dictionary { self.restore_dictionary() }
         if has_type_ref { self.restore_type_ref() }
         Ok(())
     }
@@ -244,20 +702,20 @@ impl<'a> DecoderContext<'a> {
             return Ok(());
         }
 
-        let has_dictionary = self.switch_dictionary(&instruction.dictionary);
-        let has_type_ref = self.switch_type_ref(&instruction.type_ref);
+        let has_type_ref = self.switch_type_ref(instruction.type_index);
 
-        self.msg.start_group(&instruction.name);
+        self.notify(|m| m.start_group(&instruction.name))
+            .map_err(|e| self.locate(e, &instruction.name))?;
         // If any instruction of the group needs to allocate a bit in a presence map, each element is represented
         // as a segment in the transfer encoding.
-        if instruction.has_pmap.get() {
-            self.decode_segment(&instruction.instructions)?;
+        if instruction.has_pmap {
+            self.decode_segment(&instruction.instructions)
         } else {
-            self.decode_instructions(&instruction.instructions)?;
-        }
-        self.msg.stop_group();
+            self.decode_instructions(&instruction.instructions)
+        }.map_err(|e| self.locate(e, &instruction.name))?;
+        self.notify(|m| m.stop_group())
+            .map_err(|e| self.locate(e, &instruction.name))?;
 
-        if has_dictionary { self.restore_dictionary() }
         if has_type_ref { self.restore_type_ref() }
         Ok(())
     }
@@ -266,69 +724,114 @@ impl<'a> DecoderContext<'a> {
     // A template reference can be either static or dynamic. A reference is static when a name is specified in the
     // instruction. Otherwise, it is dynamic.
     fn decode_template_ref(&mut self, instruction: &Instruction) -> Result<()> {
+        if self.template_ref_depth == self.limits.max_template_ref_depth {
+            return Err(self.locate(Error::LimitExceeded(format!(
+                "template reference depth exceeds {}", self.limits.max_template_ref_depth
+            )), ""));
+        }
+        self.template_ref_depth += 1;
         let is_dynamic = instruction.name.is_empty();
 
-        let template: Rc<Template>;
+        let template: Arc<Template>;
         if is_dynamic {
             self.decode_presence_map()?;
-            self.decode_template_id()?;
-            template = self.definitions.templates_by_id
-                .get(self.template_id.peek().unwrap())
-                .ok_or_else(|| Error::Dynamic(format!("Unknown template id: {}", self.template_id.peek().unwrap())))? // [ErrD09]
-                .clone();
+            template = self.decode_template_id()
+                .map_err(|e| self.locate(e, ""))?;
         } else {
             template = self.definitions.templates_by_name
                 .get(&instruction.name)
-                .ok_or_else(|| Error::Dynamic(format!("Unknown template: {}", instruction.name)))? // [ErrD09]
+                .ok_or_else(|| Error::fast(FastErrorCode::D8, format!("Unknown template: {}", instruction.name)))? // [ERR D8]
                 .clone();
         }
-        self.msg.start_template_ref(&template.name, is_dynamic);
+        self.notify(|m| m.start_template_ref_id(template.id, &template.name, is_dynamic))
+            .map_err(|e| self.locate(e, &template.name))?;
 
         // Update some context variables
-        let has_dictionary = self.switch_dictionary(&template.dictionary);
-        let has_type_ref = self.switch_type_ref(&template.type_ref);
+        let has_type_ref = self.switch_type_ref(template.type_index);
 
-        self.decode_instructions(&template.instructions)?;
+        self.decode_instructions(&template.instructions)
+            .map_err(|e| self.locate(e, &template.name))?;
 
-        if has_dictionary { self.restore_dictionary() }
         if has_type_ref { self.restore_type_ref() }
 
-        self.msg.stop_template_ref();
+        self.notify(|m| m.stop_template_ref())
+            .map_err(|e| self.locate(e, &template.name))?;
         if is_dynamic {
             self.drop_template_id();
-            self.drop_presence_map();
+            self.drop_presence_map()?;
         }
+        self.template_ref_depth -= 1;
         Ok(())
     }
 
-    fn extract_field(&mut self, instruction: &Instruction) -> Result<Option<Value>> {
-        let has_dict = self.switch_dictionary(&instruction.dictionary);
-        let value = instruction.extract(self)?;
-        if has_dict {
-            self.restore_dictionary();
-        }
-        Ok(value)
+    // Add location to the error unwinding from `segment` of the field path.
+    fn locate(&self, e: Error, segment: &str) -> Error {
+        e.at_field(segment, self.rdr.position())
     }
 
+    // Pass a notification to the message factory, or to the null factory while the message is muted.
     #[inline]
-    fn switch_dictionary(&mut self, dictionary: &Dictionary) -> bool {
-        if *dictionary != Dictionary::Inherit {
-            self.dictionary.push(dictionary.clone());
-            true
+    fn notify(&mut self, f: impl FnOnce(&mut dyn TryMessageFactory<'d>) -> Result<Control>) -> Result<()> {
+        let control = if self.muted {
+            f(&mut self.null_factory)
         } else {
-            false
+            f(&mut **self.msg)
+        };
+        self.control(control)
+    }
+
+    // Once the message factory returns `Control::Skip`, the rest of the message is decoded muted.
+    #[inline]
+    fn control(&mut self, control: Result<Control>) -> Result<()> {
+        if control? == Control::Skip {
+            self.muted = true;
         }
+        Ok(())
     }
 
     #[inline]
-    fn restore_dictionary(&mut self) {
-        _ = self.dictionary.pop();
+    fn extract_field(&mut self, instruction: &Instruction) -> Result<Option<ValueRef<'d>>> {
+        if self.stats.is_none() && self.trace.is_none() {
+            return instruction.extract(self);
+        }
+        let start = self.position();
+        let value = if self.trace.is_some() {
+            self.extract_traced(instruction, TraceKind::Field)?
+        } else {
+            instruction.extract(self)?
+        };
+        let size = self.position() - start;
+        if let Some(stats) = self.stats.as_deref_mut() {
+            stats.add_field(self.stats_template_id, instruction, size, value.is_none());
+        }
+        Ok(value)
+    }
+
+    // Extract the field value and add it to the trace along with the dictionary value it is based on.
+    fn extract_traced(&mut self, instruction: &Instruction, kind: TraceKind) -> Result<Option<ValueRef<'d>>> {
+        let start = self.position();
+        let bit = self.presence_map.peek().map(|p| p.pos);
+        let previous = match instruction.operator {
+            Operator::Copy | Operator::Increment | Operator::Delta | Operator::Tail => self.context.get(self.slot(instruction)),
+            _ => None,
+        };
+        let value = instruction.extract(self)?;
+        let pmap_bit = match (bit, self.presence_map.peek()) {
+            (Some(pos), Some(p)) if p.pos > pos => Some(p.bit(pos)),
+            _ => None,
+        };
+        let len = self.position() - start;
+        if let Some(trace) = self.trace.as_deref_mut() {
+            let v = value.as_ref().map(ValueRef::to_value);
+            trace.push(TraceEntry::field(start, len, kind, instruction, pmap_bit, previous, v));
+        }
+        Ok(value)
     }
 
     #[inline]
-    fn switch_type_ref(&mut self, type_ref: &TypeRef) -> bool {
-        if *type_ref != TypeRef::Any {
-            self.type_ref.push(type_ref.clone());
+    fn switch_type_ref(&mut self, type_index: usize) -> bool {
+        if type_index != 0 {
+            self.type_index.push(type_index);
             true
         } else {
             false
@@ -337,7 +840,7 @@ impl<'a> DecoderContext<'a> {
 
     #[inline]
     fn restore_type_ref(&mut self) {
-        _ = self.type_ref.pop();
+        _ = self.type_index.pop();
     }
 
     #[inline]
@@ -347,42 +850,61 @@ impl<'a> DecoderContext<'a> {
 
     #[inline]
     pub(crate) fn ctx_set(&mut self, i: &Instruction, v: &Option<Value>) {
-        self.context.set(self.make_dict_type(), i.key.clone(), v);
+        self.context.set(self.slot(i), v.clone());
+    }
+
+    #[inline]
+    pub(crate) fn ctx_set_ref(&mut self, i: &Instruction, v: &Option<ValueRef>) {
+        self.context.set(self.slot(i), v.as_ref().map(ValueRef::to_value));
     }
 
     #[inline]
     pub(crate) fn ctx_get(&mut self, i: &Instruction) -> Result<Option<Option<Value>>> {
-        let v = self.context.get(self.make_dict_type(), &i.key);
+        let v = self.context.get(self.slot(i));
         if let Some(Some(ref v)) = v {
             if !i.value_type.matches_type(v) {
                 // It is a dynamic error [ERR D4] if the field of an operator accessing an entry does not have
                 // the same type as the value of the entry.
-                return Err(Error::Runtime(format!("field {} has wrong value type in context", i.name)));  // [ERR D4]
+                return Err(Error::fast(FastErrorCode::D4, format!("field {} has wrong value type in context", i.name)));  // [ERR D4]
             }
         }
         Ok(v)
     }
 
-    fn make_dict_type(&self) -> DictionaryType {
-        let dictionary = self.dictionary.must_peek();
-        match dictionary {
-            Dictionary::Inherit => unreachable!(),
-            Dictionary::Global => {
-                DictionaryType::Global
-            }
-            Dictionary::Template => {
-                DictionaryType::Template(*self.template_id.must_peek())
-            }
-            Dictionary::Type => {
-                let name = match self.type_ref.must_peek() {
-                    TypeRef::Any => Rc::from("__any__"),
-                    TypeRef::ApplicationType(name) => name.clone(),
-                };
-                DictionaryType::Type(name)
-            }
-            Dictionary::UserDefined(name) => {
-                DictionaryType::UserDefined(name.clone())
-            }
+    // Context storage slot of the instruction's dictionary entry.
+    #[inline]
+    fn slot(&self, i: &Instruction) -> usize {
+        match i.slot {
+            DictionarySlot::Static(k) => k,
+            DictionarySlot::Template(k) => self.definitions.layout.template_slot(*self.template_index.must_peek(), k),
+            DictionarySlot::Type(k) => self.definitions.layout.type_slot(*self.type_index.must_peek(), k),
         }
     }
 }
+
+// Wrap the reader with the readers that enforce the decoding options.
+fn wrap_reader<'a, 'd, R: BorrowingReader<'d> + 'a>(r: R, strict: bool, limits: DecoderLimits) -> Box<dyn BorrowingReader<'d> + 'a> {
+    match (strict, limits.limits_input()) {
+        (false, false) => Box::new(r),
+        (false, true) => Box::new(LimitReader::new(r, limits)),
+        (true, false) => Box::new(StrictReader::new(r)),
+        (true, true) => Box::new(StrictReader::new(LimitReader::new(r, limits))),
+    }
+}
+
+// Message factory that ignores all the callbacks.
+struct NullFactory;
+
+impl<'d> MessageFactoryRef<'d> for NullFactory {
+    fn start_template(&mut self, _id: u32, _name: &str) {}
+    fn stop_template(&mut self) {}
+    fn set_value(&mut self, _id: u32, _name: &str, _value: Option<ValueRef<'d>>) {}
+    fn start_sequence(&mut self, _id: u32, _name: &str, _length: u32) {}
+    fn start_sequence_item(&mut self, _index: u32) {}
+    fn stop_sequence_item(&mut self) {}
+    fn stop_sequence(&mut self) {}
+    fn start_group(&mut self, _name: &str) {}
+    fn stop_group(&mut self) {}
+    fn start_template_ref(&mut self, _name: &str, _dynamic: bool) {}
+    fn stop_template_ref(&mut self) {}
+}
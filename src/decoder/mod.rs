@@ -1,2 +1,5 @@
 pub(crate) mod decoder;
+pub(crate) mod options;
+pub(crate) mod packet;
+pub(crate) mod push;
 pub(crate) mod reader;
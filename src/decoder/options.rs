@@ -0,0 +1,58 @@
+/// How strictly the decoder validates the transfer encoding.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Strictness {
+    /// Accept any decodable input, e.g. integers with redundant leading bytes or integers that don't fit 64 bits.
+    #[default]
+    Lenient,
+    /// Reject input with reportable errors in the transfer encoding:
+    /// * integers that don't fit 64 bits or are encoded with more bytes than needed
+    ///   ([R6][crate::FastErrorCode::R6]);
+    /// * presence maps encoded with more bytes than needed ([R7][crate::FastErrorCode::R7]) or having bits set
+    ///   that are not used by the instructions ([R8][crate::FastErrorCode::R8]);
+    /// * ASCII strings with a redundant zero preamble ([R9][crate::FastErrorCode::R9]).
+    Strict,
+}
+
+/// Options of the decoder, see [`Decoder::set_options`][crate::Decoder::set_options].
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DecoderOptions {
+    /// Validation of the transfer encoding.
+    pub strictness: Strictness,
+    /// Limits of the decoded input.
+    pub limits: DecoderLimits,
+}
+
+/// Limits that protect the decoder against hostile or corrupted input.
+/// When a limit is exceeded the decoder returns [`Error::LimitExceeded`][crate::Error::LimitExceeded].
+///
+/// By default, there are no limits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecoderLimits {
+    /// Maximum number of elements in a sequence.
+    pub max_sequence_length: u32,
+    /// Maximum length in bytes of a unicode string or a byte vector. ASCII strings have no length prefix,
+    /// they are limited by `max_message_size`.
+    pub max_string_length: usize,
+    /// Maximum nesting depth of template references.
+    pub max_template_ref_depth: usize,
+    /// Maximum size of a message in bytes.
+    pub max_message_size: usize,
+}
+
+impl DecoderLimits {
+    // Returns `true` if the input must be checked while reading.
+    pub(crate) fn limits_input(&self) -> bool {
+        self.max_string_length != usize::MAX || self.max_message_size != usize::MAX
+    }
+}
+
+impl Default for DecoderLimits {
+    fn default() -> Self {
+        Self {
+            max_sequence_length: u32::MAX,
+            max_string_length: usize::MAX,
+            max_template_ref_depth: usize::MAX,
+            max_message_size: usize::MAX,
+        }
+    }
+}
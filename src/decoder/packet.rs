@@ -0,0 +1,69 @@
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+use crate::{Result, Value};
+
+type PreambleParser = dyn Fn(&[u8]) -> Result<Preamble> + Send + Sync;
+
+/// Fields parsed from a packet preamble, e.g. packet sequence number or channel id.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Preamble {
+    pub fields: Vec<(String, Value)>,
+}
+
+impl Preamble {
+    /// Returns the value of the field with given name.
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.fields.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+}
+
+/// Layout of datagram packets used by [`Decoder::decode_packet`][crate::Decoder::decode_packet]:
+/// a fixed-size preamble followed by one or more messages.
+///
+/// ```rust
+/// use fastlib::{PacketLayout, Preamble, Value};
+///
+/// // 4-byte big-endian packet sequence number.
+/// let layout = PacketLayout::new(4, |b| Ok(Preamble {
+///     fields: vec![("SeqNum".to_string(), Value::UInt32(u32::from_be_bytes([b[0], b[1], b[2], b[3]])))],
+/// }));
+/// ```
+#[derive(Clone)]
+pub struct PacketLayout {
+    pub(crate) preamble_len: usize,
+    parser: Arc<PreambleParser>,
+}
+
+impl PacketLayout {
+    /// Create layout with preamble of `preamble_len` bytes; `parser` gets exactly `preamble_len` bytes.
+    pub fn new(preamble_len: usize, parser: impl Fn(&[u8]) -> Result<Preamble> + Send + Sync + 'static) -> Self {
+        Self {
+            preamble_len,
+            parser: Arc::new(parser),
+        }
+    }
+
+    /// Create layout of packets without a preamble.
+    pub fn without_preamble() -> Self {
+        Self::new(0, |_| Ok(Preamble::default()))
+    }
+
+    pub(crate) fn parse(&self, preamble: &[u8]) -> Result<Preamble> {
+        (self.parser)(preamble)
+    }
+}
+
+impl Default for PacketLayout {
+    fn default() -> Self {
+        Self::without_preamble()
+    }
+}
+
+impl Debug for PacketLayout {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PacketLayout")
+            .field("preamble_len", &self.preamble_len)
+            .finish_non_exhaustive()
+    }
+}
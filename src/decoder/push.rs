@@ -0,0 +1,100 @@
+use crate::{Error, Result, TryMessageFactory};
+use crate::common::context::Transactional;
+use crate::decoder::decoder::Decoder;
+
+/// Result of [`PushDecoder::decode`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DecodeStatus {
+    /// A complete message was decoded and passed to the message factory.
+    Message,
+    /// The buffered data doesn't contain a complete message; feed more data and try again.
+    NeedMoreData,
+}
+
+/// Push-style decoder for data that arrives in chunks, e.g. from TCP reads.
+///
+/// Bytes are passed to [`PushDecoder::feed`] as they arrive and complete messages are decoded with
+/// [`PushDecoder::decode`]. If the buffered data ends in the middle of a message, dictionary changes made
+/// by the partial attempt are rolled back and the data is kept until more bytes are fed.
+///
+/// Note that the message factory may receive callbacks of a partially decoded message before
+/// [`DecodeStatus::NeedMoreData`] is returned; the message is decoded from the start (beginning with
+/// [`TryMessageFactory::start_template`]) on the next attempt.
+///
+/// A partial message can't grow beyond [`DecoderLimits::max_message_size`][crate::DecoderLimits::max_message_size]:
+/// once more bytes are buffered without a complete message, [`Error::LimitExceeded`] is returned.
+pub struct PushDecoder {
+    decoder: Decoder,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl PushDecoder {
+    pub fn new(decoder: Decoder) -> Self {
+        Self {
+            decoder,
+            buf: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    /// Append received bytes to the internal buffer.
+    pub fn feed(&mut self, data: &[u8]) {
+        if self.pos > 0 {
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+        }
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Decode the next message from the buffered data.
+    /// On error other than the end of data, dictionary changes made by the message are rolled back and the
+    /// buffered data is left untouched; use [`PushDecoder::clear`] to drop it.
+    ///
+    /// The values passed to a [`MessageFactoryRef`][crate::MessageFactoryRef] borrow the buffered data,
+    /// so they can't outlive the next call of [`PushDecoder::feed`].
+    pub fn decode<'a>(&'a mut self, msg: &mut impl TryMessageFactory<'a>) -> Result<DecodeStatus> {
+        if self.pos == self.buf.len() {
+            return Ok(DecodeStatus::NeedMoreData);
+        }
+        // Partial messages are always rolled back, regardless of `Decoder::set_transactional`.
+        let buf = &self.buf[self.pos..];
+        match self.decoder.transaction(true, |d| d.decode_slice_message(buf, msg)) {
+            Ok(n) => {
+                self.pos += n;
+                Ok(DecodeStatus::Message)
+            }
+            Err(Error::Eof) | Err(Error::UnexpectedEof) => {
+                let max = self.decoder.options.limits.max_message_size;
+                if buf.len() > max {
+                    return Err(Error::LimitExceeded(format!("message size exceeds {} bytes", max)));
+                }
+                Ok(DecodeStatus::NeedMoreData)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Number of buffered bytes not decoded yet.
+    pub fn buffered(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    /// Drop all buffered data. Dictionaries are not reset; use [`Decoder::reset`] via [`PushDecoder::decoder_mut`] for that.
+    pub fn clear(&mut self) {
+        self.buf.clear();
+        self.pos = 0;
+    }
+
+    pub fn decoder(&self) -> &Decoder {
+        &self.decoder
+    }
+
+    pub fn decoder_mut(&mut self) -> &mut Decoder {
+        &mut self.decoder
+    }
+
+    pub fn into_inner(self) -> Decoder {
+        self.decoder
+    }
+}
@@ -4,11 +4,14 @@
 //! fixed sizes for integers. An integer field instruction must therefore specify the bounds of the integer.
 //! The encoding and decoding of a value is not affected by the size of the integer.
 //!
+use std::borrow::Cow;
 use std::io::{ErrorKind, Read};
 
 use bytes::Buf;
 
-use crate::{Error, Result};
+use crate::{Error, FastErrorCode, PresenceMap, Result};
+use crate::common::block::BlockLength;
+use crate::decoder::options::DecoderLimits;
 
 /// A trait that provides methods for reading basic primitive types.
 pub trait Reader {
@@ -17,25 +20,21 @@ pub trait Reader {
     /// Return [`Error::UnexpectedEof`][crate::Error::UnexpectedEof] instead.
     fn read_u8(&mut self) -> Result<u8>;
 
-    /// Read the presence map. Return the bitmap and the number of bits in the bitmap.
+    /// Read the presence map.
     ///
     /// In case of error, return [`Error::Eof`][crate::Error::Eof] if the end of the stream is reached at the first byte
     /// of the presence map. Otherwise, return any other error, e.g.: [`Error::UnexpectedEof`][crate::Error::UnexpectedEof].
-    fn read_presence_map(&mut self) -> Result<(u64, u8)> {
-        let mut bitmap: u64 = 0;
-        let mut size: u8 = 0;
+    fn read_presence_map(&mut self) -> Result<PresenceMap> {
+        let mut pmap = PresenceMap::new_empty();
         let mut byte = match self.read_u8() {
             Ok(b) => b,
             Err(Error::UnexpectedEof) => return Err(Error::Eof),
             Err(e) => return Err(e),
         };
         loop {
-            bitmap <<= 7;
-            bitmap |= (byte & 0x7f) as u64;
-            size += 7;
-
+            pmap.push_group(byte & 0x7f);
             if byte & 0x80 == 0x80 {
-                return Ok((bitmap, size))
+                return Ok(pmap)
             }
             byte = self.read_u8()?
         }
@@ -181,6 +180,652 @@ impl Reader for bytes::Bytes {
 }
 
 
+/// Extension of [`fastlib::Reader`][crate::decoder::reader::Reader] used by the decoder to get unicode strings and
+/// byte vectors that can borrow data from the input for lifetime `'a`.
+///
+/// The length prefixes are read with [`Reader::read_uint`]; the data is read with [`BorrowingReader::read_slice_ref`]
+/// which by default returns owned data read with [`Reader::read_u8`].
+pub(crate) trait BorrowingReader<'a>: Reader {
+    /// Returns the number of bytes consumed so far, if it is known.
+    fn position(&self) -> Option<usize>;
+
+    /// Make [`BorrowingReader::position`] known, e.g. by counting the bytes read. Called by the decoder
+    /// when it needs the position for statistics, tracing or limits.
+    fn count_position(&mut self) {}
+
+    /// Read `length` bytes.
+    fn read_slice_ref(&mut self, length: u64) -> Result<Cow<'a, [u8]>> {
+        let mut buf = Vec::with_capacity(length as usize);
+        for _ in 0..length {
+            buf.push(self.read_u8()?);
+        }
+        Ok(Cow::Owned(buf))
+    }
+
+    /// Skip `length` bytes.
+    fn skip_slice(&mut self, length: u64) -> Result<()> {
+        for _ in 0..length {
+            self.read_u8()?;
+        }
+        Ok(())
+    }
+
+    fn read_unicode_string_ref(&mut self) -> Result<Cow<'a, str>> {
+        let length = self.read_uint()?;
+        cow_to_str(self.read_slice_ref(length)?)
+    }
+
+    fn read_unicode_string_ref_nullable(&mut self) -> Result<Option<Cow<'a, str>>> {
+        match self.read_uint_nullable()? {
+            None => Ok(None),
+            Some(length) => Ok(Some(cow_to_str(self.read_slice_ref(length)?)?)),
+        }
+    }
+
+    fn read_bytes_ref(&mut self) -> Result<Cow<'a, [u8]>> {
+        let length = self.read_uint()?;
+        self.read_slice_ref(length)
+    }
+
+    fn read_bytes_ref_nullable(&mut self) -> Result<Option<Cow<'a, [u8]>>> {
+        match self.read_uint_nullable()? {
+            None => Ok(None),
+            Some(length) => Ok(Some(self.read_slice_ref(length)?)),
+        }
+    }
+}
+
+fn cow_to_str(b: Cow<[u8]>) -> Result<Cow<str>> {
+    match b {
+        Cow::Borrowed(b) => Ok(Cow::Borrowed(std::str::from_utf8(b)?)),
+        Cow::Owned(b) => Ok(Cow::Owned(String::from_utf8(b)?)),
+    }
+}
+
+impl<'a, R: BorrowingReader<'a> + ?Sized> BorrowingReader<'a> for &mut R {
+    fn position(&self) -> Option<usize> {
+        (**self).position()
+    }
+
+    fn count_position(&mut self) {
+        (**self).count_position()
+    }
+
+    fn read_slice_ref(&mut self, length: u64) -> Result<Cow<'a, [u8]>> {
+        (**self).read_slice_ref(length)
+    }
+
+    fn skip_slice(&mut self, length: u64) -> Result<()> {
+        (**self).skip_slice(length)
+    }
+
+    fn read_unicode_string_ref(&mut self) -> Result<Cow<'a, str>> {
+        (**self).read_unicode_string_ref()
+    }
+
+    fn read_unicode_string_ref_nullable(&mut self) -> Result<Option<Cow<'a, str>>> {
+        (**self).read_unicode_string_ref_nullable()
+    }
+
+    fn read_bytes_ref(&mut self) -> Result<Cow<'a, [u8]>> {
+        (**self).read_bytes_ref()
+    }
+
+    fn read_bytes_ref_nullable(&mut self) -> Result<Option<Cow<'a, [u8]>>> {
+        (**self).read_bytes_ref_nullable()
+    }
+}
+
+
+impl<R: Reader + ?Sized> Reader for &mut R {
+    fn read_u8(&mut self) -> Result<u8> {
+        (**self).read_u8()
+    }
+
+    fn read_presence_map(&mut self) -> Result<PresenceMap> {
+        (**self).read_presence_map()
+    }
+
+    fn read_uint(&mut self) -> Result<u64> {
+        (**self).read_uint()
+    }
+
+    fn read_uint_nullable(&mut self) -> Result<Option<u64>> {
+        (**self).read_uint_nullable()
+    }
+
+    fn read_int(&mut self) -> Result<i64> {
+        (**self).read_int()
+    }
+
+    fn read_int_nullable(&mut self) -> Result<Option<i64>> {
+        (**self).read_int_nullable()
+    }
+
+    fn read_ascii_string(&mut self) -> Result<String> {
+        (**self).read_ascii_string()
+    }
+
+    fn read_ascii_string_nullable(&mut self) -> Result<Option<String>> {
+        (**self).read_ascii_string_nullable()
+    }
+
+    fn read_unicode_string(&mut self) -> Result<String> {
+        (**self).read_unicode_string()
+    }
+
+    fn read_unicode_string_nullable(&mut self) -> Result<Option<String>> {
+        (**self).read_unicode_string_nullable()
+    }
+
+    fn read_bytes(&mut self) -> Result<Vec<u8>> {
+        (**self).read_bytes()
+    }
+
+    fn read_bytes_nullable(&mut self) -> Result<Option<Vec<u8>>> {
+        (**self).read_bytes_nullable()
+    }
+}
+
+
+/// Reader of one block of a block-framed stream. The block length is read when the reader is created
+/// and it is an error to read past the end of the block.
+pub struct BlockReader<'a, R: Reader + ?Sized> {
+    rdr: &'a mut R,
+    remaining: usize,
+}
+
+impl<'a, R: Reader + ?Sized> BlockReader<'a, R> {
+    /// Read the block length from `rdr`. Returns [`Error::Eof`][crate::Error::Eof] if the stream has ended.
+    /// It is an error if the block length is zero.
+    pub fn new(rdr: &'a mut R, length: BlockLength) -> Result<Self> {
+        let remaining = length.read(rdr)?;
+        if remaining == 0 {
+            return Err(Error::fast(FastErrorCode::D12, "block length is zero")); // [ERR D12]
+        }
+        Ok(Self { rdr, remaining })
+    }
+
+    /// Returns the number of bytes left in the block.
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    /// Skip the rest of the block, so the underlying reader is positioned at the next block.
+    pub fn skip(&mut self) -> Result<()> {
+        while self.remaining > 0 {
+            self.remaining -= 1;
+            self.rdr.read_u8()?;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Reader + ?Sized> Reader for BlockReader<'_, R> {
+    fn read_u8(&mut self) -> Result<u8> {
+        if self.remaining == 0 {
+            return Err(Error::Dynamic("message exceeds block length".to_string()));
+        }
+        self.remaining -= 1;
+        self.rdr.read_u8()
+    }
+}
+
+
+/// Wrapper around any [`fastlib::Reader`][crate::decoder::reader::Reader] that never borrows data from the input.
+///
+/// All reads are forwarded to the wrapped reader, so its own implementations of the [`Reader`] methods are used.
+/// The position is known if the wrapped reader tells the number of bytes left in it (see [`OwnedReader::with_remaining`]).
+/// Otherwise it is only known when counted (see [`BorrowingReader::count_position`]); all reads then go through
+/// [`Reader::read_u8`] of the wrapped reader.
+pub(crate) struct OwnedReader<'r, R: Reader + ?Sized> {
+    rdr: &'r mut R,
+    // Returns the number of bytes left in `rdr`.
+    remaining: Option<fn(&R) -> usize>,
+    // Number of bytes left in `rdr` at the start.
+    start: usize,
+    // Number of bytes read if they are counted.
+    counted: Option<usize>,
+}
+
+impl<'r, R: Reader + ?Sized> OwnedReader<'r, R> {
+    pub fn new(rdr: &'r mut R) -> Self {
+        Self { rdr, remaining: None, start: 0, counted: None }
+    }
+
+    /// Create the reader which position is calculated from the number of bytes left in `rdr`.
+    pub fn with_remaining(rdr: &'r mut R, remaining: fn(&R) -> usize) -> Self {
+        let start = remaining(rdr);
+        Self { rdr, remaining: Some(remaining), start, counted: None }
+    }
+}
+
+// Reads through `OwnedReader::read_u8` only, so the bytes are counted.
+struct CountingReader<'a, 'r, R: Reader + ?Sized>(&'a mut OwnedReader<'r, R>);
+
+impl<R: Reader + ?Sized> Reader for CountingReader<'_, '_, R> {
+    fn read_u8(&mut self) -> Result<u8> {
+        self.0.read_u8()
+    }
+}
+
+impl<R: Reader + ?Sized> Reader for OwnedReader<'_, R> {
+    #[inline]
+    fn read_u8(&mut self) -> Result<u8> {
+        let b = self.rdr.read_u8()?;
+        if let Some(counted) = self.counted.as_mut() {
+            *counted += 1;
+        }
+        Ok(b)
+    }
+
+    fn read_presence_map(&mut self) -> Result<PresenceMap> {
+        match self.counted {
+            Some(_) => CountingReader(self).read_presence_map(),
+            None => self.rdr.read_presence_map(),
+        }
+    }
+
+    fn read_uint(&mut self) -> Result<u64> {
+        match self.counted {
+            Some(_) => CountingReader(self).read_uint(),
+            None => self.rdr.read_uint(),
+        }
+    }
+
+    fn read_uint_nullable(&mut self) -> Result<Option<u64>> {
+        match self.counted {
+            Some(_) => CountingReader(self).read_uint_nullable(),
+            None => self.rdr.read_uint_nullable(),
+        }
+    }
+
+    fn read_int(&mut self) -> Result<i64> {
+        match self.counted {
+            Some(_) => CountingReader(self).read_int(),
+            None => self.rdr.read_int(),
+        }
+    }
+
+    fn read_int_nullable(&mut self) -> Result<Option<i64>> {
+        match self.counted {
+            Some(_) => CountingReader(self).read_int_nullable(),
+            None => self.rdr.read_int_nullable(),
+        }
+    }
+
+    fn read_ascii_string(&mut self) -> Result<String> {
+        match self.counted {
+            Some(_) => CountingReader(self).read_ascii_string(),
+            None => self.rdr.read_ascii_string(),
+        }
+    }
+
+    fn read_ascii_string_nullable(&mut self) -> Result<Option<String>> {
+        match self.counted {
+            Some(_) => CountingReader(self).read_ascii_string_nullable(),
+            None => self.rdr.read_ascii_string_nullable(),
+        }
+    }
+
+    fn read_unicode_string(&mut self) -> Result<String> {
+        match self.counted {
+            Some(_) => CountingReader(self).read_unicode_string(),
+            None => self.rdr.read_unicode_string(),
+        }
+    }
+
+    fn read_unicode_string_nullable(&mut self) -> Result<Option<String>> {
+        match self.counted {
+            Some(_) => CountingReader(self).read_unicode_string_nullable(),
+            None => self.rdr.read_unicode_string_nullable(),
+        }
+    }
+
+    fn read_bytes(&mut self) -> Result<Vec<u8>> {
+        match self.counted {
+            Some(_) => CountingReader(self).read_bytes(),
+            None => self.rdr.read_bytes(),
+        }
+    }
+
+    fn read_bytes_nullable(&mut self) -> Result<Option<Vec<u8>>> {
+        match self.counted {
+            Some(_) => CountingReader(self).read_bytes_nullable(),
+            None => self.rdr.read_bytes_nullable(),
+        }
+    }
+}
+
+impl<'a, R: Reader + ?Sized> BorrowingReader<'a> for OwnedReader<'_, R> {
+    fn position(&self) -> Option<usize> {
+        match self.remaining {
+            Some(remaining) => Some(self.start - remaining(self.rdr)),
+            None => self.counted,
+        }
+    }
+
+    fn count_position(&mut self) {
+        if self.remaining.is_none() && self.counted.is_none() {
+            self.counted = Some(0);
+        }
+    }
+
+    // Unicode strings and byte vectors are read with the wrapped reader methods, unless the bytes are counted.
+    fn read_unicode_string_ref(&mut self) -> Result<Cow<'a, str>> {
+        Ok(Cow::Owned(self.read_unicode_string()?))
+    }
+
+    fn read_unicode_string_ref_nullable(&mut self) -> Result<Option<Cow<'a, str>>> {
+        Ok(self.read_unicode_string_nullable()?.map(Cow::Owned))
+    }
+
+    fn read_bytes_ref(&mut self) -> Result<Cow<'a, [u8]>> {
+        Ok(Cow::Owned(self.read_bytes()?))
+    }
+
+    fn read_bytes_ref_nullable(&mut self) -> Result<Option<Cow<'a, [u8]>>> {
+        Ok(self.read_bytes_nullable()?.map(Cow::Owned))
+    }
+}
+
+
+/// Reader over a contiguous bytes slice. Unicode strings and byte vectors are borrowed from the slice.
+pub(crate) struct SliceReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> SliceReader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    /// Returns the number of bytes consumed so far.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    fn read_slice(&mut self, length: u64) -> Result<&'a [u8]> {
+        let remaining = self.buf.len() - self.pos;
+        if length > remaining as u64 {
+            return Err(Error::UnexpectedEof);
+        }
+        let slice = &self.buf[self.pos..self.pos + length as usize];
+        self.pos += length as usize;
+        Ok(slice)
+    }
+}
+
+impl Reader for SliceReader<'_> {
+    fn read_u8(&mut self) -> Result<u8> {
+        match self.buf.get(self.pos) {
+            None => Err(Error::UnexpectedEof),
+            Some(b) => {
+                self.pos += 1;
+                Ok(*b)
+            }
+        }
+    }
+
+    fn read_bytes(&mut self) -> Result<Vec<u8>> {
+        Ok(self.read_bytes_ref()?.into_owned())
+    }
+
+    fn read_bytes_nullable(&mut self) -> Result<Option<Vec<u8>>> {
+        Ok(self.read_bytes_ref_nullable()?.map(Cow::into_owned))
+    }
+}
+
+impl<'a> BorrowingReader<'a> for SliceReader<'a> {
+    fn position(&self) -> Option<usize> {
+        Some(self.pos)
+    }
+
+    fn read_slice_ref(&mut self, length: u64) -> Result<Cow<'a, [u8]>> {
+        Ok(Cow::Borrowed(self.read_slice(length)?))
+    }
+
+    fn skip_slice(&mut self, length: u64) -> Result<()> {
+        self.read_slice(length)?;
+        Ok(())
+    }
+}
+
+
+/// Wrapper around a decoder's reader that keeps a copy of the bytes read, see [`Trace`][crate::Trace].
+pub(crate) struct TraceReader<'t, R> {
+    rdr: R,
+    data: &'t mut Vec<u8>,
+}
+
+impl<'t, R> TraceReader<'t, R> {
+    pub fn new(rdr: R, data: &'t mut Vec<u8>) -> Self {
+        Self { rdr, data }
+    }
+}
+
+impl<'a, R: BorrowingReader<'a>> Reader for TraceReader<'_, R> {
+    fn read_u8(&mut self) -> Result<u8> {
+        let b = self.rdr.read_u8()?;
+        self.data.push(b);
+        Ok(b)
+    }
+}
+
+impl<'a, R: BorrowingReader<'a>> BorrowingReader<'a> for TraceReader<'_, R> {
+    fn position(&self) -> Option<usize> {
+        self.rdr.position()
+    }
+
+    fn count_position(&mut self) {
+        self.rdr.count_position()
+    }
+
+    fn read_slice_ref(&mut self, length: u64) -> Result<Cow<'a, [u8]>> {
+        let b = self.rdr.read_slice_ref(length)?;
+        self.data.extend_from_slice(&b);
+        Ok(b)
+    }
+
+    fn skip_slice(&mut self, length: u64) -> Result<()> {
+        self.read_slice_ref(length)?;
+        Ok(())
+    }
+}
+
+
+/// Wrapper around a decoder's reader that enforces the message size and string length limits.
+pub(crate) struct LimitReader<R> {
+    rdr: R,
+    limits: DecoderLimits,
+}
+
+impl<R> LimitReader<R> {
+    pub fn new(rdr: R, limits: DecoderLimits) -> Self {
+        Self { rdr, limits }
+    }
+}
+
+impl<'a, R: BorrowingReader<'a>> LimitReader<R> {
+    // The decoder makes the position known when limits are set, see `BorrowingReader::count_position`.
+    fn check_size(&self, length: u64) -> Result<()> {
+        let position = self.rdr.position().unwrap_or(0);
+        if (position as u64).saturating_add(length) > self.limits.max_message_size as u64 {
+            return Err(Error::LimitExceeded(format!("message size exceeds {} bytes", self.limits.max_message_size)));
+        }
+        Ok(())
+    }
+
+    fn check_string_length(&self, length: u64) -> Result<()> {
+        if length > self.limits.max_string_length as u64 {
+            return Err(Error::LimitExceeded(format!(
+                "string length {} exceeds {} bytes", length, self.limits.max_string_length
+            )));
+        }
+        self.check_size(length)
+    }
+}
+
+impl<'a, R: BorrowingReader<'a>> Reader for LimitReader<R> {
+    fn read_u8(&mut self) -> Result<u8> {
+        self.check_size(1)?;
+        self.rdr.read_u8()
+    }
+}
+
+impl<'a, R: BorrowingReader<'a>> BorrowingReader<'a> for LimitReader<R> {
+    fn position(&self) -> Option<usize> {
+        self.rdr.position()
+    }
+
+    fn count_position(&mut self) {
+        self.rdr.count_position()
+    }
+
+    fn read_slice_ref(&mut self, length: u64) -> Result<Cow<'a, [u8]>> {
+        self.check_string_length(length)?;
+        self.rdr.read_slice_ref(length)
+    }
+
+    fn skip_slice(&mut self, length: u64) -> Result<()> {
+        self.check_string_length(length)?;
+        self.rdr.skip_slice(length)
+    }
+}
+
+
+/// Wrapper around a decoder's reader that rejects reportable errors in the transfer encoding,
+/// see [`Strictness::Strict`][crate::Strictness::Strict].
+pub(crate) struct StrictReader<R> {
+    rdr: R,
+}
+
+impl<R> StrictReader<R> {
+    pub fn new(rdr: R) -> Self {
+        Self { rdr }
+    }
+}
+
+impl<R: Reader> Reader for StrictReader<R> {
+    fn read_u8(&mut self) -> Result<u8> {
+        self.rdr.read_u8()
+    }
+
+    fn read_presence_map(&mut self) -> Result<PresenceMap> {
+        let pmap = self.rdr.read_presence_map()?;
+        // The last byte has no bits set, so the presence map could be encoded without it.
+        if pmap.groups() > 1 && pmap.group(pmap.groups() - 1) == 0 {
+            return Err(Error::fast(FastErrorCode::R7, "presence map is overlong")); // [ERR R7]
+        }
+        Ok(pmap)
+    }
+
+    fn read_uint(&mut self) -> Result<u64> {
+        let mut byte = self.rdr.read_u8()?;
+        if byte == 0x00 {
+            return Err(Error::fast(FastErrorCode::R6, "integer is overlong")); // [ERR R6]
+        }
+        let mut value: u64 = 0;
+        loop {
+            if value >> 57 != 0 {
+                return Err(Error::fast(FastErrorCode::R6, "integer doesn't fit 64 bits")); // [ERR R6]
+            }
+            value = (value << 7) | (byte & 0x7f) as u64;
+            if byte & 0x80 == 0x80 {
+                return Ok(value);
+            }
+            byte = self.rdr.read_u8()?;
+        }
+    }
+
+    fn read_int(&mut self) -> Result<i64> {
+        let first = self.rdr.read_u8()?;
+        let mut byte = first;
+        let mut value: i64 = if byte & 0x40 != 0 { -1 } else { 0 };
+        let mut count = 1;
+        loop {
+            if !(-(1 << 56)..(1 << 56)).contains(&value) {
+                return Err(Error::fast(FastErrorCode::R6, "integer doesn't fit 64 bits")); // [ERR R6]
+            }
+            value = (value << 7) | (byte & 0x7f) as i64;
+            if byte & 0x80 == 0x80 {
+                return Ok(value);
+            }
+            byte = self.rdr.read_u8()?;
+            count += 1;
+            // The first byte only repeats the sign bit of the second one.
+            if count == 2 && (first == 0x00 && byte & 0x40 == 0 || first == 0x7f && byte & 0x40 != 0) {
+                return Err(Error::fast(FastErrorCode::R6, "integer is overlong")); // [ERR R6]
+            }
+        }
+    }
+
+    fn read_ascii_string(&mut self) -> Result<String> {
+        let mut byte = self.rdr.read_u8()?;
+        if byte == 0x80 {
+            return Ok(String::new());
+        }
+        let first = byte;
+        let mut buf: Vec<u8> = Vec::new();
+        loop {
+            buf.push(byte & 0x7f);
+            if byte & 0x80 == 0x80 {
+                break
+            }
+            byte = self.rdr.read_u8()?;
+            if first == 0x00 && buf.len() == 1 && byte != 0x80 {
+                return Err(Error::fast(FastErrorCode::R9, "string is overlong")); // [ERR R9]
+            }
+        }
+        // SAFETY: `buf` contains ASCII 7-bit characters
+        unsafe { Ok(String::from_utf8_unchecked(buf)) }
+    }
+
+    fn read_ascii_string_nullable(&mut self) -> Result<Option<String>> {
+        let mut byte = self.rdr.read_u8()?;
+        if byte == 0x80 {
+            return Ok(None);
+        } else if byte == 0x00 {
+            byte = self.rdr.read_u8()?;
+            if byte == 0x80 {
+                return Ok(Some(String::new()));
+            } else if byte != 0x00 {
+                return Err(Error::fast(FastErrorCode::R9, "string is overlong")); // [ERR R9]
+            }
+        }
+        let mut buf: Vec<u8> = Vec::new();
+        loop {
+            buf.push(byte & 0x7f);
+            if byte & 0x80 == 0x80 {
+                break
+            }
+            byte = self.rdr.read_u8()?;
+        }
+        // SAFETY: `buf` contains ASCII 7-bit characters
+        unsafe { Ok(Some(String::from_utf8_unchecked(buf))) }
+    }
+}
+
+impl<'a, R: BorrowingReader<'a>> BorrowingReader<'a> for StrictReader<R> {
+    fn position(&self) -> Option<usize> {
+        self.rdr.position()
+    }
+
+    fn count_position(&mut self) {
+        self.rdr.count_position()
+    }
+
+    fn read_slice_ref(&mut self, length: u64) -> Result<Cow<'a, [u8]>> {
+        self.rdr.read_slice_ref(length)
+    }
+
+    fn skip_slice(&mut self, length: u64) -> Result<()> {
+        self.rdr.skip_slice(length)
+    }
+}
+
+
 /// Wrapper around `std::io::Read` that implements [`fastlib::Reader`][crate::decoder::reader::Reader].
 pub(crate) struct StreamReader<'a> {
     stream: &'a mut dyn Read,
@@ -217,20 +862,20 @@ mod tests {
     fn read_presence_map() {
         struct TestCase {
             input: Vec<u8>,
-            pmap: (u64, u8),
+            pmap: PresenceMap,
         }
         let test_cases: Vec<TestCase> = vec![
             TestCase {
                 input: vec![0x80],
-                pmap: (0b0, 7),
+                pmap: PresenceMap::new(0b0, 7),
             },
             TestCase {
                 input: vec![0x81],
-                pmap: (0b1, 7),
+                pmap: PresenceMap::new(0b1, 7),
             },
             TestCase {
                 input: vec![0x0f, 0x8f],
-                pmap: (0b11110001111, 14),
+                pmap: PresenceMap::new(0b11110001111, 14),
             },
         ];
         for tc in test_cases {
@@ -507,4 +1152,52 @@ mod tests {
             assert_eq!(value, tc.value);
         }
     }
+
+    #[test]
+    fn read_slice_borrowed() {
+        let input = vec![0x83, 0x41, 0x42, 0x43, 0x84, 0x44, 0x45, 0x46, 0x80, 0x82, 0x47];
+        let mut buf = SliceReader::new(&input);
+        let value = buf.read_unicode_string_ref().unwrap();
+        assert_eq!(value, Cow::Borrowed("ABC"));
+        let value = buf.read_bytes_ref_nullable().unwrap();
+        assert_eq!(value, Some(Cow::Borrowed(&[0x44u8, 0x45, 0x46][..])));
+        let value = buf.read_bytes_ref_nullable().unwrap();
+        assert_eq!(value, None);
+        assert_eq!(buf.position(), 9);
+        match buf.read_bytes_ref() {
+            Err(Error::UnexpectedEof) => {}
+            _ => panic!("Expected Err(UnexpectedEof)"),
+        }
+    }
+
+    #[test]
+    fn strict_reader() {
+        fn read<T>(input: &[u8], f: impl Fn(&mut StrictReader<SliceReader>) -> Result<T>) -> Result<T> {
+            f(&mut StrictReader::new(SliceReader::new(input)))
+        }
+        fn code<T>(res: Result<T>) -> Option<FastErrorCode> {
+            res.err().and_then(|e| e.code())
+        }
+        // Minimal encodings are accepted.
+        assert_eq!(read(&[0x00, 0x40, 0x81], |r| r.read_int()).unwrap(), 8193);
+        assert_eq!(read(&[0x7f, 0x3f, 0xff], |r| r.read_int()).unwrap(), -8193);
+        assert_eq!(read(&[0x01, 0x7f, 0xff], |r| r.read_uint()).unwrap(), 0x7fff);
+        assert_eq!(read(&[0x00, 0x80], |r| r.read_ascii_string_nullable()).unwrap(), Some(String::new()));
+        assert_eq!(read(&[0x40, 0x81], |r| r.read_presence_map()).unwrap(), PresenceMap::new(0b1000000_0000001, 14));
+        // Integers with redundant leading bytes or that don't fit 64 bits.
+        assert_eq!(code(read(&[0x00, 0x81], |r| r.read_uint())), Some(FastErrorCode::R6));
+        assert_eq!(code(read(&[0x00, 0x00, 0x81], |r| r.read_uint_nullable())), Some(FastErrorCode::R6));
+        assert_eq!(code(read(&[0x00, 0x3f, 0x81], |r| r.read_int())), Some(FastErrorCode::R6));
+        assert_eq!(code(read(&[0x7f, 0x40, 0x81], |r| r.read_int())), Some(FastErrorCode::R6));
+        assert_eq!(code(read(&[0x7f; 10].iter().chain(&[0xff]).copied().collect::<Vec<_>>(), |r| r.read_uint())), Some(FastErrorCode::R6));
+        assert_eq!(code(read(&[0x3f; 10].iter().chain(&[0xff]).copied().collect::<Vec<_>>(), |r| r.read_int())), Some(FastErrorCode::R6));
+        // Presence map with redundant last byte.
+        assert_eq!(code(read(&[0x40, 0x80], |r| r.read_presence_map())), Some(FastErrorCode::R7));
+        // ASCII strings with redundant zero preamble.
+        assert_eq!(code(read(&[0x00, 0xc1], |r| r.read_ascii_string())), Some(FastErrorCode::R9));
+        assert_eq!(code(read(&[0x00, 0xc1], |r| r.read_ascii_string_nullable())), Some(FastErrorCode::R9));
+        // Lenient reader accepts all of them.
+        assert_eq!(SliceReader::new(&[0x00, 0x81]).read_uint().unwrap(), 1);
+        assert_eq!(SliceReader::new(&[0x00, 0xc1]).read_ascii_string_nullable().unwrap(), Some("A".to_string()));
+    }
 }
@@ -1,45 +1,137 @@
 use std::io::Write;
-use std::rc::Rc;
+use std::sync::Arc;
 
 use bytes::BytesMut;
+use hashbrown::HashSet;
 
-use crate::{Error, Result};
+use crate::{Error, FastErrorCode, Result};
 use crate::base::instruction::Instruction;
-use crate::base::message::MessageVisitor;
+use crate::base::field::FieldInfo;
+use crate::base::message::{MessageVisitor, TemplateKey};
 use crate::base::pmap::PresenceMap;
-use crate::base::types::{Dictionary, Template, TypeRef};
+use crate::base::types::{Operator, Template};
 use crate::base::value::{Value, ValueType};
-use crate::common::context::{Context, DictionaryType};
+use crate::common::context::{Context, DictionaryEntry, DictionaryId, DictionarySlot, Transactional};
 use crate::common::definitions::Definitions;
-use crate::encoder::writer::{StreamWriter, Writer};
+use crate::common::snapshot::Snapshot;
+use crate::common::stats::Stats;
+use crate::common::trace::{Trace, TraceEntry, TraceKind};
+use crate::common::block::BlockLength;
+use crate::encoder::writer::{BlockWriter, CountingWriter, StreamWriter, Writer};
 use crate::utils::stacked::Stacked;
 
 /// Encoder for FAST protocol messages.
 pub struct Encoder {
-    pub(crate) definitions: Definitions,
+    pub(crate) definitions: Arc<Definitions>,
     pub(crate) context: Context,
+    pub(crate) transactional: bool,
+    pub(crate) reset_templates: HashSet<u32>,
+    pub(crate) stats: Option<Stats>,
+    pub(crate) trace: Option<Trace>,
 }
 
 impl Encoder {
     #[allow(unused)]
     pub(crate) fn new_from_templates(ts: Vec<Template>) -> Result<Self> {
-        Ok(Encoder {
-            definitions: Definitions::new_from_templates(ts)?,
-            context: Context::new(),
-        })
+        Ok(Self::with_definitions(Arc::new(Definitions::new_from_templates(ts)?)))
     }
 
     pub fn new_from_xml(text: &str) -> Result<Self> {
-        Ok(Encoder {
-            definitions: Definitions::new_from_xml(text)?,
-            context: Context::new(),
-        })
+        Ok(Self::with_definitions(Arc::new(Definitions::new_from_xml(text)?)))
+    }
+
+    /// Create encoder that uses already parsed definitions. The definitions can be shared with other
+    /// decoders and encoders; each of them keeps its own dictionaries.
+    pub fn with_definitions(definitions: Arc<Definitions>) -> Self {
+        Encoder {
+            context: Context::new(definitions.layout.size()),
+            definitions,
+            transactional: true,
+            reset_templates: HashSet::new(),
+            stats: None,
+            trace: None,
+        }
+    }
+
+    /// Definitions used by the encoder.
+    pub fn definitions(&self) -> &Arc<Definitions> {
+        &self.definitions
     }
 
     pub fn reset(&mut self) {
         self.context.reset()
     }
 
+    /// Reset a single dictionary, e.g. the "template" dictionary of one template. It is an error if the template,
+    /// the application type or the user defined dictionary is unknown.
+    pub fn reset_dictionary(&mut self, dictionary: &DictionaryId) -> Result<()> {
+        for slot in self.definitions.dictionary_slots(dictionary)? {
+            self.context.clear(slot);
+        }
+        Ok(())
+    }
+
+    /// Assigned entries of all dictionaries, for inspection of the encoder state. The entries that are undefined
+    /// are not listed.
+    pub fn dictionary_entries(&self) -> Vec<DictionaryEntry> {
+        self.definitions.dictionary_entries(&self.context)
+    }
+
+    /// Take a snapshot of all dictionaries, e.g. to save it with [`Snapshot::to_bytes`][crate::Snapshot::to_bytes].
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::new(&self.definitions, &self.context)
+    }
+
+    /// Replace all dictionaries with the `snapshot`. It is an error if the snapshot was taken
+    /// with different definitions or an entry has a value of another type than its fields; the dictionaries
+    /// are left unchanged then.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<()> {
+        snapshot.check(&self.definitions)?;
+        self.context.restore(&snapshot.entries);
+        Ok(())
+    }
+
+    /// Enable or disable transactional dictionary updates (enabled by default).
+    /// When enabled, dictionary changes made while encoding a message are discarded if the message fails to encode,
+    /// so the following messages are encoded against the same state as if the failed message was never encoded.
+    /// Disabling it saves journaling of the changed entries on latency-sensitive paths.
+    pub fn set_transactional(&mut self, transactional: bool) {
+        self.transactional = transactional;
+    }
+
+    /// Reset all dictionaries after a message with one of the template `ids` is encoded, in addition to the templates
+    /// with `reset="yes"` attribute. Replaces the previously set ids; the empty list turns it off.
+    pub fn set_reset_templates(&mut self, ids: &[u32]) -> Result<()> {
+        if let Some(id) = ids.iter().find(|id| !self.definitions.templates_by_id.contains_key(id)) {
+            return Err(Error::fast(FastErrorCode::D9, format!("Unknown template id: {}", id))); // [ERR D9]
+        }
+        self.reset_templates = ids.iter().copied().collect();
+        Ok(())
+    }
+
+    /// Enable or disable collecting of statistics (disabled by default): the number of messages and bytes
+    /// per template, and how each field is encoded. Enabling resets the counters.
+    pub fn set_stats(&mut self, enabled: bool) {
+        self.stats = if enabled { Some(Stats::default()) } else { None };
+    }
+
+    /// Snapshot of the statistics if they are enabled with [`Encoder::set_stats`].
+    pub fn stats(&self) -> Option<Stats> {
+        self.stats.clone()
+    }
+
+    /// Enable or disable tracing (disabled by default). When enabled, the encoder records the bytes, presence maps
+    /// and fields of each message, see [`Trace`][crate::Trace]. Tracing slows encoding down, it is meant for debugging.
+    pub fn set_trace(&mut self, enabled: bool) {
+        self.trace = if enabled { Some(Trace::default()) } else { None };
+    }
+
+    /// Trace of the last message, including a message that failed to encode, if tracing is enabled with
+    /// [`Encoder::set_trace`].
+    pub fn trace(&self) -> Option<&Trace> {
+        self.trace.as_ref()
+    }
+
     pub fn encode_vec(&mut self, msg: &mut impl MessageVisitor) -> Result<Vec<u8>> {
         let mut buf = BytesMut::new();
         self.encode_writer(&mut buf, msg)?;
@@ -57,34 +149,70 @@ impl Encoder {
         self.encode_writer(&mut wrt, msg)
     }
 
+    /// Encode single message preceded by its length encoded as `length`, as used in block-framed streams.
+    /// The dictionary changes are undone if the block can't be written, e.g. if the message is too long for `length`.
+    pub fn encode_block(&mut self, wrt: &mut impl Writer, length: BlockLength, msg: &mut impl MessageVisitor) -> Result<()> {
+        let res = self.transaction(self.transactional, |e| {
+            let mut block = BlockWriter::new(wrt, length);
+            EncoderContext::new(e, &mut block, msg).encode_template()?;
+            block.finish()
+        });
+        self.finish_trace();
+        res
+    }
+
     pub fn encode_writer(&mut self, wrt: &mut impl Writer, msg: &mut impl MessageVisitor) -> Result<()> {
-        EncoderContext::new(self, wrt, msg).encode_template()
+        let res = self.transaction(self.transactional, |e| EncoderContext::new(e, wrt, msg).encode_template());
+        self.finish_trace();
+        res
+    }
+
+    fn finish_trace(&mut self) {
+        if let Some(trace) = self.trace.as_mut() {
+            trace.set_offsets();
+        }
+    }
+}
+
+impl Transactional for Encoder {
+    fn context_mut(&mut self) -> &mut Context {
+        &mut self.context
     }
 }
 
 /// Processing context of the encoder. It represents context state during one message encoding.
 /// Created when it starts encoding a new message and destroyed after encoding of a message.
 pub(crate) struct EncoderContext<'a> {
-    pub(crate) definitions: &'a mut Definitions,
+    pub(crate) definitions: &'a Definitions,
     pub(crate) context: &'a mut Context,
     pub(crate) wrt: Box<&'a mut dyn Writer>,
     pub(crate) msg: Box<&'a mut dyn MessageVisitor>,
 
-    // The current template id.
+    // The current template (as index in definitions).
     // It is updated when a template identifier is encountered in the stream. A static template reference can also change
     // the current template as described in the Template Reference Instruction section.
-    pub(crate) template_id: Stacked<u32>,
-
-    // The dictionary set and initial value are described in the Operators section.
-    pub(crate) dictionary: Stacked<Dictionary>,
+    pub(crate) template_index: Stacked<usize>,
 
-    // The current application type is initially the special type `any`. The current application type changes when the processor
-    // encounters an element containing a `typeRef` element. The new type is applicable to the instructions contained within
-    // the element. The `typeRef` can appear in the <template>, <group> and <sequence> elements.
-    pub(crate) type_ref: Stacked<TypeRef>,
+    // The current application type (as index in dictionary layout) is initially the special type `any`. The current
+    // application type changes when the processor encounters an element containing a `typeRef` element. The new type is
+    // applicable to the instructions contained within the element. The `typeRef` can appear in the <template>, <group>
+    // and <sequence> elements.
+    pub(crate) type_index: Stacked<usize>,
 
     // The presence map of the current segment.
     pub(crate) presence_map: Stacked<PresenceMap>,
+
+    // Templates which messages reset all dictionaries, in addition to the ones with `reset="yes"` attribute.
+    pub(crate) reset_templates: &'a HashSet<u32>,
+
+    // Statistics to update if enabled, and the template id of the current message.
+    pub(crate) stats: Option<&'a mut Stats>,
+    pub(crate) stats_template_id: u32,
+
+    // Trace to add to if tracing is enabled, and the indexes of its entries where the presence maps
+    // of the current segments go; presence maps are written after the fields of their segments.
+    pub(crate) trace: Option<&'a mut Trace>,
+    pub(crate) trace_segments: Vec<usize>,
 }
 
 impl<'a> EncoderContext<'a> {
@@ -92,15 +220,22 @@ impl<'a> EncoderContext<'a> {
                       w: &'a mut impl Writer,
                       m: &'a mut impl MessageVisitor,
     ) -> Self {
+        if let Some(trace) = d.trace.as_mut() {
+            trace.clear();
+        }
         Self {
-            definitions: &mut d.definitions,
+            definitions: &d.definitions,
             context: &mut d.context,
             wrt: Box::new(w),
             msg: Box::new(m),
-            template_id: Stacked::new_empty(),
-            dictionary: Stacked::new(Dictionary::Global),
-            type_ref: Stacked::new(TypeRef::Any),
+            template_index: Stacked::new_empty(),
+            type_index: Stacked::new(0),
             presence_map: Stacked::new(PresenceMap::new_empty()),
+            reset_templates: &d.reset_templates,
+            stats: d.stats.as_mut(),
+            stats_template_id: 0,
+            trace: d.trace.as_mut(),
+            trace_segments: Vec::new(),
         }
     }
 
@@ -112,16 +247,20 @@ impl<'a> EncoderContext<'a> {
             .ok_or_else(|| Error::Dynamic(format!("Unknown template name: {}", template_name)))?
             .clone();
 
+        if let Some(stats) = self.stats.as_deref_mut() {
+            stats.start_template(template.id, &template.name);
+            self.stats_template_id = template.id;
+        }
+
         let mut buf = BytesMut::new();
-        self.encode_template_id(&mut buf, template.id)?;
+        self.encode_template_id(&mut buf, &template)?;
 
         // Update some context variables
-        let has_dictionary = self.switch_dictionary(&template.dictionary);
-        let has_type_ref = self.switch_type_ref(&template.type_ref);
+        let has_type_ref = self.switch_type_ref(template.type_index);
 
-        self.encode_instructions(&mut buf, &template.instructions)?;
+        self.encode_instructions(&mut buf, &template.instructions)
+            .map_err(|e| e.at_template(template.id, &template.name, None))?;
 
-        if has_dictionary { self.restore_dictionary() }
         if has_type_ref { self.restore_type_ref() }
 
         self.drop_template_id();
@@ -130,25 +269,50 @@ impl<'a> EncoderContext<'a> {
         self.write_presence_map(&mut buf2)?;
         buf2.write_buf(buf.as_ref())?;
 
-        self.wrt.write_buf(buf2.as_ref()) // presence map + template_id + instructions
+        if let Some(stats) = self.stats.as_deref_mut() {
+            stats.add_message(template.id, buf2.len());
+        }
+        if let Some(trace) = self.trace.as_deref_mut() {
+            trace.data = buf2.to_vec();
+        }
+        self.wrt.write_buf(buf2.as_ref())?; // presence map + template_id + instructions
+
+        if template.reset || self.reset_templates.contains(&template.id) {
+            self.context.reset();
+        }
+        Ok(())
     }
 
     // Write presence map to the stream and remove if from the stack.
     fn write_presence_map(&mut self, buf: &mut dyn Writer) -> Result<()> {
         let presence_map = self.presence_map.pop().unwrap();
-        buf.write_presence_map(presence_map.bitmap, presence_map.size)
+        let Some(trace) = self.trace.as_deref_mut() else {
+            return buf.write_presence_map(&presence_map);
+        };
+        let mut counter = CountingWriter::new(buf);
+        counter.write_presence_map(&presence_map)?;
+        let index = self.trace_segments.pop().unwrap_or(0);
+        trace.entries.insert(index, TraceEntry::presence_map(0, counter.count, &presence_map));
+        Ok(())
+    }
+
+    // Start a new segment of the trace, its presence map is added before the fields recorded after this call.
+    fn push_trace_segment(&mut self) {
+        if let Some(trace) = self.trace.as_deref() {
+            self.trace_segments.push(trace.entries.len());
+        }
     }
 
     // Encode template id to the buffer and change the current processing context accordingly.
-    fn encode_template_id(&mut self, buf: &mut dyn Writer, template_id: u32) -> Result<()> {
-        self.template_id.push(template_id);
+    fn encode_template_id(&mut self, buf: &mut dyn Writer, template: &Template) -> Result<()> {
+        self.template_index.push(template.index);
         let instruction = self.definitions.template_id_instruction.clone();
-        instruction.inject(self, buf, &Some(Value::UInt32(template_id)))
+        self.inject_recorded(buf, &instruction, TraceKind::TemplateId, &Some(Value::UInt32(template.id)))
     }
 
     // Stop processing the current template id, restore the previous value in the processing context.
     fn drop_template_id(&mut self) {
-        self.template_id.pop();
+        self.template_index.pop();
     }
 
     fn encode_instructions(&mut self, buf: &mut dyn Writer, instructions: &[Instruction]) -> Result<()> {
@@ -172,12 +336,46 @@ impl<'a> EncoderContext<'a> {
     }
 
     fn encode_field(&mut self, buf: &mut dyn Writer, instruction: &Instruction) -> Result<()> {
-        let value = self.msg.get_value(&instruction.name, &instruction.value_type)?;
-        instruction.inject(self, buf, &value)
+        self.msg.get_field(FieldInfo::new(instruction))
+            .and_then(|value| self.inject_field(buf, instruction, &value))
+            .map_err(|e| e.at_field(&instruction.name, None))
+    }
+
+    fn inject_field(&mut self, buf: &mut dyn Writer, instruction: &Instruction, value: &Option<Value>) -> Result<()> {
+        self.inject_recorded(buf, instruction, TraceKind::Field, value)
+    }
+
+    // Inject the value and add it to the statistics and the trace if they are enabled.
+    fn inject_recorded(&mut self, buf: &mut dyn Writer, instruction: &Instruction, kind: TraceKind, value: &Option<Value>) -> Result<()> {
+        if self.stats.is_none() && self.trace.is_none() {
+            return instruction.inject(self, buf, value);
+        }
+        let bit = self.presence_map.peek().map(|p| p.pos);
+        let previous = match (&self.trace, instruction.operator) {
+            (Some(_), Operator::Copy | Operator::Increment | Operator::Delta | Operator::Tail) => {
+                self.context.get(self.slot(instruction))
+            }
+            _ => None,
+        };
+        let mut counter = CountingWriter::new(buf);
+        instruction.inject(self, &mut counter, value)?;
+        let size = counter.count;
+        if let (Some(stats), TraceKind::Field) = (self.stats.as_deref_mut(), &kind) {
+            stats.add_field(self.stats_template_id, instruction, size, value.is_none());
+        }
+        if let Some(trace) = self.trace.as_deref_mut() {
+            let pmap_bit = match (bit, self.presence_map.peek()) {
+                (Some(pos), Some(p)) if p.pos > pos => Some(p.bit(pos)),
+                _ => None,
+            };
+            trace.entries.push(TraceEntry::field(0, size, kind, instruction, pmap_bit, previous, value.clone()));
+        }
+        Ok(())
     }
 
     fn encode_segment(&mut self, buf: &mut dyn Writer, instructions: &[Instruction]) -> Result<()> {
         self.presence_map.push(PresenceMap::new_empty());
+        self.push_trace_segment();
         let mut buf2 = BytesMut::new();
         self.encode_instructions(&mut buf2, instructions)?;
         self.write_presence_map(buf)?;
@@ -197,16 +395,14 @@ impl<'a> EncoderContext<'a> {
             self.pmap_set_next_bit(true);
         }
 
-        let has_dictionary = self.switch_dictionary(&instruction.dictionary);
-        let has_type_ref = self.switch_type_ref(&instruction.type_ref);
+        let has_type_ref = self.switch_type_ref(instruction.type_index);
 
-        if instruction.has_pmap.get() {
-            self.encode_segment(buf, &instruction.instructions)?;
+        if instruction.has_pmap {
+            self.encode_segment(buf, &instruction.instructions)
         } else {
-            self.encode_instructions(buf, &instruction.instructions)?;
-        }
+            self.encode_instructions(buf, &instruction.instructions)
+        }.map_err(|e| e.at_field(&instruction.name, None))?;
 
-        if has_dictionary { self.restore_dictionary() }
         if has_type_ref { self.restore_type_ref() }
 
         self.msg.release_group()
@@ -216,31 +412,31 @@ impl<'a> EncoderContext<'a> {
         let length = self.msg.select_sequence(&instruction.name)?;
         let length_instruction = instruction.instructions.get(0).unwrap();
 
-        let has_dictionary = self.switch_dictionary(&instruction.dictionary);
-        let has_type_ref = self.switch_type_ref(&instruction.type_ref);
+        let has_type_ref = self.switch_type_ref(instruction.type_index);
         match length {
             None => {
                 if instruction.is_optional() {
-                    length_instruction.inject(self, buf, &None)?;
+                    self.inject_field(buf, length_instruction, &None)
+                        .map_err(|e| e.at_field(&format!("{}/{}", instruction.name, length_instruction.name), None))?;
                 } else {
                     return Err(Error::Dynamic(format!("Missing mandatory sequence: {}", instruction.name)));
                 }
             }
             Some(length) => {
-                length_instruction.inject(self, buf, &Some(Value::UInt32(length as u32)))?;
+                self.inject_field(buf, length_instruction, &Some(Value::UInt32(length as u32)))
+                    .map_err(|e| e.at_field(&format!("{}/{}", instruction.name, length_instruction.name), None))?;
                 for idx in 0..length {
                     self.msg.select_sequence_item(idx)?;
-                    if instruction.has_pmap.get() {
-                        self.encode_segment(buf, &instruction.instructions[1..])?;
+                    if instruction.has_pmap {
+                        self.encode_segment(buf, &instruction.instructions[1..])
                     } else {
-                        self.encode_instructions(buf, &instruction.instructions[1..])?;
-                    }
+                        self.encode_instructions(buf, &instruction.instructions[1..])
+                    }.map_err(|e| e.at_field(&format!("{}[{}]", instruction.name, idx), None))?;
                     self.msg.release_sequence_item()?;
                 }
                 self.msg.release_sequence()?;
             }
         }
-        if has_dictionary { self.restore_dictionary() }
         if has_type_ref { self.restore_type_ref() }
 
         Ok(())
@@ -250,27 +446,30 @@ impl<'a> EncoderContext<'a> {
         let is_dynamic = instruction.name.is_empty();
 
         if is_dynamic {
-            let template_name = match self.msg.select_template_ref(&instruction.name, true)? {
-                Some(name) => name,
+            let template = match self.msg.select_template_ref_key(&instruction.name, true)? {
+                Some(TemplateKey::Id(id)) => self.definitions.templates_by_id
+                    .get(&id)
+                    .ok_or_else(|| Error::fast(FastErrorCode::D9, format!("Unknown template id: {}", id)))? // [ERR D9]
+                    .clone(),
+                Some(TemplateKey::Name(name)) => self.definitions.templates_by_name
+                    .get(&name)
+                    .ok_or_else(|| Error::fast(FastErrorCode::D9, format!("Unknown template name: {}", name)))? // [ERR D9]
+                    .clone(),
                 None => {
                     return Err(Error::Dynamic(format!("Missing mandatory template reference: {}", instruction.name)))
                 }
             };
-            let template = self.definitions.templates_by_name
-                .get(&template_name)
-                .ok_or_else(|| Error::Dynamic(format!("Unknown template name: {}", template_name)))? // [ErrD09]
-                .clone();
 
             let mut buf2 = BytesMut::new();
             self.presence_map.push(PresenceMap::new_empty());
-            self.encode_template_id(&mut buf2, template.id)?;
+            self.push_trace_segment();
+            self.encode_template_id(&mut buf2, &template)?;
 
-            let has_dictionary = self.switch_dictionary(&template.dictionary);
-            let has_type_ref = self.switch_type_ref(&template.type_ref);
+            let has_type_ref = self.switch_type_ref(template.type_index);
 
-            self.encode_instructions(&mut buf2, &template.instructions)?;
+            self.encode_instructions(&mut buf2, &template.instructions)
+                .map_err(|e| e.at_field(&template.name, None))?;
 
-            if has_dictionary { self.restore_dictionary() }
             if has_type_ref { self.restore_type_ref() }
 
             self.drop_template_id();
@@ -278,42 +477,26 @@ impl<'a> EncoderContext<'a> {
             self.write_presence_map(buf)?;
             buf.write_buf(buf2.as_ref())?;
         } else {
-            self.msg.select_template_ref(&instruction.name, false)?;
+            self.msg.select_template_ref_key(&instruction.name, false)?;
             let template = self.definitions.templates_by_name
                 .get(&instruction.name)
-                .ok_or_else(|| Error::Dynamic(format!("Unknown template: {}", instruction.name)))? // [ErrD09]
+                .ok_or_else(|| Error::fast(FastErrorCode::D8, format!("Unknown template: {}", instruction.name)))? // [ERR D8]
                 .clone();
 
-            let has_dictionary = self.switch_dictionary(&template.dictionary);
-            let has_type_ref = self.switch_type_ref(&template.type_ref);
+            let has_type_ref = self.switch_type_ref(template.type_index);
 
-            self.encode_instructions(buf, &template.instructions)?;
+            self.encode_instructions(buf, &template.instructions)
+                .map_err(|e| e.at_field(&template.name, None))?;
 
-            if has_dictionary { self.restore_dictionary() }
             if has_type_ref { self.restore_type_ref() }
         }
         self.msg.release_template_ref()
     }
 
     #[inline]
-    fn switch_dictionary(&mut self, dictionary: &Dictionary) -> bool {
-        if *dictionary != Dictionary::Inherit {
-            self.dictionary.push(dictionary.clone());
-            true
-        } else {
-            false
-        }
-    }
-
-    #[inline]
-    fn restore_dictionary(&mut self) {
-        _ = self.dictionary.pop();
-    }
-
-    #[inline]
-    fn switch_type_ref(&mut self, type_ref: &TypeRef) -> bool {
-        if *type_ref != TypeRef::Any {
-            self.type_ref.push(type_ref.clone());
+    fn switch_type_ref(&mut self, type_index: usize) -> bool {
+        if type_index != 0 {
+            self.type_index.push(type_index);
             true
         } else {
             false
@@ -322,7 +505,7 @@ impl<'a> EncoderContext<'a> {
 
     #[inline]
     fn restore_type_ref(&mut self) {
-        _ = self.type_ref.pop();
+        _ = self.type_index.pop();
     }
 
     #[inline]
@@ -332,42 +515,29 @@ impl<'a> EncoderContext<'a> {
 
     #[inline]
     pub(crate) fn ctx_set(&mut self, i: &Instruction, v: &Option<Value>) {
-        self.context.set(self.make_dict_type(), i.key.clone(), v);
+        self.context.set(self.slot(i), v.clone());
     }
 
     #[inline]
     pub(crate) fn ctx_get(&mut self, i: &Instruction) -> Result<Option<Option<Value>>> {
-        let v = self.context.get(self.make_dict_type(), &i.key);
+        let v = self.context.get(self.slot(i));
         if let Some(Some(ref v)) = v {
             if !i.value_type.matches_type(v) {
                 // It is a dynamic error [ERR D4] if the field of an operator accessing an entry does not have
                 // the same type as the value of the entry.
-                return Err(Error::Runtime(format!("field {} has wrong value type in context", i.name)));  // [ERR D4]
+                return Err(Error::fast(FastErrorCode::D4, format!("field {} has wrong value type in context", i.name)));  // [ERR D4]
             }
         }
         Ok(v)
     }
 
-    fn make_dict_type(&self) -> DictionaryType {
-        let dictionary = self.dictionary.must_peek();
-        match dictionary {
-            Dictionary::Inherit => unreachable!(),
-            Dictionary::Global => {
-                DictionaryType::Global
-            }
-            Dictionary::Template => {
-                DictionaryType::Template(*self.template_id.must_peek())
-            }
-            Dictionary::Type => {
-                let name = match self.type_ref.must_peek() {
-                    TypeRef::Any => Rc::from("__any__"),
-                    TypeRef::ApplicationType(name) => name.clone(),
-                };
-                DictionaryType::Type(name)
-            }
-            Dictionary::UserDefined(name) => {
-                DictionaryType::UserDefined(name.clone())
-            }
+    // Context storage slot of the instruction's dictionary entry.
+    #[inline]
+    fn slot(&self, i: &Instruction) -> usize {
+        match i.slot {
+            DictionarySlot::Static(k) => k,
+            DictionarySlot::Template(k) => self.definitions.layout.template_slot(*self.template_index.must_peek(), k),
+            DictionarySlot::Type(k) => self.definitions.layout.type_slot(*self.type_index.must_peek(), k),
         }
     }
 }
@@ -1,8 +1,9 @@
 use std::io::Write;
 
-use bytes::BufMut;
+use bytes::{BufMut, BytesMut};
 
-use crate::{Error, Result};
+use crate::{Error, PresenceMap, Result};
+use crate::common::block::BlockLength;
 
 /// A trait that provides methods for writing basic primitive types.
 pub trait Writer {
@@ -16,37 +17,17 @@ pub trait Writer {
         Ok(())
     }
 
-    /// Write the presence map.
-    fn write_presence_map(&mut self, bitmap: u64, size: u8) -> Result<()> {
-        if size == 0 {
-            self.write_u8(0x80)?;
-            return Ok(());
-        }
-        if size % 7 != 0 {
-            return Err(Error::Runtime("write_presence_map: size must be multiple of 7".to_string()));
-        }
-
-        let mut trim = true;
-        let len = (size / 7) as usize;
-        let mut bitmap = bitmap;
-        let mut buf: Vec<u8> = Vec::with_capacity(len);
-        for _ in 0..len {
-            let b7 = (bitmap & 0x7f) as u8;
-            if trim && b7 == 0 {
-                // trim trailing zeros
-            } else {
-                buf.push(b7);
-                trim = false;
-            }
-            bitmap >>= 7;
-        }
-        if buf.len() == 0 {
-            buf.push(0x00);
+    /// Write the presence map. Trailing bytes with no bits set are not written.
+    fn write_presence_map(&mut self, pmap: &PresenceMap) -> Result<()> {
+        let len = (0..pmap.groups())
+            .rposition(|i| pmap.group(i) != 0)
+            .map_or(1, |i| i + 1);
+        for i in 0..len - 1 {
+            self.write_u8(pmap.group(i))?;
         }
         // set stop bit
-        *buf.get_mut(0).unwrap() |= 0x80;
-        buf.reverse();
-        self.write_buf(&buf)
+        let last = if pmap.groups() == 0 { 0 } else { pmap.group(len - 1) };
+        self.write_u8(last | 0x80)
     }
 
     fn write_uint(&mut self, value: u64) -> Result<()> {
@@ -207,6 +188,62 @@ impl Writer for StreamWriter<'_> {
     }
 }
 
+/// Writer of one block of a block-framed stream. Collects the message and writes it prefixed with
+/// its length to the underlying writer when [`BlockWriter::finish`] is called.
+pub struct BlockWriter<'a, W: Writer + ?Sized> {
+    wrt: &'a mut W,
+    length: BlockLength,
+    buf: BytesMut,
+}
+
+impl<'a, W: Writer + ?Sized> BlockWriter<'a, W> {
+    pub fn new(wrt: &'a mut W, length: BlockLength) -> Self {
+        Self { wrt, length, buf: BytesMut::new() }
+    }
+
+    /// Write the block length and the collected data to the underlying writer.
+    pub fn finish(self) -> Result<()> {
+        self.length.write(self.wrt, self.buf.len())?;
+        self.wrt.write_buf(&self.buf)
+    }
+}
+
+impl<W: Writer + ?Sized> Writer for BlockWriter<'_, W> {
+    fn write_u8(&mut self, value: u8) -> Result<()> {
+        self.buf.put_u8(value);
+        Ok(())
+    }
+
+    fn write_buf(&mut self, buf: &[u8]) -> Result<()> {
+        self.buf.put(buf);
+        Ok(())
+    }
+}
+
+/// Wrapper around a writer that counts the written bytes.
+pub(crate) struct CountingWriter<'a> {
+    wrt: &'a mut dyn Writer,
+    pub(crate) count: usize,
+}
+
+impl<'a> CountingWriter<'a> {
+    pub fn new(wrt: &'a mut dyn Writer) -> Self {
+        Self { wrt, count: 0 }
+    }
+}
+
+impl Writer for CountingWriter<'_> {
+    fn write_u8(&mut self, value: u8) -> Result<()> {
+        self.count += 1;
+        self.wrt.write_u8(value)
+    }
+
+    fn write_buf(&mut self, buf: &[u8]) -> Result<()> {
+        self.count += buf.len();
+        self.wrt.write_buf(buf)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -214,26 +251,26 @@ mod tests {
     #[test]
     fn write_presence_map() {
         struct TestCase {
-            pmap: (u64, u8),
+            pmap: PresenceMap,
             value: Vec<u8>,
         }
         let test_cases: Vec<TestCase> = vec![
             TestCase {
-                pmap: (0b0, 7),
+                pmap: PresenceMap::new(0b0, 7),
                 value: vec![0x80],
             },
             TestCase {
-                pmap: (0b1, 7),
+                pmap: PresenceMap::new(0b1, 7),
                 value: vec![0x81],
             },
             TestCase {
-                pmap: (0b11110001111, 14),
+                pmap: PresenceMap::new(0b11110001111, 14),
                 value: vec![0x0f, 0x8f],
             },
         ];
         for tc in test_cases {
             let mut buf = bytes::BytesMut::new();
-            buf.write_presence_map(tc.pmap.0, tc.pmap.1).unwrap();
+            buf.write_presence_map(&tc.pmap).unwrap();
             assert_eq!(buf.to_vec(), tc.value);
         }
     }
@@ -175,10 +175,64 @@
 //! [`crate::text::JsonMessageFactory`][crate::JsonMessageFactory] but more likely you will want to construct
 //! you own message structs.
 //!
-pub use base::{decimal::Decimal, value::Value, value::ValueType};
-pub use base::message::{MessageFactory, MessageVisitor};
-pub use decoder::{decoder::Decoder, reader::Reader};
-pub use encoder::{encoder::Encoder, writer::Writer};
+//! ## Zero-copy decoding
+//!
+//! Implement [`fastlib::MessageFactoryRef`][crate::MessageFactoryRef] to receive [`fastlib::ValueRef`][crate::ValueRef]
+//! values that borrow unicode strings and byte vectors from the input buffer instead of allocating them:
+//!
+//! ```rust,ignore
+//! use fastlib::Decoder;
+//!
+//! let mut decoder = Decoder::new_from_xml(include_str!("templates.xml"))?;
+//! let mut msg = MyMessageFactoryRef::new();
+//!
+//! // Decode the first message in the buffer; returns the number of bytes consumed.
+//! let n = decoder.decode_slice(&raw_data, &mut msg)?;
+//! ```
+//!
+//! ## Filtering messages
+//!
+//! Implement [`fastlib::TryMessageFactory`][crate::TryMessageFactory] to decide about the message while it is decoded.
+//! Each callback returns [`fastlib::Control::Continue`][crate::Control::Continue],
+//! [`fastlib::Control::Skip`][crate::Control::Skip] to decode the rest of the message without passing it to the factory,
+//! or an error such as [`fastlib::Error::Aborted`][crate::Error::Aborted] which stops decoding and is returned to the caller.
+//! The skipped message still updates the dictionaries, so the following messages are decoded correctly.
+//!
+//! ## Decoding partially received data
+//!
+//! [`fastlib::PushDecoder`][crate::PushDecoder] buffers bytes as they arrive and decodes complete messages only.
+//! Dictionary changes of a message that is not received completely are rolled back:
+//!
+//! ```rust,ignore
+//! use fastlib::{DecodeStatus, Decoder, PushDecoder};
+//!
+//! let mut decoder = PushDecoder::new(Decoder::new_from_xml(include_str!("templates.xml"))?);
+//! decoder.feed(&chunk);
+//! while decoder.decode(&mut msg)? == DecodeStatus::Message {
+//!     // process the message
+//! }
+//! ```
+//!
+//! ## Sharing definitions between threads
+//!
+//! [`fastlib::Definitions`][crate::Definitions] are immutable and can be parsed once and shared between decoders
+//! and encoders, e.g. one decoder per channel on its own thread. Each decoder/encoder keeps its own dictionaries:
+//!
+//! ```rust,ignore
+//! use std::sync::Arc;
+//! use fastlib::{Decoder, Definitions};
+//!
+//! let definitions = Arc::new(Definitions::new_from_xml(include_str!("templates.xml"))?);
+//! let mut decoder_a = Decoder::with_definitions(definitions.clone());
+//! let mut decoder_b = Decoder::with_definitions(definitions);
+//! std::thread::spawn(move || decoder_b.decode_vec(raw_data, &mut msg));
+//! ```
+//!
+pub use base::{decimal::Decimal, field::FieldInfo, pmap::PresenceMap, types::{Operator, Presence}, value::Value, value::ValueRef, value::ValueType};
+pub use base::message::{Control, MessageFactory, MessageFactoryRef, MessageVisitor, TemplateKey, TryMessageFactory};
+pub use common::{block::BlockLength, context::{DictionaryEntry, DictionaryId}, definitions::Definitions, snapshot::Snapshot, stats::{FieldStats, Stats, TemplateStats}, trace::{Trace, TraceEntry, TraceKind}};
+pub use decoder::{decoder::Decoder, options::{DecoderLimits, DecoderOptions, Strictness}, packet::{PacketLayout, Preamble}, push::{DecodeStatus, PushDecoder}, reader::{BlockReader, Reader}};
+pub use encoder::{encoder::Encoder, writer::{BlockWriter, Writer}};
 pub use model::ModelFactory;
 pub use text::{JsonMessageFactory, TextMessageFactory, TextMessageVisitor};
 
@@ -221,6 +275,13 @@ pub enum Error {
     #[error("Runtime Error: {0}")]
     Runtime(String),
 
+    /// Errors defined by the FAST specification, identified by their code.
+    #[error("{} Error [{code}]: {message}", .code.kind())]
+    Fast {
+        code: FastErrorCode,
+        message: String,
+    },
+
     ///! End of file/stream reached.
     #[error("End of file/stream reached")]
     Eof,
@@ -229,6 +290,14 @@ pub enum Error {
     #[error("Unexpected end of file/stream reached")]
     UnexpectedEof,
 
+    /// Decoder limit exceeded, see [`DecoderLimits`].
+    #[error("Limit exceeded: {0}")]
+    LimitExceeded(String),
+
+    /// Decoding aborted by the message factory, see [`TryMessageFactory`].
+    #[error("Aborted: {0}")]
+    Aborted(Box<dyn std::error::Error + Send + Sync>),
+
     #[error(transparent)]
     IoError(#[from] std::io::Error),
 
@@ -241,6 +310,226 @@ pub enum Error {
     #[error(transparent)]
     FromUtf8Error(#[from] std::string::FromUtf8Error),
 
+    #[error(transparent)]
+    Utf8Error(#[from] std::str::Utf8Error),
+
     #[error(transparent)]
     XMLTreeError(#[from] roxmltree::Error),
+
+    /// Error happened while decoding or encoding a message, with the location in the message where it happened.
+    #[error("{source} ({location})")]
+    Located {
+        source: Box<Error>,
+        location: Box<ErrorLocation>,
+    },
+}
+
+impl Error {
+    /// Location in the message where the error happened, if known.
+    pub fn location(&self) -> Option<&ErrorLocation> {
+        match self {
+            Error::Located { location, .. } => Some(location),
+            _ => None,
+        }
+    }
+
+    /// The error without location.
+    pub fn inner(&self) -> &Error {
+        match self {
+            Error::Located { source, .. } => source,
+            _ => self,
+        }
+    }
+
+    /// Code of the error as defined by the FAST specification, if the error is one of those.
+    pub fn code(&self) -> Option<FastErrorCode> {
+        match self {
+            Error::Fast { code, .. } => Some(*code),
+            Error::Located { source, .. } => source.code(),
+            Error::XMLTreeError(_) => Some(FastErrorCode::S1),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn fast(code: FastErrorCode, message: impl Into<String>) -> Self {
+        Error::Fast { code, message: message.into() }
+    }
+
+    // Classify the error with FAST error `code`, keeping its message.
+    pub(crate) fn with_code(self, code: FastErrorCode) -> Self {
+        match self {
+            Error::Fast { message, .. } => Error::Fast { code, message },
+            Error::Static(message) | Error::Dynamic(message) | Error::Runtime(message) => Error::Fast { code, message },
+            e => Error::Fast { code, message: e.to_string() },
+        }
+    }
+
+    // Add the field path `segment` to the location of the error as it unwinds from a field, group, sequence item or
+    // template reference. The `offset` is recorded when the location is created, i.e. at the innermost field.
+    // End of data errors are returned as is, so they can be matched by callers.
+    pub(crate) fn at_field(self, segment: &str, offset: Option<usize>) -> Self {
+        match self {
+            Error::Eof | Error::UnexpectedEof => self,
+            Error::Located { source, mut location } => {
+                if !segment.is_empty() {
+                    location.field_path = if location.field_path.is_empty() {
+                        segment.to_string()
+                    } else {
+                        format!("{}/{}", segment, location.field_path)
+                    };
+                }
+                Error::Located { source, location }
+            }
+            _ => Error::Located {
+                source: Box::new(self),
+                location: Box::new(ErrorLocation {
+                    offset,
+                    field_path: segment.to_string(),
+                    ..Default::default()
+                }),
+            },
+        }
+    }
+
+    // Make the offset of the location relative to the input that starts `n` bytes before the message.
+    pub(crate) fn offset_by(self, n: usize) -> Self {
+        match self {
+            Error::Located { source, mut location } => {
+                location.offset = location.offset.map(|o| o + n);
+                Error::Located { source, location }
+            }
+            e => e,
+        }
+    }
+
+    // Add the message template to the location of the error as it unwinds from the template.
+    pub(crate) fn at_template(self, id: u32, name: &str, offset: Option<usize>) -> Self {
+        match self.at_field(name, offset) {
+            Error::Located { source, mut location } => {
+                location.template_id = Some(id);
+                location.template_name = Some(name.to_string());
+                Error::Located { source, location }
+            }
+            e => e,
+        }
+    }
+}
+
+/// Error codes defined by the FAST 1.1 specification.
+///
+/// Static errors (`S*`) are found in templates, dynamic errors (`D*`) in encoded data or application values, and
+/// reportable errors (`R*`) are conditions that decoders and encoders may report or tolerate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FastErrorCode {
+    /// Template is not valid XML or does not follow the template schema.
+    S1,
+    /// Operator is not applicable to the field type.
+    S2,
+    /// Initial value cannot be converted to the field type.
+    S3,
+    /// Constant operator has no initial value.
+    S4,
+    /// Default operator of a mandatory field has no initial value.
+    S5,
+    /// Type of an application field cannot be converted to or from the field type.
+    D1,
+    /// Integer value does not fit the bounds of the field type.
+    D2,
+    /// Decimal value cannot be encoded with the operators of its exponent and mantissa.
+    D3,
+    /// Dictionary entry has a different type than the field accessing it.
+    D4,
+    /// Mandatory field is not present, its previous value is undefined and there is no initial value.
+    D5,
+    /// Mandatory field is not present and its previous value is empty.
+    D6,
+    /// Subtraction length is larger than the base value or the previous value of a tail is empty.
+    D7,
+    /// Static template reference points to an unknown template.
+    D8,
+    /// Template identifier in the stream points to an unknown template.
+    D9,
+    /// Length of a sequence is not an unsigned 32-bit integer.
+    D10,
+    /// String does not follow the syntax of the type it is converted to.
+    D11,
+    /// Block length is zero.
+    D12,
+    /// Decimal value cannot be represented by an exponent in the range \[-63, 63\] and a 64-bit mantissa.
+    R1,
+    /// Combined value of a unicode string after a delta or tail operation is not valid UTF-8.
+    R2,
+    /// Unicode string cannot be converted to ASCII.
+    R3,
+    /// Integer value does not fit the integer type it is converted to.
+    R4,
+    /// Decimal value with a fractional part is converted to an integer.
+    R5,
+    /// Integer is encoded with more bytes than needed.
+    R6,
+    /// Presence map is encoded with more bytes than needed.
+    R7,
+    /// Presence map has more bits than required by the instructions.
+    R8,
+    /// String is encoded with more bytes than needed.
+    R9,
+}
+
+impl FastErrorCode {
+    /// Kind of the error: "Static", "Dynamic" or "Reportable".
+    pub fn kind(&self) -> &'static str {
+        use FastErrorCode::*;
+        match self {
+            S1 | S2 | S3 | S4 | S5 => "Static",
+            D1 | D2 | D3 | D4 | D5 | D6 | D7 | D8 | D9 | D10 | D11 | D12 => "Dynamic",
+            R1 | R2 | R3 | R4 | R5 | R6 | R7 | R8 | R9 => "Reportable",
+        }
+    }
+
+    /// Returns `true` for reportable errors (`R*`).
+    pub fn is_reportable(&self) -> bool {
+        self.kind() == "Reportable"
+    }
+}
+
+impl std::fmt::Display for FastErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+
+/// Location in a message where a decoding or encoding error happened.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ErrorLocation {
+    /// Byte offset in the input where the error was detected (decoding only). It is counted from the start of the
+    /// message, except for [`Decoder::decode_packet`] and [`Decoder::messages_with_offsets`] that count it from the
+    /// start of the packet or the buffer.
+    pub offset: Option<usize>,
+
+    /// Id of the message template.
+    pub template_id: Option<u32>,
+
+    /// Name of the message template.
+    pub template_name: Option<String>,
+
+    /// Path to the field, e.g. `MDSecurityDefinition/Legs[2]/LegSymbol`. Sequence items are indexed from 0.
+    pub field_path: String,
+}
+
+impl std::fmt::Display for ErrorLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut sep = "";
+        if let Some(id) = self.template_id {
+            write!(f, "template {}", id)?;
+            sep = ", ";
+        }
+        if !self.field_path.is_empty() {
+            write!(f, "{}field {}", sep, self.field_path)?;
+            sep = ", ";
+        }
+        if let Some(offset) = self.offset {
+            write!(f, "{}offset {}", sep, offset)?;
+        }
+        Ok(())
+    }
 }
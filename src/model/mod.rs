@@ -18,6 +18,9 @@ mod decimal;
 pub struct ModelFactory {
     pub data: Option<TemplateData>,
 
+    /// Name of the template when the message was skipped by the decoder; `data` is `None` in this case.
+    pub skipped: Option<String>,
+
     /// Stores current context name and value.
     /// Here context value can be `ValueData::Group` or `ValueData::Sequence`.
     context: Stacked<(String, ValueData)>,
@@ -31,12 +34,19 @@ impl ModelFactory {
     pub fn new() -> Self {
         Self {
             data: None,
+            skipped: None,
             context: Stacked::new_empty(),
             ref_num: Stacked::new(0),
         }
     }
 }
 
+impl Default for ModelFactory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MessageFactory for ModelFactory {
     fn start_template(&mut self, _id: u32, name: &str) {
         self.context.push((
@@ -50,6 +60,10 @@ impl MessageFactory for ModelFactory {
         self.data = Some(TemplateData { name, value })
     }
 
+    fn skip_template(&mut self, _id: u32, name: &str) {
+        self.skipped = Some(name.to_string());
+    }
+
     fn set_value(&mut self, _id: u32, name: &str, value: Option<Value>) {
         let (_, context) = self.context.must_peek_mut();
         match context {
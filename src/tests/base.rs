@@ -6,7 +6,10 @@ use std::io::Cursor;
 
 use hashbrown::HashMap;
 
-use crate::{Decimal, Error};
+use crate::{Decimal, DecoderLimits, DecoderOptions, DictionaryEntry, DictionaryId, Error, FastErrorCode, FieldInfo};
+use crate::{MessageFactoryRef, MessageVisitor, Result, Strictness, TemplateKey, TextMessageFactory, TextMessageVisitor, ValueRef};
+use crate::base::types::TypeRef;
+use crate::common::context::DictionarySlot;
 use crate::decoder::decoder::Decoder;
 use crate::encoder::encoder::Encoder;
 use crate::model::{ModelFactory, ModelVisitor};
@@ -750,3 +753,554 @@ fn decode_unexpected_eof() {
         Ok(_) => assert!(false, "Expected Err(UnexpectedEof)"),
     }
 }
+
+#[test]
+fn error_codes() {
+    let code = |xml: &str| Decoder::new_from_xml(xml).err().and_then(|e| e.code());
+    assert_eq!(code("<templates"), Some(FastErrorCode::S1));
+    assert_eq!(code(r#"<templates><template name="A" id="1"><decimal name="D" id="1"><tail/></decimal></template></templates>"#), Some(FastErrorCode::S2));
+    assert_eq!(code(r#"<templates><template name="A" id="1"><uInt32 name="U" id="1"><copy value="x"/></uInt32></template></templates>"#), Some(FastErrorCode::S3));
+    assert_eq!(code(r#"<templates><template name="A" id="1"><uInt32 name="U" id="1"><constant/></uInt32></template></templates>"#), Some(FastErrorCode::S4));
+    assert_eq!(code(r#"<templates><template name="A" id="1"><uInt32 name="U" id="1"><default/></uInt32></template></templates>"#), Some(FastErrorCode::S5));
+    assert_eq!(code(r#"<templates><template name="A" id="1"><uInt16 name="U" id="1"/></template></templates>"#), Some(FastErrorCode::S1));
+    assert_eq!(code(r#"<templates><template name="A" id="1"><uInt32 id="1"/></template></templates>"#), Some(FastErrorCode::S1));
+    assert_eq!(code(r#"<templates><template name="A" id="1"><uInt32 name="U"/></template></templates>"#), Some(FastErrorCode::S1));
+
+    let mut d = Decoder::new_from_xml(include_str!("templates/base.xml")).unwrap();
+    let mut msg = LoggingMessageFactory::new();
+    // Unknown template id 99.
+    let err = d.decode_vec(vec![0xc0, 0xe3], &mut msg).unwrap_err();
+    assert_eq!(err.code(), Some(FastErrorCode::D9));
+    assert!(err.to_string().starts_with("Dynamic Error [D9]: Unknown template id: 99"));
+    // Template id has no previous value for the copy operator.
+    let err = d.decode_vec(vec![0x80], &mut msg).unwrap_err();
+    assert_eq!(err.code(), Some(FastErrorCode::D5));
+    // uInt32 value doesn't fit 32 bits.
+    let mut d = Decoder::new_from_xml(r#"<templates><template name="A" id="1"><uInt32 name="U" id="1"/></template></templates>"#).unwrap();
+    let err = d.decode_vec(vec![0xc0, 0x81, 0x10, 0x00, 0x00, 0x00, 0x80], &mut msg).unwrap_err();
+    assert_eq!(err.code(), Some(FastErrorCode::D2));
+    assert_eq!(Error::UnexpectedEof.code(), None);
+}
+
+#[test]
+fn decode_limits() {
+    let limited = |limits: DecoderLimits| {
+        let mut d = Decoder::new_from_xml(include_str!("templates/base.xml")).unwrap();
+        d.set_options(DecoderOptions { limits, ..Default::default() });
+        d
+    };
+    let is_limit_exceeded = |res: Result<()>| matches!(res.unwrap_err().inner(), Error::LimitExceeded(_));
+    let mut msg = LoggingMessageFactory::new();
+
+    // Byte vector with a huge length prefix.
+    let raw = vec![0xc0, 0x83, 0x7f, 0x7f, 0x7f, 0xff, 0x00];
+    let mut d = limited(DecoderLimits { max_string_length: 16, ..Default::default() });
+    assert!(is_limit_exceeded(d.decode_vec(raw.clone(), &mut msg)));
+    assert!(is_limit_exceeded(d.decode_slice(&raw, &mut msg).map(|_| ())));
+    assert!(matches!(limited(DecoderLimits::default()).decode_slice(&raw, &mut msg), Err(Error::UnexpectedEof)));
+
+    let raw = vec![0xc0, 0x83, 0x81, 0xc1, 0x82, 0xb3];
+    let mut d = limited(DecoderLimits { max_message_size: 5, ..Default::default() });
+    let err = d.decode_vec(raw.clone(), &mut msg).unwrap_err();
+    assert!(matches!(err.inner(), Error::LimitExceeded(_)));
+    assert_eq!(err.location().unwrap().field_path, "ByteVector/OptionalVector");
+    limited(DecoderLimits { max_message_size: 6, ..Default::default() }).decode_vec(raw, &mut msg).unwrap();
+
+    // Inner sequence has 2 elements.
+    let raw = vec![0xc0, 0x85, 0x81, 0x81, 0x82, 0x83, 0x83, 0x84, 0x81, 0xc0, 0x82];
+    let mut d = limited(DecoderLimits { max_sequence_length: 1, ..Default::default() });
+    let err = d.decode_vec(raw.clone(), &mut msg).unwrap_err();
+    assert!(matches!(err.inner(), Error::LimitExceeded(_)));
+    assert_eq!(err.location().unwrap().field_path, "Sequence/OuterSequence[0]/InnerSequence");
+    limited(DecoderLimits { max_sequence_length: 2, ..Default::default() }).decode_vec(raw, &mut msg).unwrap();
+
+    // Dynamic template references nested 4 levels deep.
+    let raw = vec![0xc0, 0x89, 0x86, 0xc0, 0x89, 0x86, 0xc0, 0x89, 0x86, 0xc0, 0x89, 0x86, 0xe0, 0x87, 0x85];
+    let mut d = limited(DecoderLimits { max_template_ref_depth: 3, ..Default::default() });
+    assert!(is_limit_exceeded(d.decode_vec(raw.clone(), &mut msg)));
+    limited(DecoderLimits { max_template_ref_depth: 4, ..Default::default() }).decode_vec(raw, &mut msg).unwrap();
+}
+
+#[test]
+fn decode_slice_borrowed() {
+    struct BorrowingFactory<'a> {
+        values: Vec<(String, Option<ValueRef<'a>>)>,
+    }
+
+    impl<'a> MessageFactoryRef<'a> for BorrowingFactory<'a> {
+        fn start_template(&mut self, _id: u32, _name: &str) {}
+        fn stop_template(&mut self) {}
+        fn set_value(&mut self, _id: u32, name: &str, value: Option<ValueRef<'a>>) {
+            self.values.push((name.to_string(), value));
+        }
+        fn start_sequence(&mut self, _id: u32, _name: &str, _length: u32) {}
+        fn start_sequence_item(&mut self, _index: u32) {}
+        fn stop_sequence_item(&mut self) {}
+        fn stop_sequence(&mut self) {}
+        fn start_group(&mut self, _name: &str) {}
+        fn stop_group(&mut self) {}
+        fn start_template_ref(&mut self, _name: &str, _dynamic: bool) {}
+        fn stop_template_ref(&mut self) {}
+    }
+
+    // Two messages in one buffer.
+    let raw = vec![
+        0xc0, 0x82, 0x61, 0x62, 0xe3, 0x64, 0x65, 0xe6, 0x83, 0x67, 0x68, 0x69, 0x84, 0x6b, 0x6c, 0x6d,
+        0xc0, 0x83, 0x81, 0xc1, 0x82, 0xb3,
+    ];
+    let mut d = Decoder::new_from_xml(include_str!("templates/base.xml")).unwrap();
+
+    let mut msg = BorrowingFactory { values: Vec::new() };
+    let n = d.decode_slice(&raw, &mut msg).unwrap();
+    assert_eq!(n, 16);
+    assert_eq!(msg.values, vec![
+        ("MandatoryAscii".to_string(), Some(ValueRef::ASCIIString("abc".into()))),
+        ("OptionalAscii".to_string(), Some(ValueRef::ASCIIString("def".into()))),
+        ("MandatoryUnicode".to_string(), Some(ValueRef::UnicodeString("ghi".into()))),
+        ("OptionalUnicode".to_string(), Some(ValueRef::UnicodeString("klm".into()))),
+    ]);
+    assert!(!msg.values[0].1.as_ref().unwrap().is_borrowed());
+    assert!(msg.values[2].1.as_ref().unwrap().is_borrowed());
+    assert!(msg.values[3].1.as_ref().unwrap().is_borrowed());
+
+    let mut msg = BorrowingFactory { values: Vec::new() };
+    let m = d.decode_slice(&raw[n..], &mut msg).unwrap();
+    assert_eq!(n + m, raw.len());
+    assert_eq!(msg.values, vec![
+        ("MandatoryVector".to_string(), Some(ValueRef::Bytes([193u8][..].into()))),
+        ("OptionalVector".to_string(), Some(ValueRef::Bytes([179u8][..].into()))),
+    ]);
+    assert!(msg.values.iter().all(|(_, v)| v.as_ref().unwrap().is_borrowed()));
+
+    // Owned message factories can be used with zero-copy decoding as well.
+    let mut msg = ModelFactory::new();
+    d.decode_slice(&raw[n..], &mut msg).unwrap();
+    assert_eq!(msg.data.unwrap().name, "ByteVector");
+}
+
+#[test]
+fn decode_projection() {
+    let decode = |raw: Vec<u8>, template_id: u32, fields: &[u32], strict: bool| {
+        let mut d = Decoder::new_from_xml(include_str!("templates/base.xml")).unwrap();
+        if strict {
+            d.set_options(DecoderOptions { strictness: Strictness::Strict, ..Default::default() });
+        }
+        d.set_projection(template_id, fields).unwrap();
+        let mut msg = ModelFactory::new();
+        d.decode_vec(raw, &mut msg).unwrap();
+        msg.data.unwrap()
+    };
+    let strings = vec![0xc0, 0x82, 0x61, 0x62, 0xe3, 0x64, 0x65, 0xe6, 0x83, 0x67, 0x68, 0x69, 0x84, 0x6b, 0x6c, 0x6d];
+    let strings_null = vec![0xc0, 0x82, 0x61, 0x62, 0xe3, 0x80, 0x83, 0x67, 0x68, 0x69, 0x80];
+    for strict in [false, true] {
+        assert_eq!(decode(strings.clone(), 2, &[2], strict), TemplateData {
+            name: "String".to_string(),
+            value: ValueData::Group(HashMap::from([
+                ("OptionalAscii".to_string(), ValueData::Value(Some(Value::ASCIIString("def".to_string())))),
+            ])),
+        });
+        assert_eq!(decode(strings_null.clone(), 2, &[1, 2], strict), TemplateData {
+            name: "String".to_string(),
+            value: ValueData::Group(HashMap::from([
+                ("MandatoryAscii".to_string(), ValueData::Value(Some(Value::ASCIIString("abc".to_string())))),
+                ("OptionalAscii".to_string(), ValueData::Value(None)),
+            ])),
+        });
+        assert_eq!(decode(vec![0xc0, 0x83, 0x81, 0xc1, 0x82, 0xb3], 3, &[], strict), TemplateData {
+            name: "ByteVector".to_string(),
+            value: ValueData::Group(HashMap::new()),
+        });
+    }
+}
+
+#[test]
+fn decode_encode_long_presence_map() {
+    // 1 bit for template id and 80 bits for the fields make a presence map of 12 bytes.
+    let fields: String = (0..80).map(|i| format!(r#"<uInt32 name="F{i}" id="{}"><copy/></uInt32>"#, i + 1)).collect();
+    let xml = format!(r#"<templates xmlns="http://www.fixprotocol.org/ns/fast/td/1.1"><template name="Wide" id="1">{fields}</template></templates>"#);
+    let data = |last: u32| TemplateData {
+        name: "Wide".to_string(),
+        value: ValueData::Group((0..80u32).map(|i| {
+            let v = if i == 79 { last } else { i };
+            (format!("F{i}"), ValueData::Value(Some(Value::UInt32(v))))
+        }).collect()),
+    };
+    let mut e = Encoder::new_from_xml(&xml).unwrap();
+    let mut d = Decoder::new_from_xml(&xml).unwrap();
+    for (last, pmap_len) in [(100, 12), (100, 1), (101, 12)] {
+        let raw = e.encode_vec(&mut ModelVisitor::new(data(last))).unwrap();
+        assert_eq!(raw.iter().position(|b| b & 0x80 != 0).unwrap() + 1, pmap_len);
+        let mut msg = ModelFactory::new();
+        d.decode_vec(raw, &mut msg).unwrap();
+        assert_eq!(msg.data.unwrap(), data(last));
+    }
+}
+
+#[test]
+fn dictionary_layout() {
+    let d = Decoder::new_from_xml(r#"
+<templates xmlns="http://www.fixprotocol.org/ns/fast/td/1.1">
+    <template name="A" id="1" dictionary="template">
+        <uInt32 name="Seq" id="1"><copy/></uInt32>
+        <uInt32 name="Px" id="2" dictionary="global"><copy/></uInt32>
+        <uInt32 name="Qty" id="3" dictionary="user"><copy key="Px"/></uInt32>
+    </template>
+    <template name="B" id="2" dictionary="type" typeRef="Quote">
+        <uInt32 name="Seq" id="1"><copy/></uInt32>
+        <group name="G" typeRef="Trade">
+            <uInt32 name="Seq" id="1"><copy/></uInt32>
+        </group>
+    </template>
+</templates>
+"#).unwrap();
+    let layout = &d.definitions.layout;
+    // "__template_id__" and "Px" in global dictionary, "Px" in "user" dictionary
+    assert_eq!(layout.static_keys.len(), 3);
+    assert_eq!(layout.template_keys.len(), 1);
+    assert_eq!(layout.type_keys.len(), 1);
+    assert_eq!(layout.types.len(), 3);
+    assert_eq!(layout.size(), 3 + 2 * 1 + 3 * 1);
+
+    let a = d.definitions.templates_by_name.get("A").unwrap();
+    assert_eq!(a.instructions[0].slot, DictionarySlot::Template(0));
+    assert_eq!(a.instructions[1].slot, DictionarySlot::Static(1));
+    assert_eq!(a.instructions[2].slot, DictionarySlot::Static(2));
+
+    let b = d.definitions.templates_by_name.get("B").unwrap();
+    assert_eq!(b.index, 1);
+    assert_eq!(b.type_index, 1);
+    assert_eq!(b.instructions[0].slot, DictionarySlot::Type(0));
+    assert_eq!(b.instructions[1].type_index, 2);
+    assert_eq!(b.instructions[1].instructions[0].slot, DictionarySlot::Type(0));
+    assert_ne!(layout.type_slot(b.type_index, 0), layout.type_slot(b.instructions[1].type_index, 0));
+}
+
+#[test]
+fn type_ref_element() {
+    let xml = r#"
+<templates xmlns="http://www.fixprotocol.org/ns/fast/td/1.1">
+    <template name="A" id="1" dictionary="type">
+        <typeRef name="Quote" ns="http://example.com/types"/>
+        <uInt32 name="Seq" id="1"><copy/></uInt32>
+        <group name="G">
+            <typeRef name="Trade"/>
+            <uInt32 name="Seq" id="1"><copy/></uInt32>
+        </group>
+        <sequence name="S">
+            <typeRef name="Trade"/>
+            <uInt32 name="Seq" id="1"><copy/></uInt32>
+        </sequence>
+    </template>
+</templates>
+"#;
+    let mut d = Decoder::new_from_xml(xml).unwrap();
+    let a = d.definitions.templates_by_name.get("A").unwrap().clone();
+    assert_eq!(a.type_ref, TypeRef::ApplicationType(Some("http://example.com/types".into()), "Quote".into()));
+    assert_eq!(a.instructions.len(), 3);
+    assert_eq!(a.instructions[1].type_ref, TypeRef::from_str("Trade"));
+    let s = &a.instructions[2];
+    assert_eq!(s.type_ref, TypeRef::from_str("Trade"));
+    assert_eq!(s.instructions.len(), 2);
+    assert_eq!(s.instructions[0].value_type, ValueType::Length);
+    assert_eq!(s.instructions[1].name, "Seq");
+    assert_eq!(a.instructions[1].type_index, s.type_index);
+    assert_ne!(a.type_index, s.type_index);
+
+    let mut e = Encoder::new_from_xml(xml).unwrap();
+    let text = "A=<Seq=1|G=<Seq=2>|S=<Seq=3><Seq=4>>";
+    let raw = e.encode_vec(&mut TextMessageVisitor::from_text(text).unwrap()).unwrap();
+    let mut msg = TextMessageFactory::new();
+    d.decode_vec(raw, &mut msg).unwrap();
+    assert_eq!(msg.text, text);
+    let types = |d: &Decoder| -> Vec<_> {
+        d.dictionary_entries().into_iter()
+            .filter_map(|e| match e.dictionary {
+                DictionaryId::Type { name, ns } => Some((name, ns, e.value)),
+                _ => None,
+            })
+            .collect()
+    };
+    let quote = ("Quote".to_string(), Some("http://example.com/types".to_string()), Some(Value::UInt32(1)));
+    assert_eq!(types(&d), vec![
+        quote.clone(),
+        ("Trade".to_string(), None, Some(Value::UInt32(4))),
+    ]);
+
+    // The same type name in different namespaces has separate type dictionaries.
+    let xml_ns = xml
+        .replacen(r#"<typeRef name="Trade"/>"#, r#"<typeRef name="Trade" ns="a"/>"#, 1)
+        .replacen(r#"<typeRef name="Trade"/>"#, r#"<typeRef name="Trade" ns="b"/>"#, 1);
+    let mut d = Decoder::new_from_xml(&xml_ns).unwrap();
+    let mut e = Encoder::new_from_xml(&xml_ns).unwrap();
+    let raw = e.encode_vec(&mut TextMessageVisitor::from_text(text).unwrap()).unwrap();
+    let mut msg = TextMessageFactory::new();
+    d.decode_vec(raw, &mut msg).unwrap();
+    assert_eq!(msg.text, text);
+    assert_eq!(types(&d), vec![
+        quote,
+        ("Trade".to_string(), Some("a".to_string()), Some(Value::UInt32(2))),
+        ("Trade".to_string(), Some("b".to_string()), Some(Value::UInt32(4))),
+    ]);
+    let trade = |ns: &str| DictionaryId::Type { name: "Trade".to_string(), ns: Some(ns.to_string()) };
+    d.reset_dictionary(&trade("a")).unwrap();
+    assert_eq!(types(&d).len(), 2);
+    assert!(d.reset_dictionary(&DictionaryId::Type { name: "Trade".to_string(), ns: None }).is_err());
+
+    let err = Decoder::new_from_xml(&xml.replace(r#"<typeRef name="Trade"/>"#, "<typeRef/>")).err().unwrap();
+    assert_eq!(err.to_string(), "Static Error [S1]: typeRef must have 'name' attribute");
+}
+
+#[test]
+fn decode_encode_field_info() {
+    type Info = (u32, String, bool, Operator, String, String, Option<(Operator, Operator)>);
+
+    fn info(field: FieldInfo) -> Info {
+        assert!(!field.is_decimal_component());
+        let components = field.decimal_components().map(|(e, m)| {
+            assert!(e.is_decimal_component() && m.is_decimal_component());
+            (e.operator(), m.operator())
+        });
+        (field.id(), field.name().to_string(), field.is_optional(), field.operator(), field.dictionary().to_string(),
+         field.key().to_string(), components)
+    }
+
+    struct InfoFactory {
+        msg: ModelFactory,
+        fields: Vec<Info>,
+    }
+
+    impl MessageFactory for InfoFactory {
+        fn start_template(&mut self, id: u32, name: &str) { MessageFactory::start_template(&mut self.msg, id, name) }
+        fn stop_template(&mut self) { MessageFactory::stop_template(&mut self.msg) }
+        fn set_value(&mut self, id: u32, name: &str, value: Option<Value>) { MessageFactory::set_value(&mut self.msg, id, name, value) }
+        fn set_field(&mut self, field: FieldInfo, value: Option<Value>) {
+            self.fields.push(info(field));
+            MessageFactory::set_value(self, field.id(), field.name(), value)
+        }
+        fn start_sequence(&mut self, id: u32, name: &str, length: u32) { MessageFactory::start_sequence(&mut self.msg, id, name, length) }
+        fn start_sequence_item(&mut self, index: u32) { MessageFactory::start_sequence_item(&mut self.msg, index) }
+        fn stop_sequence_item(&mut self) { MessageFactory::stop_sequence_item(&mut self.msg) }
+        fn stop_sequence(&mut self) { MessageFactory::stop_sequence(&mut self.msg) }
+        fn start_group(&mut self, name: &str) { MessageFactory::start_group(&mut self.msg, name) }
+        fn stop_group(&mut self) { MessageFactory::stop_group(&mut self.msg) }
+        fn start_template_ref(&mut self, name: &str, dynamic: bool) { MessageFactory::start_template_ref(&mut self.msg, name, dynamic) }
+        fn stop_template_ref(&mut self) { MessageFactory::stop_template_ref(&mut self.msg) }
+    }
+
+    struct InfoVisitor {
+        msg: ModelVisitor,
+        fields: Vec<Info>,
+    }
+
+    impl MessageVisitor for InfoVisitor {
+        fn get_template_name(&mut self) -> Result<String> { self.msg.get_template_name() }
+        fn get_value(&mut self, name: &str, type_: &ValueType) -> Result<Option<Value>> { self.msg.get_value(name, type_) }
+        fn get_field(&mut self, field: FieldInfo) -> Result<Option<Value>> {
+            self.fields.push(info(field));
+            self.get_value(field.name(), field.value_type())
+        }
+        fn select_group(&mut self, name: &str) -> Result<bool> { self.msg.select_group(name) }
+        fn release_group(&mut self) -> Result<()> { self.msg.release_group() }
+        fn select_sequence(&mut self, name: &str) -> Result<Option<usize>> { self.msg.select_sequence(name) }
+        fn select_sequence_item(&mut self, index: usize) -> Result<()> { self.msg.select_sequence_item(index) }
+        fn release_sequence_item(&mut self) -> Result<()> { self.msg.release_sequence_item() }
+        fn release_sequence(&mut self) -> Result<()> { self.msg.release_sequence() }
+        fn select_template_ref(&mut self, name: &str, dynamic: bool) -> Result<Option<String>> { self.msg.select_template_ref(name, dynamic) }
+        fn release_template_ref(&mut self) -> Result<()> { self.msg.release_template_ref() }
+    }
+
+    let xml = r#"
+<templates xmlns="http://www.fixprotocol.org/ns/fast/td/1.1">
+    <template name="A" id="1" dictionary="template">
+        <uInt32 name="Seq" id="1"><increment/></uInt32>
+        <decimal name="Px" id="2" presence="optional" dictionary="user">
+            <exponent><copy/></exponent>
+            <mantissa><delta/></mantissa>
+        </decimal>
+        <group name="G">
+            <string name="Side" id="3" presence="optional" key="S" dictionary="global"><copy/></string>
+        </group>
+    </template>
+</templates>
+"#;
+    let data = TemplateData {
+        name: "A".to_string(),
+        value: ValueData::Group(HashMap::from([
+            ("Seq".to_string(), ValueData::Value(Some(Value::UInt32(1)))),
+            ("Px".to_string(), ValueData::Value(Some(Value::Decimal(Decimal::new(12, -1))))),
+            ("G".to_string(), ValueData::Group(HashMap::from([
+                ("Side".to_string(), ValueData::Value(Some(Value::ASCIIString("B".to_string())))),
+            ]))),
+        ])),
+    };
+    let expected: Vec<Info> = vec![
+        (1, "Seq".to_string(), false, Operator::Increment, "template".to_string(), "Seq".to_string(), None),
+        (2, "Px".to_string(), true, Operator::None, "user".to_string(), "Px".to_string(), Some((Operator::Copy, Operator::Delta))),
+        (3, "Side".to_string(), true, Operator::Copy, "global".to_string(), "S".to_string(), None),
+    ];
+
+    let mut e = Encoder::new_from_xml(xml).unwrap();
+    let mut msg = InfoVisitor { msg: ModelVisitor::new(data.clone()), fields: Vec::new() };
+    let raw = e.encode_vec(&mut msg).unwrap();
+    assert_eq!(msg.fields, expected);
+
+    let mut d = Decoder::new_from_xml(xml).unwrap();
+    let mut msg = InfoFactory { msg: ModelFactory::new(), fields: Vec::new() };
+    d.decode_vec(raw, &mut msg).unwrap();
+    assert_eq!(msg.fields, expected);
+    assert_eq!(msg.msg.data.unwrap(), data);
+}
+
+#[test]
+fn decode_encode_dynamic_reference_by_id() {
+    struct RefVisitor {
+        template_id: u32,
+    }
+
+    impl MessageVisitor for RefVisitor {
+        fn get_template_name(&mut self) -> Result<String> { Ok("Outer".to_string()) }
+        fn get_value(&mut self, name: &str, _type: &ValueType) -> Result<Option<Value>> {
+            Ok(Some(match name {
+                "C" => Value::ASCIIString("c".to_string()),
+                _ => Value::UInt32(self.template_id * 10),
+            }))
+        }
+        fn select_group(&mut self, _name: &str) -> Result<bool> { Ok(false) }
+        fn release_group(&mut self) -> Result<()> { Ok(()) }
+        fn select_sequence(&mut self, _name: &str) -> Result<Option<usize>> { Ok(None) }
+        fn select_sequence_item(&mut self, _index: usize) -> Result<()> { Ok(()) }
+        fn release_sequence_item(&mut self) -> Result<()> { Ok(()) }
+        fn release_sequence(&mut self) -> Result<()> { Ok(()) }
+        fn select_template_ref(&mut self, _name: &str, _dynamic: bool) -> Result<Option<String>> { unreachable!() }
+        fn select_template_ref_key(&mut self, _name: &str, _dynamic: bool) -> Result<Option<TemplateKey>> {
+            Ok(Some(TemplateKey::Id(self.template_id)))
+        }
+        fn release_template_ref(&mut self) -> Result<()> { Ok(()) }
+    }
+
+    // Templates 2 and 3 have the same name and template 4 has no name.
+    let xml = r#"
+<templates xmlns="http://www.fixprotocol.org/ns/fast/td/1.1">
+    <template name="Outer" id="1">
+        <uInt32 name="A" id="1"/>
+        <templateRef/>
+    </template>
+    <template name="Inner" id="2"><uInt32 name="B" id="2"/></template>
+    <template name="Inner" id="3"><string name="C" id="3"/></template>
+    <template id="4"><uInt32 name="D" id="4"/></template>
+</templates>
+"#;
+    let mut e = Encoder::new_from_xml(xml).unwrap();
+    let mut d = Decoder::new_from_xml(xml).unwrap();
+    for (id, name, field) in [(2, "Inner", "2:B Some(UInt32(20))"), (3, "Inner", "3:C Some(ASCIIString(\"c\"))"), (4, "", "4:D Some(UInt32(40))")] {
+        let raw = e.encode_vec(&mut RefVisitor { template_id: id }).unwrap();
+        let mut msg = LoggingMessageFactory::new();
+        d.decode_vec(raw, &mut msg).unwrap();
+        assert_eq!(msg.calls, vec![
+            "start_template: 1:Outer".to_string(),
+            format!("set_value: 1:A Some(UInt32({}))", id * 10),
+            format!("start_template_ref: {id}:{name}:true"),
+            format!("set_value: {field}"),
+            "stop_template_ref".to_string(),
+            "stop_template".to_string(),
+        ]);
+    }
+
+    assert_eq!(e.encode_vec(&mut RefVisitor { template_id: 5 }).unwrap_err().code(), Some(FastErrorCode::D9));
+}
+
+#[test]
+fn reset_dictionary() {
+    let xml = r#"
+<templates xmlns="http://www.fixprotocol.org/ns/fast/td/1.1">
+    <template name="A" id="1" dictionary="template">
+        <uInt32 name="Seq" id="1"><copy/></uInt32>
+        <uInt32 name="Px" id="2" dictionary="global"><copy/></uInt32>
+        <uInt32 name="Qty" id="3" dictionary="user"><copy/></uInt32>
+    </template>
+    <template name="B" id="2" dictionary="type" typeRef="Quote">
+        <uInt32 name="Seq" id="1"><copy/></uInt32>
+    </template>
+</templates>
+"#;
+    let data = |name: &str, values: &[(&str, u32)]| TemplateData {
+        name: name.to_string(),
+        value: ValueData::Group(values.iter().map(|(n, v)| (n.to_string(), ValueData::Value(Some(Value::UInt32(*v))))).collect()),
+    };
+    let entry = |dictionary: DictionaryId, key: &str, value: u32| DictionaryEntry {
+        dictionary,
+        key: key.to_string(),
+        value: Some(Value::UInt32(value)),
+    };
+    let mut e = Encoder::new_from_xml(xml).unwrap();
+    let mut d = Decoder::new_from_xml(xml).unwrap();
+    for data in [data("A", &[("Seq", 1), ("Px", 2), ("Qty", 3)]), data("B", &[("Seq", 4)])] {
+        let raw = e.encode_vec(&mut ModelVisitor::new(data)).unwrap();
+        d.decode_vec(raw, &mut ModelFactory::new()).unwrap();
+    }
+    assert_eq!(d.dictionary_entries(), vec![
+        entry(DictionaryId::Global, "__template_id__", 2),
+        entry(DictionaryId::Global, "Px", 2),
+        entry(DictionaryId::User("user".to_string()), "Qty", 3),
+        entry(DictionaryId::Template(1), "Seq", 1),
+        entry(DictionaryId::Type { name: "Quote".to_string(), ns: None }, "Seq", 4),
+    ]);
+    assert_eq!(e.dictionary_entries(), d.dictionary_entries());
+
+    for dictionary in [DictionaryId::Template(1), DictionaryId::User("user".to_string()),
+                       DictionaryId::Type { name: "Quote".to_string(), ns: None }] {
+        let mut entries = d.dictionary_entries();
+        entries.retain(|e| e.dictionary != dictionary);
+        d.reset_dictionary(&dictionary).unwrap();
+        e.reset_dictionary(&dictionary).unwrap();
+        assert_eq!(d.dictionary_entries(), entries);
+        assert_eq!(e.dictionary_entries(), entries);
+    }
+    d.reset_dictionary(&DictionaryId::Global).unwrap();
+    assert!(d.dictionary_entries().is_empty());
+
+    assert_eq!(d.reset_dictionary(&DictionaryId::Template(3)).unwrap_err().code(), Some(FastErrorCode::D9));
+    assert!(d.reset_dictionary(&DictionaryId::User("other".to_string())).is_err());
+    assert!(e.reset_dictionary(&DictionaryId::Type { name: "Trade".to_string(), ns: None }).is_err());
+}
+
+#[test]
+fn restore_snapshot_value_types() {
+    let xml = r#"
+<templates xmlns="http://www.fixprotocol.org/ns/fast/td/1.1">
+    <template name="A" id="1">
+        <uInt32 name="Seq" id="1"><copy/></uInt32>
+        <string name="Sym" id="2"><copy/></string>
+    </template>
+</templates>
+"#;
+    let mut e = Encoder::new_from_xml(xml).unwrap();
+    let data = TemplateData {
+        name: "A".to_string(),
+        value: ValueData::Group(HashMap::from([
+            ("Seq".to_string(), ValueData::Value(Some(Value::UInt32(1)))),
+            ("Sym".to_string(), ValueData::Value(Some(Value::ASCIIString("X".to_string())))),
+        ])),
+    };
+    e.encode_vec(&mut ModelVisitor::new(data)).unwrap();
+    let snapshot = e.snapshot();
+    let entries = e.dictionary_entries();
+
+    let mut d = Decoder::new_from_xml(xml).unwrap();
+    d.restore(&snapshot).unwrap();
+    assert_eq!(d.dictionary_entries(), entries);
+
+    // A value of another type in the uInt32 entry is rejected and the dictionaries are left unchanged.
+    let mut corrupted = snapshot.clone();
+    for (slot, value) in &mut corrupted.entries {
+        if d.definitions.dictionary_key(*slot).1 == "Seq" {
+            *value = Some(Value::Bytes(vec![1]));
+        }
+    }
+    let mut d = Decoder::new_from_xml(xml).unwrap();
+    let err = d.restore(&corrupted).unwrap_err();
+    assert_eq!(err.to_string(), "Runtime Error: snapshot entry Seq of Global dictionary has value of wrong type: Bytes([1])");
+    assert!(d.dictionary_entries().is_empty());
+    assert!(e.restore(&corrupted).is_err());
+    assert_eq!(e.dictionary_entries(), entries);
+}
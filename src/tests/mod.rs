@@ -52,7 +52,7 @@ pub fn test_instructions(iss: &Vec<Instruction>, tis: &Vec<TestField>, name: &st
         assert_eq!(t.presence, tt.presence, "{} presence mismatch", tt.name);
         assert_eq!(t.operator, tt.operator, "{} operator mismatch", tt.name);
         assert_eq!(t.value_type, tt.value, "{} value mismatch", tt.name);
-        assert_eq!(t.has_pmap.get(), tt.has_pmap, "{} has_pmap mismatch", tt.name);
+        assert_eq!(t.has_pmap, tt.has_pmap, "{} has_pmap mismatch", tt.name);
         test_instructions(&t.instructions, &tt.instructions, &tt.name);
     }
 }
@@ -110,6 +110,10 @@ impl MessageFactory for LoggingMessageFactory {
         self.calls.push(format!("start_template_ref: {name}:{dynamic}"));
     }
 
+    fn start_template_ref_id(&mut self, id: u32, name: &str, dynamic: bool) {
+        self.calls.push(format!("start_template_ref: {id}:{name}:{dynamic}"));
+    }
+
     fn stop_template_ref(&mut self) {
         self.calls.push("stop_template_ref".to_string());
     }
@@ -39,6 +39,12 @@ impl TextMessageFactory {
     }
 }
 
+impl Default for TextMessageFactory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MessageFactory for TextMessageFactory {
     fn start_template(&mut self, _id: u32, name: &str) {
         self.reset();
@@ -148,6 +154,12 @@ impl JsonMessageFactory {
     }
 }
 
+impl Default for JsonMessageFactory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MessageFactory for JsonMessageFactory {
     fn start_template(&mut self, _id: u32, name: &str) {
         self.reset();
@@ -203,6 +203,53 @@ fn test_heartbeats() {
     )
 }
 
+#[test]
+fn test_stream_iter() {
+    let raw: Vec<u8> = vec![
+        0xc0, 0x84, 0x81, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2c, 0x58, 0x80,
+        0x80, 0x82, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2d, 0x26, 0x90,
+        0x80, 0x83, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2d, 0x74, 0xa0,
+    ];
+    let mut d = Decoder::new_from_xml(DEFINITION).unwrap();
+    let mut rdr = raw.as_slice();
+    let msgs: Vec<Message> = fastlib::from_stream_iter(&mut d, &mut rdr)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let seq: Vec<u32> = msgs
+        .iter()
+        .map(|m| match m {
+            Message::MDHeartbeat(h) => h.msg_header.msg_seq_num,
+            _ => panic!("unexpected message"),
+        })
+        .collect();
+    assert_eq!(seq, vec![1, 2, 3]);
+}
+
+#[test]
+fn test_skipped_template() {
+    let raw: Vec<u8> = vec![
+        0xc0, 0x84, 0x81, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2c, 0x58, 0x80,
+        0x80, 0x82, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2d, 0x26, 0x90,
+        0x80, 0x83, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2d, 0x74, 0xa0,
+    ];
+    let mut d = Decoder::new_from_xml(DEFINITION).unwrap();
+    d.skip_template_by_name("MDHeartbeat").unwrap();
+    let res: Result<Message, _> = fastlib::from_vec(&mut d, raw[..11].to_vec());
+    assert_eq!(
+        res.unwrap_err().to_string(),
+        "Runtime Error: template MDHeartbeat was skipped, nothing to deserialize"
+    );
+
+    let mut d = Decoder::new_from_xml(DEFINITION).unwrap();
+    d.skip_template_by_name("MDHeartbeat").unwrap();
+    let mut rdr = raw.as_slice();
+    let msgs: Vec<Message> = fastlib::from_stream_iter(&mut d, &mut rdr)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert!(msgs.is_empty());
+    assert!(rdr.is_empty());
+}
+
 #[test]
 fn test_logon() {
     do_tests_seq(
@@ -2,14 +2,22 @@
 //!
 //! See: https://help.cqg.com/apihelp/#!Documents/quotesdirectfixfast.htm
 //!
-use fastlib::{Encoder, JsonMessageFactory, TextMessageFactory, TextMessageVisitor};
-use fastlib::Decoder;
+use std::sync::Arc;
+use std::thread;
+
+use fastlib::{BlockLength, BlockWriter, DecodeStatus, Definitions, Encoder, Error, FastErrorCode, JsonMessageFactory, PushDecoder};
+use fastlib::{Control, MessageFactory, PacketLayout, Preamble, Reader, Snapshot, TextMessageFactory, TextMessageVisitor, Value, ValueRef, Writer};
+use fastlib::{Decoder, DecoderLimits, DecoderOptions, Strictness};
 
 const DEFINITION: &str = include_str!("templates.xml");
 
+const SECURITY_DEFINITION: &str = "MDSecurityDefinition=<MessageType=d|ApplVerID=8|SenderCompID=CQG|MsgSeqNum=964|SendingTime=20240606212353155|TotNumReports=966|Events=<EventType=7|EventDate=20241129|EventTime=220000000>|SecurityGroup=MBTS13|Symbol=MBTS13C100|SecurityName=Micro Bitcoin Reverse Cal Spread|SecurityDesc=MBTS13X24|SecurityID=60714110|SecurityIDSource=100|CFICode=FXXXXX|SecurityExchange=GLBX|CQGSecurityName=F.US.MBTW13X24|StrikePrice=0|Currency=USD|MDFeedTypes=<MDFeedType=CQGC|MarketDepth=0><MDFeedType=CQGI|MarketDepth=1>|InstrAttrib=<InstrAttribType=1003|InstrAttribValue=100>|MaturityMonthYear=202411|MinPriceIncrement=1|MinPriceIncrementAmount=0.1|DisplayFactor=1|ApplID=4|Connections=<ConnectionType=1|ConnectionIPAddress=239.246.5.4|ConnectionPortNumber=11004><ConnectionType=2|ConnectionIPAddress=239.246.6.4|ConnectionPortNumber=12004><ConnectionType=3|ConnectionIPAddress=10.1.0.120|ConnectionPortNumber=10000><ConnectionType=3|ConnectionIPAddress=10.1.0.120|ConnectionPortNumber=10001>|TradingSessions=<TradeDate=20240531|TradSesStartTime=20240530220000000|TradSesOpenTime=20240530211500000|TradSesCloseTime=20240531210000000|TradSesEndTime=20240531210000000><TradeDate=20240603|TradSesStartTime=20240602220000000|TradSesOpenTime=20240602211500000|TradSesCloseTime=20240603210000000|TradSesEndTime=20240603210000000><TradeDate=20240604|TradSesStartTime=20240603220000000|TradSesOpenTime=20240603211500000|TradSesCloseTime=20240604210000000|TradSesEndTime=20240604210000000><TradeDate=20240605|TradSesStartTime=20240604220000000|TradSesOpenTime=20240604211500000|TradSesCloseTime=20240605210000000|TradSesEndTime=20240605210000000><TradeDate=20240606|TradSesStartTime=20240605220000000|TradSesOpenTime=20240605211500000|TradSesCloseTime=20240606210000000|TradSesEndTime=20240606210000000><TradeDate=20240607|TradSesStartTime=20240606220000000|TradSesOpenTime=20240606211500000|TradSesCloseTime=20240607210000000|TradSesEndTime=20240607210000000>>";
+
 fn do_tests_seq(raw: Vec<Vec<u8>>, data: Vec<&str>) {
     let mut e = Encoder::new_from_xml(DEFINITION).unwrap();
     let mut d = Decoder::new_from_xml(DEFINITION).unwrap();
+    let mut ds = Decoder::new_from_xml(DEFINITION).unwrap();
+    ds.set_options(DecoderOptions { strictness: Strictness::Strict, ..Default::default() });
 
     for (i, (raw, data)) in raw.into_iter().zip(data).enumerate() {
         let mut msg = TextMessageVisitor::from_text(data).unwrap();
@@ -17,8 +25,12 @@ fn do_tests_seq(raw: Vec<Vec<u8>>, data: Vec<&str>) {
         assert_eq!(res, raw, "encode failed #{}", i + 1);
 
         let mut msg = TextMessageFactory::new();
-        d.decode_vec(raw, &mut msg).unwrap();
+        d.decode_vec(raw.clone(), &mut msg).unwrap();
         assert_eq!(&msg.text, data, "decode failed #{}", i + 1);
+
+        let mut msg = TextMessageFactory::new();
+        ds.decode_vec(raw, &mut msg).unwrap();
+        assert_eq!(&msg.text, data, "strict decode failed #{}", i + 1);
     }
 }
 
@@ -103,3 +115,749 @@ fn test_messages_json() {
         ],
     )
 }
+
+#[test]
+fn test_shared_definitions() {
+    fn assert_send<T: Send>() {}
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send::<Decoder>();
+    assert_send::<Encoder>();
+    assert_send_sync::<Definitions>();
+
+    let raw = vec![
+        vec![0xc0, 0x84, 0x81, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2c, 0x58, 0x80],
+        vec![0x80, 0x82, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2d, 0x26, 0x90],
+    ];
+    let data = vec![
+        "MDHeartbeat=<MessageType=0|ApplVerID=8|SenderCompID=CQG|MsgSeqNum=1|SendingTime=20240606000000000>",
+        "MDHeartbeat=<MessageType=0|ApplVerID=8|SenderCompID=CQG|MsgSeqNum=2|SendingTime=20240606000010000>",
+    ];
+
+    let definitions = Arc::new(Definitions::new_from_xml(DEFINITION).unwrap());
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let mut d = Decoder::with_definitions(definitions.clone());
+            let mut e = Encoder::with_definitions(definitions.clone());
+            let (raw, data) = (raw.clone(), data.clone());
+            thread::spawn(move || {
+                // Each decoder/encoder keeps its own dictionaries.
+                for (raw, data) in raw.into_iter().zip(data) {
+                    let mut msg = TextMessageVisitor::from_text(data).unwrap();
+                    assert_eq!(e.encode_vec(&mut msg).unwrap(), raw);
+
+                    let mut msg = TextMessageFactory::new();
+                    d.decode_vec(raw, &mut msg).unwrap();
+                    assert_eq!(&msg.text, data);
+                }
+            })
+        })
+        .collect();
+    for h in handles {
+        h.join().unwrap();
+    }
+    assert!(Arc::ptr_eq(Decoder::with_definitions(definitions.clone()).definitions(), &definitions));
+}
+
+#[test]
+fn test_push_decoder() {
+    let raw: Vec<u8> = vec![
+        0xc0, 0x84, 0x81, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2c, 0x58, 0x80,
+        0x80, 0x82, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2d, 0x26, 0x90,
+        0x80, 0x83, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2d, 0x74, 0xa0,
+    ];
+    let data = vec![
+        "MDHeartbeat=<MessageType=0|ApplVerID=8|SenderCompID=CQG|MsgSeqNum=1|SendingTime=20240606000000000>",
+        "MDHeartbeat=<MessageType=0|ApplVerID=8|SenderCompID=CQG|MsgSeqNum=2|SendingTime=20240606000010000>",
+        "MDHeartbeat=<MessageType=0|ApplVerID=8|SenderCompID=CQG|MsgSeqNum=3|SendingTime=20240606000020000>",
+    ];
+
+    // Feed the data in chunks of every size; partial attempts must not corrupt the dictionaries.
+    for chunk in 1..=raw.len() {
+        let mut d = PushDecoder::new(Decoder::new_from_xml(DEFINITION).unwrap());
+        let mut msg = TextMessageFactory::new();
+        let mut decoded = Vec::new();
+        for part in raw.chunks(chunk) {
+            d.feed(part);
+            while d.decode(&mut msg).unwrap() == DecodeStatus::Message {
+                decoded.push(msg.text.clone());
+            }
+        }
+        assert_eq!(decoded, data, "chunk size {}", chunk);
+        assert_eq!(d.buffered(), 0);
+    }
+
+    // Fallible message factories can skip messages.
+    let mut d = PushDecoder::new(Decoder::new_from_xml(DEFINITION).unwrap());
+    let mut msg = FilterMessageFactory { msg: TextMessageFactory::new(), messages: Vec::new(), skip: 2, abort: 0 };
+    d.feed(&raw);
+    while d.decode(&mut msg).unwrap() == DecodeStatus::Message {}
+    assert_eq!(msg.messages, vec![data[0], data[2]]);
+
+    // A partial message can't grow beyond the message size limit.
+    let mut decoder = Decoder::new_from_xml(DEFINITION).unwrap();
+    decoder.set_options(DecoderOptions {
+        limits: DecoderLimits { max_message_size: 5, ..Default::default() },
+        ..Default::default()
+    });
+    let mut d = PushDecoder::new(decoder);
+    let mut msg = TextMessageFactory::new();
+    d.feed(&raw[..4]);
+    assert_eq!(d.decode(&mut msg).unwrap(), DecodeStatus::NeedMoreData);
+    d.feed(&raw[4..6]);
+    assert!(matches!(d.decode(&mut msg).unwrap_err().inner(), Error::LimitExceeded(_)));
+}
+
+#[test]
+fn test_messages() {
+    let raw: Vec<u8> = vec![
+        0xc0, 0x84, 0x81, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2c, 0x58, 0x80,
+        0x80, 0x82, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2d, 0x26, 0x90,
+        0x80, 0x83, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2d, 0x74, 0xa0,
+    ];
+    let data = vec![
+        "MDHeartbeat=<MessageType=0|ApplVerID=8|SenderCompID=CQG|MsgSeqNum=1|SendingTime=20240606000000000>",
+        "MDHeartbeat=<MessageType=0|ApplVerID=8|SenderCompID=CQG|MsgSeqNum=2|SendingTime=20240606000010000>",
+        "MDHeartbeat=<MessageType=0|ApplVerID=8|SenderCompID=CQG|MsgSeqNum=3|SendingTime=20240606000020000>",
+    ];
+
+    let mut d = Decoder::new_from_xml(DEFINITION).unwrap();
+    let mut bytes = bytes::Bytes::from(raw.clone());
+    let msgs: Vec<String> = d
+        .messages::<TextMessageFactory, _>(&mut bytes)
+        .map(|m| m.unwrap().text)
+        .collect();
+    assert_eq!(msgs, data);
+
+    let mut d = Decoder::new_from_xml(DEFINITION).unwrap();
+    let msgs: Vec<(usize, String)> = d
+        .messages_with_offsets::<TextMessageFactory>(bytes::Bytes::from(raw.clone()))
+        .map(|m| m.map(|(offset, m)| (offset, m.text)).unwrap())
+        .collect();
+    assert_eq!(msgs, vec![(0, data[0].to_string()), (11, data[1].to_string()), (21, data[2].to_string())]);
+
+    // Truncated last message is an error, not the end of data.
+    let mut d = Decoder::new_from_xml(DEFINITION).unwrap();
+    let mut msgs = d.messages_with_offsets::<TextMessageFactory>(bytes::Bytes::from(raw[..raw.len() - 3].to_vec()));
+    assert_eq!(msgs.next().unwrap().unwrap().0, 0);
+    assert_eq!(msgs.next().unwrap().unwrap().0, 11);
+    assert!(matches!(msgs.next(), Some(Err(Error::UnexpectedEof))));
+    assert!(msgs.next().is_none());
+}
+
+#[test]
+fn test_stats() {
+    let raw: Vec<u8> = vec![
+        0xc0, 0x84, 0x81, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2c, 0x58, 0x80,
+        0x80, 0x82, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2d, 0x26, 0x90,
+        0x80, 0x83, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2d, 0x74, 0xa0,
+    ];
+    let mut d = Decoder::new_from_xml(DEFINITION).unwrap();
+    assert!(d.stats().is_none());
+    d.set_stats(true);
+    let mut e = Encoder::new_from_xml(DEFINITION).unwrap();
+    e.set_stats(true);
+    for msg in d.messages::<TextMessageFactory, _>(bytes::Bytes::from(raw)) {
+        e.encode_vec(&mut TextMessageVisitor::from_text(&msg.unwrap().text).unwrap()).unwrap();
+    }
+
+    let stats = d.stats().unwrap();
+    assert_eq!(stats.templates.len(), 1);
+    let t = &stats.templates[&4];
+    assert_eq!((t.name.as_str(), t.messages, t.bytes), ("MDHeartbeat", 3, 31));
+    let f = &t.fields["SenderCompID"];
+    assert_eq!((f.operator, f.count, f.nulls, f.hits, f.bytes), ("constant", 3, 0, 3, 0));
+    let f = &t.fields["MsgSeqNum"];
+    assert_eq!((f.operator, f.count, f.nulls, f.hits, f.bytes), ("none", 3, 0, 0, 3));
+    assert_eq!(t.fields["SendingTime"].average_size(), 8.0);
+    // Encoder counts the same.
+    assert_eq!(e.stats().unwrap(), stats);
+}
+
+#[test]
+fn test_trace() {
+    let raw: Vec<u8> = vec![
+        0xc0, 0x84, 0x81, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2c, 0x58, 0x80,
+        0x80, 0x82, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2d, 0x26, 0x90,
+    ];
+    let mut d = Decoder::new_from_xml(DEFINITION).unwrap();
+    d.set_trace(true);
+    let mut e = Encoder::new_from_xml(DEFINITION).unwrap();
+    e.set_trace(true);
+    let mut pos = 0;
+    while pos < raw.len() {
+        let mut msg = TextMessageFactory::new();
+        pos += d.decode_slice(&raw[pos..], &mut msg).unwrap();
+        e.encode_vec(&mut TextMessageVisitor::from_text(&msg.text).unwrap()).unwrap();
+        assert_eq!(e.trace(), d.trace());
+    }
+    assert_eq!(d.trace().unwrap().to_string(), "\
+0000  80                       presence map 0000000
+0001                           template id = 4 [copy, bit 0, prev 4]
+0001                           MessageType = 0 [constant]
+0001                           ApplVerID = 8 [constant]
+0001                           SenderCompID = CQG [constant]
+0001  82                       MsgSeqNum = 2 [none]
+0002  23 7a 17 15 15 2d 26 90  SendingTime = 20240606000010000 [none]
+");
+
+    // Message cut inside SendingTime.
+    assert!(d.decode_slice(&raw[11..16], &mut TextMessageFactory::new()).is_err());
+    assert_eq!(d.trace().unwrap().to_string(), "\
+0000  80                       presence map 0000000
+0001                           template id = 4 [copy, bit 0, prev 4]
+0001                           MessageType = 0 [constant]
+0001                           ApplVerID = 8 [constant]
+0001                           SenderCompID = CQG [constant]
+0001  82                       MsgSeqNum = 2 [none]
+0002  23 7a 17                 (not decoded)
+");
+
+    // Presence maps of nested segments are placed before their fields in the encoder's trace.
+    let raw = e.encode_vec(&mut TextMessageVisitor::from_text(SECURITY_DEFINITION).unwrap()).unwrap();
+    d.decode_slice(&raw, &mut TextMessageFactory::new()).unwrap();
+    assert_eq!(e.trace(), d.trace());
+    assert_eq!(e.trace().unwrap().data, raw);
+}
+
+#[test]
+fn test_strict() {
+    let data = "MDHeartbeat=<MessageType=0|ApplVerID=8|SenderCompID=CQG|MsgSeqNum=1|SendingTime=20240606000000000>";
+    // MsgSeqNum with redundant leading zero byte.
+    let overlong = vec![0xc0, 0x84, 0x00, 0x81, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2c, 0x58, 0x80];
+    // Presence map with a bit set that is not used by the template.
+    let extra_bit = vec![0xc1, 0x84, 0x81, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2c, 0x58, 0x80];
+
+    for raw in [overlong, extra_bit] {
+        let mut d = Decoder::new_from_xml(DEFINITION).unwrap();
+        let mut msg = TextMessageFactory::new();
+        d.decode_vec(raw, &mut msg).unwrap();
+        assert_eq!(msg.text, data);
+    }
+
+    let mut d = Decoder::new_from_xml(DEFINITION).unwrap();
+    d.set_options(DecoderOptions { strictness: Strictness::Strict, ..Default::default() });
+    let mut msg = TextMessageFactory::new();
+    let err = d.decode_vec(vec![0xc0, 0x84, 0x00, 0x81, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2c, 0x58, 0x80], &mut msg).unwrap_err();
+    assert_eq!(err.code(), Some(FastErrorCode::R6));
+    assert_eq!(err.location().unwrap().field_path, "MDHeartbeat/MsgHeader/MsgSeqNum");
+    let err = d.decode_vec(vec![0xc1, 0x84, 0x81, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2c, 0x58, 0x80], &mut msg).unwrap_err();
+    assert_eq!(err.code(), Some(FastErrorCode::R8));
+}
+
+#[test]
+fn test_transactional() {
+    let raw1 = vec![0xc0, 0x84, 0x81, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2c, 0x58, 0x80];
+    let raw2 = vec![0x80, 0x82, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2d, 0x26, 0x90];
+    let data1 = "MDHeartbeat=<MessageType=0|ApplVerID=8|SenderCompID=CQG|MsgSeqNum=1|SendingTime=20240606000000000>";
+    let data2 = "MDHeartbeat=<MessageType=0|ApplVerID=8|SenderCompID=CQG|MsgSeqNum=2|SendingTime=20240606000010000>";
+    // The template id (copy operator) is stored in the dictionary before the message fails.
+    let broken = "MDLogon=<MessageType=A|ApplVerID=8|SenderCompID=CQG|MsgSeqNum=2|SendingTime=20240606212352157|EncryptMethod=0>";
+    let broken_raw = vec![0xc0, 0x85, 0x81, 0x23, 0x7a, 0x17, 0x15, 0x7a];
+
+    for transactional in [true, false] {
+        let mut e = Encoder::new_from_xml(DEFINITION).unwrap();
+        e.set_transactional(transactional);
+        e.encode_vec(&mut TextMessageVisitor::from_text(data1).unwrap()).unwrap();
+        assert!(e.encode_vec(&mut TextMessageVisitor::from_text(broken).unwrap()).is_err());
+        let res = e.encode_vec(&mut TextMessageVisitor::from_text(data2).unwrap()).unwrap();
+        assert_eq!(res == raw2, transactional);
+
+        let mut d = Decoder::new_from_xml(DEFINITION).unwrap();
+        d.set_transactional(transactional);
+        let mut msg = TextMessageFactory::new();
+        d.decode_vec(raw1.clone(), &mut msg).unwrap();
+        assert!(d.decode_vec(broken_raw.clone(), &mut msg).is_err());
+        let res = d.decode_vec(raw2.clone(), &mut msg);
+        assert_eq!(res.is_ok() && msg.text == data2, transactional);
+    }
+}
+
+#[test]
+fn test_reset_templates() {
+    let raw1 = vec![0xc0, 0x84, 0x81, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2c, 0x58, 0x80];
+    let raw2 = vec![0xc0, 0x84, 0x82, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2d, 0x26, 0x90];
+    let data1 = "MDHeartbeat=<MessageType=0|ApplVerID=8|SenderCompID=CQG|MsgSeqNum=1|SendingTime=20240606000000000>";
+    let data2 = "MDHeartbeat=<MessageType=0|ApplVerID=8|SenderCompID=CQG|MsgSeqNum=2|SendingTime=20240606000010000>";
+
+    // Each heartbeat resets the dictionaries, so the next one is encoded in full.
+    let by_attribute = DEFINITION.replace(r#"id="4" name="MDHeartbeat""#, r#"id="4" name="MDHeartbeat" reset="yes""#);
+    for by_id in [true, false] {
+        let definitions = if by_id { DEFINITION } else { &by_attribute };
+        let mut e = Encoder::new_from_xml(definitions).unwrap();
+        let mut d = Decoder::new_from_xml(definitions).unwrap();
+        if by_id {
+            assert_eq!(e.set_reset_templates(&[4, 100]).unwrap_err().code(), Some(FastErrorCode::D9));
+            assert_eq!(d.set_reset_templates(&[100]).unwrap_err().code(), Some(FastErrorCode::D9));
+            e.set_reset_templates(&[4]).unwrap();
+            d.set_reset_templates(&[4]).unwrap();
+        }
+        assert_eq!(e.encode_vec(&mut TextMessageVisitor::from_text(data1).unwrap()).unwrap(), raw1);
+        assert_eq!(e.encode_vec(&mut TextMessageVisitor::from_text(data2).unwrap()).unwrap(), raw2);
+
+        let mut msg = TextMessageFactory::new();
+        d.decode_vec(raw1.clone(), &mut msg).unwrap();
+        assert_eq!(msg.text, data1);
+        d.decode_vec(raw2.clone(), &mut msg).unwrap();
+        assert_eq!(msg.text, data2);
+    }
+
+    let invalid = DEFINITION.replace(r#"id="4" name="MDHeartbeat""#, r#"id="4" name="MDHeartbeat" reset="always""#);
+    assert!(Decoder::new_from_xml(&invalid).is_err());
+}
+
+#[test]
+fn test_snapshot() {
+    let raw1 = vec![0xc0, 0x84, 0x81, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2c, 0x58, 0x80];
+    let raw2 = vec![0x80, 0x82, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2d, 0x26, 0x90];
+    let data1 = "MDHeartbeat=<MessageType=0|ApplVerID=8|SenderCompID=CQG|MsgSeqNum=1|SendingTime=20240606000000000>";
+    let data2 = "MDHeartbeat=<MessageType=0|ApplVerID=8|SenderCompID=CQG|MsgSeqNum=2|SendingTime=20240606000010000>";
+
+    let mut d = Decoder::new_from_xml(DEFINITION).unwrap();
+    d.decode_vec(raw1.clone(), &mut TextMessageFactory::new()).unwrap();
+    let bytes = d.snapshot().to_bytes();
+    let snapshot = Snapshot::from_bytes(&bytes).unwrap();
+    assert_eq!(snapshot, d.snapshot());
+    assert!(Snapshot::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+    // The second message depends on the dictionary values set by the first one.
+    let mut d = Decoder::new_from_xml(DEFINITION).unwrap();
+    d.restore(&snapshot).unwrap();
+    let mut msg = TextMessageFactory::new();
+    d.decode_vec(raw2.clone(), &mut msg).unwrap();
+    assert_eq!(msg.text, data2);
+
+    let mut e = Encoder::new_from_xml(DEFINITION).unwrap();
+    assert_eq!(e.encode_vec(&mut TextMessageVisitor::from_text(data1).unwrap()).unwrap(), raw1);
+    let snapshot = Snapshot::from_bytes(&e.snapshot().to_bytes()).unwrap();
+    let mut e = Encoder::new_from_xml(DEFINITION).unwrap();
+    e.restore(&snapshot).unwrap();
+    assert_eq!(e.encode_vec(&mut TextMessageVisitor::from_text(data2).unwrap()).unwrap(), raw2);
+
+    // The snapshot can't be restored with different definitions.
+    let other = Definitions::new_from_xml(&DEFINITION.replace(r#"name="MsgSeqNum""#, r#"name="SeqNum""#)).unwrap();
+    assert_ne!(other.fingerprint(), e.definitions().fingerprint());
+    assert_eq!(Definitions::new_from_xml(DEFINITION).unwrap().fingerprint(), e.definitions().fingerprint());
+    let mut d = Decoder::with_definitions(Arc::new(other));
+    assert!(d.restore(&snapshot).is_err());
+    assert!(d.dictionary_entries().is_empty());
+}
+
+#[test]
+fn test_fork() {
+    let raw1 = vec![0xc0, 0x84, 0x81, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2c, 0x58, 0x80];
+    let raw2 = vec![0x80, 0x82, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2d, 0x26, 0x90];
+    let data2 = "MDHeartbeat=<MessageType=0|ApplVerID=8|SenderCompID=CQG|MsgSeqNum=2|SendingTime=20240606000010000>";
+
+    let mut d = Decoder::new_from_xml(DEFINITION).unwrap();
+    d.decode_vec(raw1.clone(), &mut TextMessageFactory::new()).unwrap();
+    let entries = d.dictionary_entries();
+
+    // Decoding with the fork doesn't change the state of the decoder.
+    let mut f = d.fork();
+    assert_eq!(f.dictionary_entries(), entries);
+    let mut msg = TextMessageFactory::new();
+    f.decode_vec(raw2.clone(), &mut msg).unwrap();
+    assert_eq!(msg.text, data2);
+    f.reset();
+    assert!(f.dictionary_entries().is_empty());
+    assert_eq!(d.dictionary_entries(), entries);
+
+    let mut msg = TextMessageFactory::new();
+    d.decode_vec(raw2.clone(), &mut msg).unwrap();
+    assert_eq!(msg.text, data2);
+}
+
+fn block(length: BlockLength, raw: &[u8]) -> Vec<u8> {
+    let mut buf = bytes::BytesMut::new();
+    let mut wrt = BlockWriter::new(&mut buf, length);
+    wrt.write_buf(raw).unwrap();
+    wrt.finish().unwrap();
+    buf.to_vec()
+}
+
+#[test]
+fn test_blocks() {
+    let raw = vec![
+        vec![0xc0, 0x84, 0x81, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2c, 0x58, 0x80],
+        vec![0x80, 0x82, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2d, 0x26, 0x90],
+    ];
+    let data = vec![
+        "MDHeartbeat=<MessageType=0|ApplVerID=8|SenderCompID=CQG|MsgSeqNum=1|SendingTime=20240606000000000>",
+        "MDHeartbeat=<MessageType=0|ApplVerID=8|SenderCompID=CQG|MsgSeqNum=2|SendingTime=20240606000010000>",
+    ];
+
+    for length in [BlockLength::StopBit, BlockLength::U16BigEndian, BlockLength::U32LittleEndian] {
+        let mut e = Encoder::new_from_xml(DEFINITION).unwrap();
+        for (raw, data) in raw.iter().zip(&data) {
+            let mut buf = bytes::BytesMut::new();
+            e.encode_block(&mut buf, length, &mut TextMessageVisitor::from_text(data).unwrap()).unwrap();
+            assert_eq!(buf.to_vec(), block(length, raw), "{:?}", length);
+        }
+
+        let mut stream = Vec::new();
+        // Message with unknown template id 99.
+        stream.extend(block(length, &[0xc0, 0xe3, 0x81, 0x82]));
+        stream.extend(block(length, &raw[0]));
+        // Message with an extra byte in the block.
+        stream.extend(block(length, &[raw[1].as_slice(), &[0x80]].concat()));
+        // Message that exceeds the block.
+        stream.extend(block(length, &raw[1][..5]));
+        stream.extend(block(length, &raw[1]));
+
+        let mut d = Decoder::new_from_xml(DEFINITION).unwrap();
+        let mut msg = TextMessageFactory::new();
+        let mut stream = bytes::Bytes::from(stream);
+        assert!(!d.decode_block(&mut stream, length, &mut msg).unwrap());
+        assert!(d.decode_block(&mut stream, length, &mut msg).unwrap());
+        assert_eq!(msg.text, data[0]);
+        assert!(d.decode_block(&mut stream, length, &mut msg).is_err());
+        assert!(d.decode_block(&mut stream, length, &mut msg).is_err());
+        assert!(d.decode_block(&mut stream, length, &mut msg).unwrap());
+        assert_eq!(msg.text, data[1]);
+        assert!(matches!(d.decode_block(&mut stream, length, &mut msg), Err(Error::Eof)));
+    }
+
+    let mut d = Decoder::new_from_xml(DEFINITION).unwrap();
+    let mut stream = bytes::Bytes::from(vec![0x00, 0x00]);
+    let err = d.decode_block(&mut stream, BlockLength::U16BigEndian, &mut TextMessageFactory::new()).unwrap_err();
+    assert_eq!(err.code(), Some(FastErrorCode::D12));
+
+    // A message too long for the block length doesn't change the dictionaries.
+    let oversized = SECURITY_DEFINITION.replace("Micro Bitcoin Reverse Cal Spread", &"x".repeat(70000));
+    let mut e = Encoder::new_from_xml(DEFINITION).unwrap();
+    let mut buf = bytes::BytesMut::new();
+    let res = e.encode_block(&mut buf, BlockLength::U16BigEndian, &mut TextMessageVisitor::from_text(&oversized).unwrap());
+    assert!(res.is_err());
+    assert!(buf.is_empty());
+    assert!(e.dictionary_entries().is_empty());
+    e.encode_block(&mut buf, BlockLength::U16BigEndian, &mut TextMessageVisitor::from_text(SECURITY_DEFINITION).unwrap()).unwrap();
+    let mut msg = TextMessageFactory::new();
+    assert!(d.decode_block(&mut buf.freeze(), BlockLength::U16BigEndian, &mut msg).unwrap());
+    assert_eq!(msg.text, SECURITY_DEFINITION);
+}
+
+// Collects decoded messages as text along with the packet preamble, and the skipped templates.
+struct PacketMessageFactory {
+    msg: TextMessageFactory,
+    preamble: Option<Preamble>,
+    messages: Vec<(Option<Value>, String)>,
+    skipped: Vec<(u32, String)>,
+}
+
+impl MessageFactory for PacketMessageFactory {
+    fn start_template(&mut self, id: u32, name: &str) {
+        self.msg.start_template(id, name)
+    }
+
+    fn stop_template(&mut self) {
+        self.msg.stop_template();
+        let seq = self.preamble.as_ref().and_then(|p| p.get("SeqNum").cloned());
+        self.messages.push((seq, self.msg.text.clone()));
+    }
+
+    fn set_value(&mut self, id: u32, name: &str, value: Option<Value>) {
+        self.msg.set_value(id, name, value)
+    }
+
+    fn start_sequence(&mut self, id: u32, name: &str, length: u32) {
+        self.msg.start_sequence(id, name, length)
+    }
+
+    fn start_sequence_item(&mut self, index: u32) {
+        self.msg.start_sequence_item(index)
+    }
+
+    fn stop_sequence_item(&mut self) {
+        self.msg.stop_sequence_item()
+    }
+
+    fn stop_sequence(&mut self) {
+        self.msg.stop_sequence()
+    }
+
+    fn start_group(&mut self, name: &str) {
+        self.msg.start_group(name)
+    }
+
+    fn stop_group(&mut self) {
+        self.msg.stop_group()
+    }
+
+    fn start_template_ref(&mut self, name: &str, dynamic: bool) {
+        self.msg.start_template_ref(name, dynamic)
+    }
+
+    fn stop_template_ref(&mut self) {
+        self.msg.stop_template_ref()
+    }
+
+    fn set_preamble(&mut self, preamble: &Preamble) {
+        self.preamble = Some(preamble.clone());
+    }
+
+    fn skip_template(&mut self, id: u32, name: &str) {
+        self.skipped.push((id, name.to_string()));
+    }
+}
+
+#[test]
+fn test_packets() {
+    let packet: Vec<u8> = vec![
+        0x00, 0x00, 0x01, 0x02, // preamble
+        0xc0, 0x84, 0x81, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2c, 0x58, 0x80,
+        0x80, 0x82, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2d, 0x26, 0x90,
+    ];
+    let data = vec![
+        "MDHeartbeat=<MessageType=0|ApplVerID=8|SenderCompID=CQG|MsgSeqNum=1|SendingTime=20240606000000000>",
+        "MDHeartbeat=<MessageType=0|ApplVerID=8|SenderCompID=CQG|MsgSeqNum=2|SendingTime=20240606000010000>",
+    ];
+
+    let mut d = Decoder::new_from_xml(DEFINITION).unwrap();
+    d.set_packet_layout(PacketLayout::new(4, |b| Ok(Preamble {
+        fields: vec![("SeqNum".to_string(), Value::UInt32(u32::from_be_bytes([b[0], b[1], b[2], b[3]])))],
+    })));
+    let mut msg = PacketMessageFactory { msg: TextMessageFactory::new(), preamble: None, messages: Vec::new(), skipped: Vec::new() };
+    assert_eq!(d.decode_packet(&packet, &mut msg).unwrap(), 2);
+    assert_eq!(msg.messages, vec![
+        (Some(Value::UInt32(258)), data[0].to_string()),
+        (Some(Value::UInt32(258)), data[1].to_string()),
+    ]);
+
+    // Packet without messages.
+    assert_eq!(d.decode_packet(&packet[..4], &mut msg).unwrap(), 0);
+    assert!(d.decode_packet(&packet[..3], &mut msg).is_err());
+    assert!(d.decode_packet(&packet[..20], &mut msg).is_err());
+}
+
+#[test]
+fn test_skip_templates() {
+    let raw: Vec<u8> = vec![
+        0xc0, 0x84, 0x81, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2c, 0x58, 0x80,
+        0x80, 0x82, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2d, 0x26, 0x90,
+        0x80, 0x83, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2d, 0x74, 0xa0,
+    ];
+    let data = "MDHeartbeat=<MessageType=0|ApplVerID=8|SenderCompID=CQG|MsgSeqNum=3|SendingTime=20240606000020000>";
+
+    let mut d = Decoder::new_from_xml(DEFINITION).unwrap();
+    assert_eq!(d.skip_template(100).unwrap_err().code(), Some(FastErrorCode::D9));
+    assert_eq!(d.skip_template_by_name("Unknown").unwrap_err().code(), Some(FastErrorCode::D9));
+    d.skip_template_by_name("MDHeartbeat").unwrap();
+    let mut msg = PacketMessageFactory { msg: TextMessageFactory::new(), preamble: None, messages: Vec::new(), skipped: Vec::new() };
+    let mut pos = d.decode_slice(&raw, &mut msg).unwrap();
+    pos += d.decode_slice(&raw[pos..], &mut msg).unwrap();
+    assert_eq!(pos, 21);
+    assert!(msg.messages.is_empty());
+    assert_eq!(msg.skipped, vec![(4, "MDHeartbeat".to_string()), (4, "MDHeartbeat".to_string())]);
+
+    // The last message depends on dictionary values set by the skipped ones.
+    d.clear_skipped_templates();
+    d.decode_slice(&raw[pos..], &mut msg).unwrap();
+    assert_eq!(msg.messages, vec![(None, data.to_string())]);
+    assert_eq!(msg.skipped.len(), 2);
+}
+
+// Passes messages to `TextMessageFactory` but skips the message with `MsgSeqNum` equal to `skip`
+// and aborts decoding at the message with `MsgSeqNum` equal to `abort`.
+struct FilterMessageFactory {
+    msg: TextMessageFactory,
+    messages: Vec<String>,
+    skip: u32,
+    abort: u32,
+}
+
+impl fastlib::TryMessageFactory<'_> for FilterMessageFactory {
+    fn start_template(&mut self, id: u32, name: &str) -> fastlib::Result<Control> {
+        MessageFactory::start_template(&mut self.msg, id, name);
+        Ok(Control::Continue)
+    }
+
+    fn stop_template(&mut self) -> fastlib::Result<Control> {
+        MessageFactory::stop_template(&mut self.msg);
+        self.messages.push(self.msg.text.clone());
+        Ok(Control::Continue)
+    }
+
+    fn set_value(&mut self, id: u32, name: &str, value: Option<ValueRef>) -> fastlib::Result<Control> {
+        if name == "MsgSeqNum" {
+            match value {
+                Some(ValueRef::UInt32(n)) if n == self.skip => return Ok(Control::Skip),
+                Some(ValueRef::UInt32(n)) if n == self.abort => return Err(Error::Aborted(format!("MsgSeqNum={n}").into())),
+                _ => {}
+            }
+        }
+        fastlib::MessageFactoryRef::set_value(&mut self.msg, id, name, value);
+        Ok(Control::Continue)
+    }
+
+    fn start_sequence(&mut self, id: u32, name: &str, length: u32) -> fastlib::Result<Control> {
+        MessageFactory::start_sequence(&mut self.msg, id, name, length);
+        Ok(Control::Continue)
+    }
+
+    fn start_sequence_item(&mut self, index: u32) -> fastlib::Result<Control> {
+        MessageFactory::start_sequence_item(&mut self.msg, index);
+        Ok(Control::Continue)
+    }
+
+    fn stop_sequence_item(&mut self) -> fastlib::Result<Control> {
+        MessageFactory::stop_sequence_item(&mut self.msg);
+        Ok(Control::Continue)
+    }
+
+    fn stop_sequence(&mut self) -> fastlib::Result<Control> {
+        MessageFactory::stop_sequence(&mut self.msg);
+        Ok(Control::Continue)
+    }
+
+    fn start_group(&mut self, name: &str) -> fastlib::Result<Control> {
+        MessageFactory::start_group(&mut self.msg, name);
+        Ok(Control::Continue)
+    }
+
+    fn stop_group(&mut self) -> fastlib::Result<Control> {
+        MessageFactory::stop_group(&mut self.msg);
+        Ok(Control::Continue)
+    }
+
+    fn start_template_ref(&mut self, name: &str, dynamic: bool) -> fastlib::Result<Control> {
+        MessageFactory::start_template_ref(&mut self.msg, name, dynamic);
+        Ok(Control::Continue)
+    }
+
+    fn stop_template_ref(&mut self) -> fastlib::Result<Control> {
+        MessageFactory::stop_template_ref(&mut self.msg);
+        Ok(Control::Continue)
+    }
+}
+
+#[test]
+fn test_try_message_factory() {
+    let raw: Vec<u8> = vec![
+        0xc0, 0x84, 0x81, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2c, 0x58, 0x80,
+        0x80, 0x82, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2d, 0x26, 0x90,
+        0x80, 0x83, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2d, 0x74, 0xa0,
+    ];
+
+    // The skipped message is decoded to the end, so the next one starts at the right offset
+    // and sees the dictionary values set by the skipped one.
+    let mut d = Decoder::new_from_xml(DEFINITION).unwrap();
+    let mut msg = FilterMessageFactory { msg: TextMessageFactory::new(), messages: Vec::new(), skip: 2, abort: 0 };
+    let mut pos = 0;
+    while pos < raw.len() {
+        pos += d.decode_slice(&raw[pos..], &mut msg).unwrap();
+    }
+    assert_eq!(msg.messages, vec![
+        "MDHeartbeat=<MessageType=0|ApplVerID=8|SenderCompID=CQG|MsgSeqNum=1|SendingTime=20240606000000000>",
+        "MDHeartbeat=<MessageType=0|ApplVerID=8|SenderCompID=CQG|MsgSeqNum=3|SendingTime=20240606000020000>",
+    ]);
+
+    let mut d = Decoder::new_from_xml(DEFINITION).unwrap();
+    let mut msg = FilterMessageFactory { msg: TextMessageFactory::new(), messages: Vec::new(), skip: 0, abort: 2 };
+    let pos = d.decode_slice(&raw, &mut msg).unwrap();
+    let err = d.decode_slice(&raw[pos..], &mut msg).unwrap_err();
+    assert!(matches!(err.inner(), Error::Aborted(e) if e.to_string() == "MsgSeqNum=2"));
+    let location = err.location().unwrap();
+    assert_eq!(location.template_name.as_deref(), Some("MDHeartbeat"));
+    assert_eq!(location.field_path, "MDHeartbeat/MsgHeader/MsgSeqNum");
+    assert_eq!(msg.messages.len(), 1);
+}
+
+#[test]
+fn test_projection() {
+    let mut e = Encoder::new_from_xml(DEFINITION).unwrap();
+    let raw = e.encode_vec(&mut TextMessageVisitor::from_text(SECURITY_DEFINITION).unwrap()).unwrap();
+    let next = e.encode_vec(&mut TextMessageVisitor::from_text(SECURITY_DEFINITION).unwrap()).unwrap();
+    assert!(next.len() < raw.len());
+
+    let mut d = Decoder::new_from_xml(DEFINITION).unwrap();
+    assert_eq!(d.set_projection(100, &[]).unwrap_err().code(), Some(FastErrorCode::D9));
+    d.set_projection(2, &[34, 55, 48, 20004]).unwrap();
+    let mut msg = TextMessageFactory::new();
+    assert_eq!(d.decode_slice(&raw, &mut msg).unwrap(), raw.len());
+    assert_eq!(
+        msg.text,
+        "MDSecurityDefinition=<MsgSeqNum=964|Events=<>|Symbol=MBTS13C100|SecurityID=60714110|MDFeedTypes=<><>|\
+        InstrAttrib=<>|Connections=<ConnectionPortNumber=11004><ConnectionPortNumber=12004><ConnectionPortNumber=10000>\
+        <ConnectionPortNumber=10001>|TradingSessions=<><><><><><>>"
+    );
+
+    // The next message depends on dictionary values of the fields not passed to the factory.
+    d.clear_projections();
+    let mut msg = TextMessageFactory::new();
+    d.decode_slice(&next, &mut msg).unwrap();
+    assert_eq!(msg.text, SECURITY_DEFINITION);
+}
+
+// Reader that counts the calls of its own `read_uint` implementation.
+struct UintCountingReader {
+    bytes: bytes::Bytes,
+    uints: usize,
+}
+
+impl Reader for UintCountingReader {
+    fn read_u8(&mut self) -> fastlib::Result<u8> {
+        self.bytes.read_u8()
+    }
+
+    fn read_uint(&mut self) -> fastlib::Result<u64> {
+        self.uints += 1;
+        self.bytes.read_uint()
+    }
+}
+
+#[test]
+fn test_reader_overrides() {
+    let raw = vec![0xc0, 0x84, 0x81, 0x23, 0x7a, 0x17, 0x15, 0x15, 0x2c, 0x58, 0x80];
+    let data = "MDHeartbeat=<MessageType=0|ApplVerID=8|SenderCompID=CQG|MsgSeqNum=1|SendingTime=20240606000000000>";
+
+    let mut rdr = UintCountingReader { bytes: bytes::Bytes::from(raw.clone()), uints: 0 };
+    let mut d = Decoder::new_from_xml(DEFINITION).unwrap();
+    let mut msg = TextMessageFactory::new();
+    d.decode_reader(&mut rdr, &mut msg).unwrap();
+    assert_eq!(msg.text, data);
+    // Template id, MsgSeqNum and SendingTime.
+    assert_eq!(rdr.uints, 3);
+
+    // Statistics need the message size, so the bytes are counted with `read_u8`.
+    let mut rdr = UintCountingReader { bytes: bytes::Bytes::from(raw.clone()), uints: 0 };
+    let mut d = Decoder::new_from_xml(DEFINITION).unwrap();
+    d.set_stats(true);
+    d.decode_reader(&mut rdr, &mut msg).unwrap();
+    assert_eq!(msg.text, data);
+    assert_eq!(rdr.uints, 0);
+    assert_eq!(d.stats().unwrap().templates[&4].bytes, raw.len() as u64);
+}
+
+#[test]
+fn test_error_location() {
+    let data = SECURITY_DEFINITION;
+    let mut e = Encoder::new_from_xml(DEFINITION).unwrap();
+    let raw = e.encode_vec(&mut TextMessageVisitor::from_text(data).unwrap()).unwrap();
+
+    // Block is 10 bytes shorter than the message, so decoding stops inside the last trading session.
+    let mut d = Decoder::new_from_xml(DEFINITION).unwrap();
+    let mut short = block(BlockLength::U32BigEndian, &raw);
+    short[3] -= 10;
+    let mut bytes = bytes::Bytes::from(short);
+    let err = d.decode_block(&mut bytes, BlockLength::U32BigEndian, &mut TextMessageFactory::new()).unwrap_err();
+    assert!(matches!(err.inner(), Error::Dynamic(_)));
+    let location = err.location().unwrap();
+    assert_eq!(location.offset, Some(raw.len() - 10));
+    assert_eq!(location.template_id, Some(2));
+    assert_eq!(location.template_name.as_deref(), Some("MDSecurityDefinition"));
+    assert_eq!(location.field_path, "MDSecurityDefinition/TradingSessions[5]/TradSesCloseTime");
+
+    let mut e = Encoder::new_from_xml(DEFINITION).unwrap();
+    let broken = data.replace("|ConnectionPortNumber=12004", "");
+    let err = e.encode_vec(&mut TextMessageVisitor::from_text(&broken).unwrap()).unwrap_err();
+    assert!(matches!(err.inner(), Error::Runtime(_)));
+    let location = err.location().unwrap();
+    assert_eq!(location.offset, None);
+    assert_eq!(location.template_id, Some(2));
+    assert_eq!(location.field_path, "MDSecurityDefinition/Connections[1]/ConnectionPortNumber");
+    assert_eq!(
+        err.to_string(),
+        "Runtime Error: mandatory field ConnectionPortNumber has no value \
+        (template 2, field MDSecurityDefinition/Connections[1]/ConnectionPortNumber)"
+    );
+}